@@ -0,0 +1,215 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! On-disk data structures of file system
+//!
+//! This crate is shared by the kernel and `mkfs`, so that they always
+//! agree on disk layout.
+
+#![no_std]
+
+/// Block size of file system, which is the same as `virtio::BSIZE`
+pub const BSIZE: usize = 1024;
+
+/// Magic number in super block
+pub const FSMAGIC: u32 = 0x10203040;
+
+/// Inode number of root directory
+pub const ROOTINO: u32 = 1;
+
+/// Number of direct block addresses in inode
+pub const NDIRECT: usize = 11;
+
+/// Number of block addresses in an indirect block
+pub const NINDIRECT: usize = BSIZE / core::mem::size_of::<u32>();
+
+/// Number of block addresses in a doubly-indirect block
+pub const NDINDIRECT: usize = NINDIRECT * NINDIRECT;
+
+/// Maximum number of blocks of a file
+pub const MAXFILE: usize = NDIRECT + NINDIRECT + NDINDIRECT;
+
+/// Maximum length of directory entry name
+pub const DIRSIZ: usize = 14;
+
+/// Maximum number of blocks in log
+pub const LOGSIZE: usize = 30;
+
+/// Inode type of directory
+pub const T_DIR: u16 = 1;
+
+/// Inode type of file
+pub const T_FILE: u16 = 2;
+
+/// Inode type of device
+pub const T_DEVICE: u16 = 3;
+
+/// Super block, which describes the disk layout
+///
+/// `[ boot block | super block | log | inode blocks | free bit map | data blocks ]`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SuperBlock {
+    /// Must be `FSMAGIC`
+    pub magic: u32,
+    /// Size of file system image (blocks)
+    pub size: u32,
+    /// Number of data blocks
+    pub nblocks: u32,
+    /// Number of inodes
+    pub ninodes: u32,
+    /// Number of log blocks
+    pub nlog: u32,
+    /// Block number of first log block
+    pub logstart: u32,
+    /// Block number of first inode block
+    pub inodestart: u32,
+    /// Block number of first free map block
+    pub bmapstart: u32,
+}
+
+impl SuperBlock {
+    pub const fn zero() -> Self {
+        Self {
+            magic: 0,
+            size: 0,
+            nblocks: 0,
+            ninodes: 0,
+            nlog: 0,
+            logstart: 0,
+            inodestart: 0,
+            bmapstart: 0,
+        }
+    }
+
+    /// Block containing inode `inum`
+    pub fn iblock(&self, inum: u32) -> u32 {
+        inum / IPB as u32 + self.inodestart
+    }
+
+    /// Block of free map containing bit for block `b`
+    pub fn bblock(&self, b: u32) -> u32 {
+        b / BPB as u32 + self.bmapstart
+    }
+}
+
+/// On-disk inode
+///
+/// `addrs` holds `NDIRECT` direct blocks, followed by one indirect block
+/// and one doubly-indirect block.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DInode {
+    /// File type, 0 means the inode is free
+    pub typ: u16,
+    /// Major device number (`T_DEVICE` only)
+    pub major: u16,
+    /// Minor device number (`T_DEVICE` only)
+    pub minor: u16,
+    /// Number of links to inode in file system
+    pub nlink: u16,
+    /// Size of file (bytes)
+    pub size: u32,
+    /// Data block addresses
+    pub addrs: [u32; NDIRECT + 2],
+}
+
+impl DInode {
+    pub const fn zero() -> Self {
+        Self {
+            typ: 0,
+            major: 0,
+            minor: 0,
+            nlink: 0,
+            size: 0,
+            addrs: [0; NDIRECT + 2],
+        }
+    }
+}
+
+/// Inodes per block
+pub const IPB: usize = BSIZE / core::mem::size_of::<DInode>();
+
+/// Bitmap bits per block
+pub const BPB: usize = BSIZE * 8;
+
+/// Directory entry
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Dirent {
+    /// Inode number, 0 means the entry is free
+    pub inum: u16,
+    /// File name, padded with 0
+    pub name: [u8; DIRSIZ],
+}
+
+impl Dirent {
+    pub const fn zero() -> Self {
+        Self {
+            inum: 0,
+            name: [0; DIRSIZ],
+        }
+    }
+
+    /// Name of entry as bytes, without trailing zeros
+    pub fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(DIRSIZ);
+        &self.name[0..len]
+    }
+
+    /// Set name of entry, truncated to `DIRSIZ`
+    pub fn set_name(&mut self, name: &[u8]) {
+        let len = name.len().min(DIRSIZ);
+        self.name = [0; DIRSIZ];
+        self.name[0..len].copy_from_slice(&name[0..len]);
+    }
+}
+
+/// Size of a directory entry
+pub const DIRENT_SIZE: usize = core::mem::size_of::<Dirent>();
+
+/// Log header, which is the first block of log region
+///
+/// Logged blocks follow the header block, and `block` records their home locations.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LogHeader {
+    /// Number of logged blocks, 0 means there's no committed transaction
+    pub n: u32,
+    /// Home locations of logged blocks
+    pub block: [u32; LOGSIZE],
+}
+
+impl LogHeader {
+    pub const fn zero() -> Self {
+        Self { n: 0, block: [0; LOGSIZE] }
+    }
+}
+
+/// Read a `T` at `offset` of block data
+pub fn read_struct<T: Copy>(data: &[u8; BSIZE], offset: usize) -> T {
+    if offset + core::mem::size_of::<T>() > BSIZE {
+        panic!("read_struct out of bound");
+    }
+    unsafe { core::ptr::read_unaligned(data.as_ptr().add(offset) as *const T) }
+}
+
+/// Write `val` to `offset` of block data
+pub fn write_struct<T: Copy>(data: &mut [u8; BSIZE], offset: usize, val: &T) {
+    if offset + core::mem::size_of::<T>() > BSIZE {
+        panic!("write_struct out of bound");
+    }
+    unsafe { core::ptr::write_unaligned(data.as_mut_ptr().add(offset) as *mut T, *val) }
+}
+
+/// View an on-disk structure as bytes
+pub fn as_bytes<T>(val: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(val as *const T as *const u8, core::mem::size_of::<T>()) }
+}
+
+/// View an on-disk structure as mutable bytes
+pub fn as_bytes_mut<T>(val: &mut T) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(val as *mut T as *mut u8, core::mem::size_of::<T>()) }
+}
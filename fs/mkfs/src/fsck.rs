@@ -0,0 +1,229 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Consistency check of file system image
+//!
+//! `fsck` walks the directory tree from root, and checks that:
+//! * super block describes a valid layout, and log has no committed transaction.
+//! * every directory starts with `.` and `..` pointing to itself and its parent.
+//! * directory entries point to allocated inodes, and every allocated inode is reachable.
+//! * `nlink` of each inode equals the number of entries pointing to it (`.` excluded).
+//! * every block belongs to at most one inode, and free bit map matches used blocks.
+
+use crate::image::{Image, Result};
+use fs_defs::*;
+use std::collections::HashMap;
+
+struct Fsck<'a> {
+    img: &'a mut Image,
+    errors: usize,
+    /// number of directory entries pointing to each inode
+    refs: HashMap<u32, u32>,
+    /// owner inode of each referenced block
+    owner: HashMap<u32, u32>,
+}
+
+impl Fsck<'_> {
+    fn error(&mut self, msg: String) {
+        println!("error: {}", msg);
+        self.errors += 1;
+    }
+
+    fn check_super_block(&mut self) {
+        let sb = self.img.sb;
+        let ninodeblocks = sb.ninodes / IPB as u32 + 1;
+        let nbitmap = sb.size / BPB as u32 + 1;
+        if sb.logstart != 2
+            || sb.inodestart != sb.logstart + sb.nlog
+            || sb.bmapstart != sb.inodestart + ninodeblocks
+            || sb.bmapstart + nbitmap + sb.nblocks != sb.size {
+            self.error("invalid layout in super block".to_string());
+        }
+        if sb.nlog < 2 || sb.nlog as usize > LOGSIZE {
+            self.error(format!("invalid log size {}", sb.nlog));
+        }
+    }
+
+    fn check_log(&mut self) -> Result<()> {
+        let block = self.img.read_block(self.img.sb.logstart)?;
+        let lh: LogHeader = read_struct(&block, 0);
+        if lh.n != 0 {
+            self.error(format!("log has {} committed blocks not installed, boot the kernel to recover", lh.n));
+        }
+        Ok(())
+    }
+
+    /// Record block `b` as used by inode `inum`
+    fn use_block(&mut self, inum: u32, b: u32) {
+        if b < self.img.data_start() || b >= self.img.sb.size {
+            self.error(format!("inode {} refers to block {} out of data region", inum, b));
+        } else if let Some(owner) = self.owner.insert(b, inum) {
+            self.error(format!("block {} is used by both inode {} and {}", b, owner, inum));
+        }
+    }
+
+    /// Record all blocks referred to by block `b`, which is an indirect block of `level`
+    fn use_indirect(&mut self, inum: u32, b: u32, level: usize) -> Result<()> {
+        self.use_block(inum, b);
+        if level > 0 && b >= self.img.data_start() && b < self.img.sb.size {
+            let block = self.img.read_block(b)?;
+            for i in 0..NINDIRECT {
+                let addr: u32 = read_struct(&block, i * std::mem::size_of::<u32>());
+                if addr != 0 {
+                    self.use_indirect(inum, addr, level - 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check inode `inum` and its blocks
+    fn check_inode(&mut self, inum: u32, din: &DInode) -> Result<()> {
+        if din.size as usize > MAXFILE * BSIZE {
+            self.error(format!("inode {} has size {} larger than maximum file size", inum, din.size));
+        }
+        for i in 0..NDIRECT + 2 {
+            if din.addrs[i] != 0 {
+                let level = if i < NDIRECT { 0 } else { i - NDIRECT + 1 };
+                self.use_indirect(inum, din.addrs[i], level)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check directory `dir`, whose parent is `parent`, and everything in it
+    fn check_dir(&mut self, dir: u32, parent: u32) -> Result<()> {
+        let dirents = self.img.dirents(dir)?;
+        if dirents.len() < 2
+            || dirents[0].name() != b"." || dirents[0].inum as u32 != dir
+            || dirents[1].name() != b".." || dirents[1].inum as u32 != parent {
+            self.error(format!("directory {} doesn't start with \".\" and \"..\"", dir));
+        }
+        for (i, de) in dirents.iter().enumerate() {
+            let inum = de.inum as u32;
+            if inum == 0 || i < 2 {
+                continue;
+            }
+            let name = String::from_utf8_lossy(de.name()).into_owned();
+            if inum >= self.img.sb.ninodes {
+                self.error(format!("entry \"{}\" in directory {} refers to invalid inode {}", name, dir, inum));
+                continue;
+            }
+            let din = self.img.read_inode(inum)?;
+            if din.typ == 0 {
+                self.error(format!("entry \"{}\" in directory {} refers to free inode {}", name, dir, inum));
+                continue;
+            }
+            let visited = self.refs.contains_key(&inum);
+            *self.refs.entry(inum).or_insert(0) += 1;
+            if din.typ == T_DIR {
+                if visited {
+                    self.error(format!("directory {} has more than one entry", inum));
+                    continue;
+                }
+                // ".." of child directory
+                *self.refs.entry(dir).or_insert(0) += 1;
+                self.check_inode(inum, &din)?;
+                self.check_dir(inum, dir)?;
+            } else if !visited {
+                self.check_inode(inum, &din)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_tree(&mut self) -> Result<()> {
+        let root = self.img.read_inode(ROOTINO)?;
+        if root.typ != T_DIR {
+            self.error("root inode is not a directory".to_string());
+            return Ok(());
+        }
+        // ".." of root
+        *self.refs.entry(ROOTINO).or_insert(0) += 1;
+        self.check_inode(ROOTINO, &root)?;
+        self.check_dir(ROOTINO, ROOTINO)
+    }
+
+    fn check_links(&mut self) -> Result<()> {
+        for inum in 1..self.img.sb.ninodes {
+            let din = self.img.read_inode(inum)?;
+            if din.typ == 0 {
+                continue;
+            }
+            match self.refs.get(&inum).cloned() {
+                None => self.error(format!("inode {} is allocated but not referenced", inum)),
+                Some(refs) if refs != din.nlink as u32 => {
+                    self.error(format!("inode {} has nlink {}, but {} references", inum, din.nlink, refs))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_bitmap(&mut self) -> Result<()> {
+        for b in 0..self.img.sb.size {
+            let used = b < self.img.data_start() || self.owner.contains_key(&b);
+            let marked = self.img.is_used(b)?;
+            if used && !marked {
+                self.error(format!("block {} is used but marked free", b));
+            } else if !used && marked {
+                self.error(format!("block {} is marked in use but not referenced", b));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check image, and return number of errors found
+pub fn fsck(img: &mut Image) -> Result<usize> {
+    let mut fsck = Fsck {
+        img,
+        errors: 0,
+        refs: HashMap::new(),
+        owner: HashMap::new(),
+    };
+    fsck.check_super_block();
+    if fsck.errors != 0 {
+        return Ok(fsck.errors);
+    }
+    fsck.check_log()?;
+    fsck.check_tree()?;
+    fsck.check_links()?;
+    fsck.check_bitmap()?;
+    println!("{} inodes, {} blocks used, {} errors",
+             fsck.refs.len(), fsck.owner.len(), fsck.errors);
+    Ok(fsck.errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::TempImage;
+
+    #[test]
+    fn test_fresh_image() {
+        let tmp = TempImage::new("fsck-fresh");
+        let mut img = tmp.create();
+        assert_eq!(fsck(&mut img).unwrap(), 0);
+        img.mkdir("/dir").unwrap();
+        img.put("/dir/file", &[1; BSIZE * 20]).unwrap();
+        assert_eq!(fsck(&mut img).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cleared_bitmap() {
+        let tmp = TempImage::new("fsck-bitmap");
+        let mut img = tmp.create();
+        let inum = img.put("/file", b"content").unwrap();
+        let b = img.read_inode(inum).unwrap().addrs[0];
+        let bn = img.sb.bblock(b);
+        let mut block = img.read_block(bn).unwrap();
+        let bi = b as usize % BPB;
+        block[bi / 8] &= !(1 << (bi % 8));
+        img.write_block(bn, &block).unwrap();
+        assert_eq!(fsck(&mut img).unwrap(), 1);
+    }
+}
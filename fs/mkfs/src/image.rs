@@ -0,0 +1,428 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Disk image in kernel's on-disk format
+//!
+//! Disk layout:
+//! `[ boot block | super block | log | inode blocks | free bit map | data blocks ]`
+
+use fs_defs::*;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Block data
+pub type Block = [u8; BSIZE];
+
+/// A file system image opened on host
+pub struct Image {
+    file: File,
+    pub sb: SuperBlock,
+}
+
+impl Image {
+    /// Create an empty file system of `size` blocks and `ninodes` inodes at `path`
+    pub fn create(path: &Path, size: u32, ninodes: u32) -> Result<Image> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let nlog = LOGSIZE as u32;
+        let ninodeblocks = ninodes / IPB as u32 + 1;
+        let nbitmap = size / BPB as u32 + 1;
+        // 1 boot block, 1 super block
+        let nmeta = 2 + nlog + ninodeblocks + nbitmap;
+        if nmeta >= size {
+            return Err(format!("{} blocks are too few for {} meta blocks", size, nmeta).into());
+        }
+        let sb = SuperBlock {
+            magic: FSMAGIC,
+            size,
+            nblocks: size - nmeta,
+            ninodes,
+            nlog,
+            logstart: 2,
+            inodestart: 2 + nlog,
+            bmapstart: 2 + nlog + ninodeblocks,
+        };
+        // file is truncated, and extending it fills zeros
+        file.set_len(size as u64 * BSIZE as u64)?;
+        let mut img = Image { file, sb };
+        let mut block = [0; BSIZE];
+        write_struct(&mut block, 0, &sb);
+        img.write_block(1, &block)?;
+        // mark meta blocks as in use
+        for b in 0..nmeta {
+            img.set_used(b, true)?;
+        }
+        let rootino = img.ialloc(T_DIR)?;
+        if rootino != ROOTINO {
+            return Err(format!("root inode should be {}", ROOTINO).into());
+        }
+        // root has `nlink` of 1 for its own "..", as "." is not counted
+        img.dirlink(rootino, ".", rootino)?;
+        img.dirlink(rootino, "..", rootino)?;
+        Ok(img)
+    }
+
+    /// Open an existing image at `path`
+    pub fn open(path: &Path) -> Result<Image> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut img = Image { file, sb: SuperBlock::zero() };
+        let block = img.read_block(1)?;
+        img.sb = read_struct(&block, 0);
+        if img.sb.magic != FSMAGIC {
+            return Err(format!("invalid file system magic {:x}", img.sb.magic).into());
+        }
+        Ok(img)
+    }
+
+    /// First data block
+    pub fn data_start(&self) -> u32 {
+        self.sb.size - self.sb.nblocks
+    }
+
+    pub fn read_block(&mut self, b: u32) -> Result<Block> {
+        let mut block = [0; BSIZE];
+        self.file.seek(SeekFrom::Start(b as u64 * BSIZE as u64))?;
+        self.file.read_exact(&mut block)?;
+        Ok(block)
+    }
+
+    pub fn write_block(&mut self, b: u32, block: &Block) -> Result<()> {
+        self.file.seek(SeekFrom::Start(b as u64 * BSIZE as u64))?;
+        self.file.write_all(block)?;
+        Ok(())
+    }
+
+    /// Whether block `b` is marked as in use in free bit map
+    pub fn is_used(&mut self, b: u32) -> Result<bool> {
+        let block = self.read_block(self.sb.bblock(b))?;
+        let bi = b as usize % BPB;
+        Ok(block[bi / 8] & (1 << (bi % 8)) != 0)
+    }
+
+    fn set_used(&mut self, b: u32, used: bool) -> Result<()> {
+        let bn = self.sb.bblock(b);
+        let mut block = self.read_block(bn)?;
+        let bi = b as usize % BPB;
+        if used {
+            block[bi / 8] |= 1 << (bi % 8);
+        } else {
+            block[bi / 8] &= !(1 << (bi % 8));
+        }
+        self.write_block(bn, &block)
+    }
+
+    /// Allocate a zeroed data block
+    pub fn balloc(&mut self) -> Result<u32> {
+        for b in self.data_start()..self.sb.size {
+            if !self.is_used(b)? {
+                self.set_used(b, true)?;
+                self.write_block(b, &[0; BSIZE])?;
+                return Ok(b);
+            }
+        }
+        Err("out of blocks".into())
+    }
+
+    pub fn read_inode(&mut self, inum: u32) -> Result<DInode> {
+        let block = self.read_block(self.sb.iblock(inum))?;
+        Ok(read_struct(&block, inode_offset(inum)))
+    }
+
+    pub fn write_inode(&mut self, inum: u32, din: &DInode) -> Result<()> {
+        let bn = self.sb.iblock(inum);
+        let mut block = self.read_block(bn)?;
+        write_struct(&mut block, inode_offset(inum), din);
+        self.write_block(bn, &block)
+    }
+
+    /// Allocate an inode of type `typ`, with `nlink` of 1
+    pub fn ialloc(&mut self, typ: u16) -> Result<u32> {
+        for inum in 1..self.sb.ninodes {
+            if self.read_inode(inum)?.typ == 0 {
+                let mut din = DInode::zero();
+                din.typ = typ;
+                din.nlink = 1;
+                self.write_inode(inum, &din)?;
+                return Ok(inum);
+            }
+        }
+        Err("out of inodes".into())
+    }
+
+    /// Get `idx`th block address in indirect block `bn`, allocating one if `alloc` is set
+    fn indirect_entry(&mut self, bn: u32, idx: usize, alloc: bool) -> Result<Option<u32>> {
+        let mut block = self.read_block(bn)?;
+        let offset = idx * std::mem::size_of::<u32>();
+        let addr: u32 = read_struct(&block, offset);
+        if addr != 0 || !alloc {
+            return Ok(if addr == 0 { None } else { Some(addr) });
+        }
+        let addr = self.balloc()?;
+        write_struct(&mut block, offset, &addr);
+        self.write_block(bn, &block)?;
+        Ok(Some(addr))
+    }
+
+    /// Get block address in `addr`, allocating one if `alloc` is set
+    fn addr_entry(&mut self, addr: &mut u32, alloc: bool) -> Result<Option<u32>> {
+        if *addr == 0 {
+            if !alloc {
+                return Ok(None);
+            }
+            *addr = self.balloc()?;
+        }
+        Ok(Some(*addr))
+    }
+
+    /// Disk block address of the `bn`th block in inode
+    pub fn bmap(&mut self, din: &mut DInode, bn: usize, alloc: bool) -> Result<Option<u32>> {
+        if bn < NDIRECT {
+            return self.addr_entry(&mut din.addrs[bn], alloc);
+        }
+        let bn = bn - NDIRECT;
+        if bn < NINDIRECT {
+            return match self.addr_entry(&mut din.addrs[NDIRECT], alloc)? {
+                Some(indirect) => self.indirect_entry(indirect, bn, alloc),
+                None => Ok(None)
+            };
+        }
+        let bn = bn - NINDIRECT;
+        if bn < NDINDIRECT {
+            let dindirect = match self.addr_entry(&mut din.addrs[NDIRECT + 1], alloc)? {
+                Some(dindirect) => dindirect,
+                None => return Ok(None)
+            };
+            return match self.indirect_entry(dindirect, bn / NINDIRECT, alloc)? {
+                Some(indirect) => self.indirect_entry(indirect, bn % NINDIRECT, alloc),
+                None => Ok(None)
+            };
+        }
+        Err("file too large".into())
+    }
+
+    /// Read whole content of inode `inum`
+    pub fn read_all(&mut self, inum: u32) -> Result<Vec<u8>> {
+        let mut din = self.read_inode(inum)?;
+        let size = din.size as usize;
+        let mut content = Vec::with_capacity(size);
+        let mut bn = 0;
+        while content.len() < size {
+            let n = (size - content.len()).min(BSIZE);
+            match self.bmap(&mut din, bn, false)? {
+                Some(addr) => content.extend_from_slice(&self.read_block(addr)?[0..n]),
+                None => content.resize(content.len() + n, 0)
+            }
+            bn += 1;
+        }
+        Ok(content)
+    }
+
+    /// Append `data` to inode `inum`
+    pub fn append(&mut self, inum: u32, data: &[u8]) -> Result<()> {
+        let mut din = self.read_inode(inum)?;
+        let mut off = din.size as usize;
+        let mut tot = 0;
+        while tot < data.len() {
+            let bn = off / BSIZE;
+            if bn >= MAXFILE {
+                return Err("file too large".into());
+            }
+            let addr = self.bmap(&mut din, bn, true)?.unwrap();
+            let begin = off % BSIZE;
+            let m = (data.len() - tot).min(BSIZE - begin);
+            let mut block = self.read_block(addr)?;
+            block[begin..begin + m].copy_from_slice(&data[tot..tot + m]);
+            self.write_block(addr, &block)?;
+            tot += m;
+            off += m;
+        }
+        din.size = off as u32;
+        self.write_inode(inum, &din)
+    }
+
+    /// All directory entries of directory `dir`, including free ones
+    pub fn dirents(&mut self, dir: u32) -> Result<Vec<Dirent>> {
+        let content = self.read_all(dir)?;
+        Ok(content.chunks(DIRENT_SIZE).map(|chunk| {
+            let mut de = Dirent::zero();
+            as_bytes_mut(&mut de)[0..chunk.len()].copy_from_slice(chunk);
+            de
+        }).collect())
+    }
+
+    /// Look for `name` in directory `dir`, and return its inode number
+    pub fn dirlookup(&mut self, dir: u32, name: &str) -> Result<Option<u32>> {
+        let name = &name.as_bytes()[0..name.len().min(DIRSIZ)];
+        Ok(self.dirents(dir)?.iter()
+            .find(|de| de.inum != 0 && de.name() == name)
+            .map(|de| de.inum as u32))
+    }
+
+    /// Check if `name` can be added to directory `dir`
+    fn check_new_name(&mut self, dir: u32, name: &str) -> Result<()> {
+        if name.is_empty() || name.len() > DIRSIZ {
+            return Err(format!("invalid name \"{}\", which should have 1 to {} bytes", name, DIRSIZ).into());
+        }
+        if self.dirlookup(dir, name)?.is_some() {
+            return Err(format!("\"{}\" already exists", name).into());
+        }
+        Ok(())
+    }
+
+    /// Add entry `name` for inode `inum` to directory `dir`
+    pub fn dirlink(&mut self, dir: u32, name: &str, inum: u32) -> Result<()> {
+        self.check_new_name(dir, name)?;
+        let mut de = Dirent::zero();
+        de.inum = inum as u16;
+        de.set_name(name.as_bytes());
+        self.append(dir, as_bytes(&de))
+    }
+
+    /// Look up inode number of absolute `path`
+    pub fn namei(&mut self, path: &str) -> Result<Option<u32>> {
+        let mut inum = ROOTINO;
+        for elem in path.split('/').filter(|elem| !elem.is_empty()) {
+            if self.read_inode(inum)?.typ != T_DIR {
+                return Ok(None);
+            }
+            inum = match self.dirlookup(inum, elem)? {
+                Some(inum) => inum,
+                None => return Ok(None)
+            };
+        }
+        Ok(Some(inum))
+    }
+
+    /// Look up parent directory of absolute `path`, return it with the final element
+    fn nameiparent<'a>(&mut self, path: &'a str) -> Result<(u32, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = match path.rfind('/') {
+            Some(pos) => (&path[0..pos], &path[pos + 1..]),
+            None => ("", path)
+        };
+        match self.namei(parent)? {
+            Some(dir) if self.read_inode(dir)?.typ == T_DIR => Ok((dir, name)),
+            _ => Err(format!("directory \"{}\" not found", parent).into())
+        }
+    }
+
+    /// Create a directory at `path`
+    pub fn mkdir(&mut self, path: &str) -> Result<u32> {
+        let (parent, name) = self.nameiparent(path)?;
+        self.check_new_name(parent, name)?;
+        let inum = self.ialloc(T_DIR)?;
+        self.dirlink(parent, name, inum)?;
+        self.dirlink(inum, ".", inum)?;
+        self.dirlink(inum, "..", parent)?;
+        // for ".."
+        let mut pdin = self.read_inode(parent)?;
+        pdin.nlink += 1;
+        self.write_inode(parent, &pdin)?;
+        Ok(inum)
+    }
+
+    /// Create a file at `path` with `content`
+    pub fn put(&mut self, path: &str, content: &[u8]) -> Result<u32> {
+        let (parent, name) = self.nameiparent(path)?;
+        self.check_new_name(parent, name)?;
+        let inum = self.ialloc(T_FILE)?;
+        self.dirlink(parent, name, inum)?;
+        self.append(inum, content)?;
+        Ok(inum)
+    }
+}
+
+/// Offset of inode `inum` in its inode block
+fn inode_offset(inum: u32) -> usize {
+    (inum as usize % IPB) * std::mem::size_of::<DInode>()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Image file in temporary directory, removed when dropped
+    pub struct TempImage {
+        pub path: PathBuf,
+    }
+
+    impl TempImage {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("mkfs-{}-{}.img", std::process::id(), name));
+            TempImage { path }
+        }
+
+        /// Create a small file system in the image
+        pub fn create(&self) -> Image {
+            Image::create(&self.path, 2048, 64).unwrap()
+        }
+    }
+
+    impl Drop for TempImage {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let tmp = TempImage::new("new");
+        tmp.create();
+        let mut img = Image::open(&tmp.path).unwrap();
+        assert_eq!(img.sb.size, 2048);
+        assert_eq!(img.sb.ninodes, 64);
+        assert_eq!(img.read_inode(ROOTINO).unwrap().typ, T_DIR);
+        assert_eq!(img.namei("/").unwrap(), Some(ROOTINO));
+        assert_eq!(img.namei("/..").unwrap(), Some(ROOTINO));
+    }
+
+    #[test]
+    fn test_put_get_large_file() {
+        let tmp = TempImage::new("large");
+        let mut img = tmp.create();
+        // reaches double indirect block, and ends in the middle of a block
+        let content: Vec<u8> = (0..(NDIRECT + NINDIRECT + 10) * BSIZE + 100).map(|i| (i % 251) as u8).collect();
+        let inum = img.put("/large", &content).unwrap();
+        assert_ne!(img.read_inode(inum).unwrap().addrs[NDIRECT + 1], 0);
+
+        let mut img = Image::open(&tmp.path).unwrap();
+        assert_eq!(img.namei("/large").unwrap(), Some(inum));
+        assert_eq!(img.read_all(inum).unwrap(), content);
+    }
+
+    #[test]
+    fn test_mkdir() {
+        let tmp = TempImage::new("mkdir");
+        let mut img = tmp.create();
+        let dir = img.mkdir("/dir").unwrap();
+        let sub = img.mkdir("/dir/sub/").unwrap();
+        let file = img.put("/dir/sub/file", b"hello").unwrap();
+        assert_eq!(img.namei("/dir/sub/file").unwrap(), Some(file));
+        assert_eq!(img.namei("/dir/sub/..").unwrap(), Some(dir));
+        assert_eq!(img.read_all(file).unwrap(), b"hello");
+        // "dir" is linked from "/" and "sub/.."
+        assert_eq!(img.read_inode(dir).unwrap().nlink, 2);
+        assert_eq!(img.read_inode(sub).unwrap().nlink, 1);
+        assert!(img.put("/none/file", b"").is_err());
+        assert!(img.put("/dir/sub/file/x", b"").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_name() {
+        let tmp = TempImage::new("duplicate");
+        let mut img = tmp.create();
+        let inum = img.put("/file", b"first").unwrap();
+        img.mkdir("/dir").unwrap();
+        assert!(img.put("/file", b"second").is_err());
+        assert!(img.mkdir("/file").is_err());
+        assert!(img.put("/dir", b"").is_err());
+        assert!(img.mkdir("/dir").is_err());
+        assert!(img.put("/dir/.", b"").is_err());
+        assert_eq!(img.read_all(inum).unwrap(), b"first");
+    }
+}
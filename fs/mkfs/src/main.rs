@@ -0,0 +1,123 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Build and inspect file system images of core-os
+//!
+//! ```plain
+//! mkfs new <image> [files...]      create an image, copying files to root directory
+//! mkfs mkdir <image> <path>        create a directory
+//! mkfs put <image> <file> <path>   copy a host file into image
+//! mkfs ls <image> [path]           list a directory
+//! mkfs get <image> <path> <file>   extract a file from image
+//! mkfs fsck <image>                check consistency of image
+//! ```
+
+mod image;
+mod fsck;
+
+use image::{Image, Result};
+use fs_defs::*;
+use std::path::Path;
+
+/// Size of file system in blocks, which is 32MB
+const FSSIZE: u32 = 32 * 1024;
+
+/// Number of inodes
+const NINODES: u32 = 200;
+
+const USAGE: &str = "usage:
+    mkfs new <image> [files...]      create an image, copying files to root directory
+    mkfs mkdir <image> <path>        create a directory
+    mkfs put <image> <file> <path>   copy a host file into image
+    mkfs ls <image> [path]           list a directory
+    mkfs get <image> <path> <file>   extract a file from image
+    mkfs fsck <image>                check consistency of image";
+
+/// Create a new image with `files` in root directory
+fn new(image: &str, files: &[&str]) -> Result<()> {
+    let mut img = Image::create(Path::new(image), FSSIZE, NINODES)?;
+    let sb = img.sb;
+    println!("nmeta {} (boot, super, log blocks {} inode blocks {}, bitmap blocks {}) blocks {} total {}",
+             sb.size - sb.nblocks, sb.nlog, sb.bmapstart - sb.inodestart,
+             sb.size - sb.nblocks - sb.bmapstart, sb.nblocks, sb.size);
+    for file in files {
+        let name = Path::new(file).file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| format!("invalid file name {}", file))?;
+        println!("Processing {} (/{} in fs)", file, name);
+        put(&mut img, file, &format!("/{}", name))?;
+    }
+    println!("{} files written", files.len());
+    Ok(())
+}
+
+/// Copy host file `file` to `path` in image
+fn put(img: &mut Image, file: &str, path: &str) -> Result<()> {
+    let content = std::fs::read(file).map_err(|e| format!("cannot open {}: {}", file, e))?;
+    img.put(path, &content)?;
+    Ok(())
+}
+
+/// List entries of directory, or a single file at `path`
+fn ls(img: &mut Image, path: &str) -> Result<()> {
+    let inum = img.namei(path)?.ok_or_else(|| format!("{} not found", path))?;
+    let din = img.read_inode(inum)?;
+    let entries = if din.typ == T_DIR {
+        img.dirents(inum)?.iter()
+            .filter(|de| de.inum != 0)
+            .map(|de| (String::from_utf8_lossy(de.name()).into_owned(), de.inum as u32))
+            .collect()
+    } else {
+        vec![(path.to_string(), inum)]
+    };
+    for (name, inum) in entries {
+        let din = img.read_inode(inum)?;
+        let typ = match din.typ {
+            T_DIR => "dir",
+            T_FILE => "file",
+            T_DEVICE => "device",
+            _ => "unknown",
+        };
+        println!("{:<14} {:<6} {:>4} {:>9}", name, typ, inum, din.size);
+    }
+    Ok(())
+}
+
+/// Extract `path` in image to host file `file`
+fn get(img: &mut Image, path: &str, file: &str) -> Result<()> {
+    let inum = img.namei(path)?.ok_or_else(|| format!("{} not found", path))?;
+    if img.read_inode(inum)?.typ != T_FILE {
+        return Err(format!("{} is not a file", path).into());
+    }
+    std::fs::write(file, img.read_all(inum)?)?;
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args.as_slice() {
+        ["new", image, files @ ..] => new(image, files),
+        ["mkdir", image, path] => Image::open(Path::new(image))?.mkdir(path).map(|_| ()),
+        ["put", image, file, path] => put(&mut Image::open(Path::new(image))?, file, path),
+        ["ls", image] => ls(&mut Image::open(Path::new(image))?, "/"),
+        ["ls", image, path] => ls(&mut Image::open(Path::new(image))?, path),
+        ["get", image, path, file] => get(&mut Image::open(Path::new(image))?, path, file),
+        ["fsck", image] => {
+            let errors = fsck::fsck(&mut Image::open(Path::new(image))?)?;
+            if errors != 0 {
+                return Err(format!("{} errors found", errors).into());
+            }
+            Ok(())
+        }
+        _ => Err(USAGE.into())
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("mkfs: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -13,7 +13,7 @@ use core::sync::atomic::Ordering;
 
 /// Get current time from MMIO
 pub fn time() -> Duration {
-    let mtime = crate::clint::CLINT_MTIME_BASE as *const u64;
+    let mtime = crate::clint::CLINT_MTIME() as *const u64;
     Duration::from_nanos(unsafe { mtime.read_volatile() } * 100)
 }
 
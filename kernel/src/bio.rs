@@ -0,0 +1,228 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Buffer cache
+//!
+//! The buffer cache holds `NBUF` cached copies of disk blocks. Caching
+//! reduces the number of disk reads, and provides a synchronization point
+//! for blocks used by multiple processes.
+//!
+//! * Get a locked buffer with `bread`. Only one process may hold the buffer
+//!   of a block at a time.
+//! * After changing buffer data, call `BufGuard::write` to write it to disk.
+//!   File system code should use `fs::log_write` instead, so that the change
+//!   is written as part of a transaction.
+//! * The buffer is released when `BufGuard` is dropped. A buffer is pinned
+//!   in cache as long as it is in use, and unused buffers are recycled in
+//!   least-recently-used order.
+//! * Call `invalidate` after a device is no longer used, e.g. unmounted, so that
+//!   its blocks are read from disk again next time.
+
+use crate::spinlock::Mutex;
+use crate::sleeplock::{SleepLock, SleepLockGuard};
+use crate::virtio::{disk, blk::Blk, BSIZE};
+use crate::panic;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+/// Number of buffers in cache
+pub const NBUF: usize = 30;
+
+/// Data of a cached block
+pub struct Buf {
+    pub data: [u8; BSIZE],
+}
+
+impl Buf {
+    pub const fn new() -> Self {
+        Self { data: [0; BSIZE] }
+    }
+}
+
+/// Which block a buffer holds, protected by `BCACHE` lock
+#[derive(Clone, Copy)]
+struct BufMeta {
+    dev: u32,
+    blockno: u32,
+    /// number of users of this buffer, buffer is pinned if non-zero
+    refcnt: usize,
+    /// whether data has been read from disk
+    valid: bool,
+    /// time when the buffer is last released, for LRU
+    last_used: usize,
+}
+
+impl BufMeta {
+    pub const fn new() -> Self {
+        Self { dev: 0, blockno: 0, refcnt: 0, valid: false, last_used: 0 }
+    }
+}
+
+struct BCache {
+    meta: [BufMeta; NBUF],
+    /// increases each time a buffer is released
+    clock: usize,
+}
+
+static BCACHE: Mutex<BCache> = Mutex::new(BCache {
+    meta: [BufMeta::new(); NBUF],
+    clock: 0,
+}, "bcache");
+
+const BUF_INIT: SleepLock<Buf> = SleepLock::new(Buf::new(), "buffer");
+
+/// Buffer data, each protected by a sleep lock
+static BUFS: [SleepLock<Buf>; NBUF] = [BUF_INIT; NBUF];
+
+/// A locked buffer. Buffer is released when guard is dropped.
+pub struct BufGuard {
+    /// Device number
+    pub dev: u32,
+    /// Block number
+    pub blockno: u32,
+    idx: usize,
+    buf: ManuallyDrop<SleepLockGuard<'static, Buf>>,
+}
+
+/// Look through buffer cache for block `blockno` on device `dev`.
+/// If not found, recycle the least recently used unused buffer.
+/// In either case, return index of the buffer with its reference count increased.
+fn bget(dev: u32, blockno: u32) -> usize {
+    let mut bcache = BCACHE.lock();
+    for i in 0..NBUF {
+        let m = &mut bcache.meta[i];
+        if m.refcnt != 0 || m.valid {
+            if m.dev == dev && m.blockno == blockno {
+                m.refcnt += 1;
+                return i;
+            }
+        }
+    }
+    let mut victim: Option<usize> = None;
+    for i in 0..NBUF {
+        let m = &bcache.meta[i];
+        if m.refcnt == 0 {
+            match victim {
+                Some(v) if bcache.meta[v].last_used <= m.last_used => {}
+                _ => victim = Some(i)
+            }
+        }
+    }
+    let i = match victim {
+        Some(i) => i,
+        None => panic!("bget: no buffers")
+    };
+    bcache.meta[i] = BufMeta { dev, blockno, refcnt: 1, valid: false, last_used: 0 };
+    i
+}
+
+/// Driver of device `dev`, which should have been checked to exist
+fn device(dev: u32) -> &'static Blk {
+    match disk(dev) {
+        Some(blk) => blk,
+        None => panic!("bio: no device {}", dev)
+    }
+}
+
+/// Return a locked buffer with content of block `blockno` on device `dev`.
+///
+/// As it may read from disk, this function should be called in process context.
+pub fn bread(dev: u32, blockno: u32) -> BufGuard {
+    let idx = bget(dev, blockno);
+    let mut buf = BUFS[idx].lock();
+    if !BCACHE.lock().meta[idx].valid {
+        device(dev).read(blockno, &mut buf.data);
+        BCACHE.lock().meta[idx].valid = true;
+    }
+    BufGuard { dev, blockno, idx, buf: ManuallyDrop::new(buf) }
+}
+
+/// Drop cached blocks of device `dev`. No buffer of `dev` should be in use.
+pub fn invalidate(dev: u32) {
+    let mut bcache = BCACHE.lock();
+    for m in bcache.meta.iter_mut().filter(|m| m.dev == dev) {
+        if m.refcnt != 0 {
+            panic!("invalidate: buffer of device {} in use", dev);
+        }
+        m.valid = false;
+    }
+}
+
+impl BufGuard {
+    /// Write buffer content to disk
+    pub fn write(&mut self) {
+        device(self.dev).write(self.blockno, &self.buf.data);
+    }
+
+    /// Keep buffer in cache even after it is released, until `unpin` is called.
+    pub fn pin(&self) {
+        BCACHE.lock().meta[self.idx].refcnt += 1;
+    }
+
+    /// Undo a `pin`
+    pub fn unpin(&self) {
+        BCACHE.lock().meta[self.idx].refcnt -= 1;
+    }
+}
+
+impl Deref for BufGuard {
+    type Target = Buf;
+    fn deref(&self) -> &Buf { &self.buf }
+}
+
+impl DerefMut for BufGuard {
+    fn deref_mut(&mut self) -> &mut Buf { &mut self.buf }
+}
+
+impl Drop for BufGuard {
+    /// Unlock the buffer, and unpin it if no one is using it.
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.buf); }
+        let mut bcache = BCACHE.lock();
+        bcache.clock += 1;
+        let clock = bcache.clock;
+        let m = &mut bcache.meta[self.idx];
+        m.refcnt -= 1;
+        if m.refcnt == 0 {
+            m.last_used = clock;
+        }
+    }
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("cache hit", test_cache_hit),
+            ("recycle", test_recycle),
+        ]
+    }
+
+    /// Test reading the same block twice hits the cache
+    pub fn test_cache_hit() {
+        let idx = {
+            let b = bread(1, 1);
+            b.idx
+        };
+        let b = bread(1, 1);
+        assert_eq!(b.idx, idx);
+        assert!(BCACHE.lock().meta[idx].valid);
+    }
+
+    /// Test reading more blocks than cache size recycles buffers
+    /// and keeps content consistent
+    pub fn test_recycle() {
+        let magic = {
+            let b = bread(1, 1);
+            b.data
+        };
+        for blockno in 2..(NBUF as u32 + 10) {
+            bread(1, blockno);
+        }
+        let b = bread(1, 1);
+        assert_eq!(&b.data[..], &magic[..]);
+    }
+}
@@ -10,11 +10,12 @@
 use crate::symbols::{NCPUS, SCHEDULER_INTERVAL};
 use crate::println;
 use crate::arch::{hart_id, sp};
+use crate::fdt::machine;
 
+/// CLINT base address on QEMU RISC-V, used if there's no device tree
 pub const CLINT_BASE: usize = 0x200_0000;
-pub const CLINT_MTIMECMP_BASE: usize = CLINT_BASE + 0x4000;
-pub const fn CLINT_MTIMECMP(hart: usize) -> usize { CLINT_MTIMECMP_BASE + 8 * hart }
-pub const CLINT_MTIME_BASE: usize = CLINT_BASE + 0xBFF8;
+pub fn CLINT_MTIMECMP(hart: usize) -> usize { machine().clint.base + 0x4000 + 8 * hart }
+pub fn CLINT_MTIME() -> usize { machine().clint.base + 0xBFF8 }
 
 /// space for timer trap to save information.
 static mut MSCRATCH0: [[u64; 8]; NCPUS] = [[0; 8]; NCPUS];
@@ -25,7 +26,7 @@ pub unsafe fn timer_init() {
     let id = mhartid::read();
     let interval = SCHEDULER_INTERVAL as u64;
     let mtimecmp = CLINT_MTIMECMP(id) as *mut u64;
-    let mtime = CLINT_MTIME_BASE as *const u64;
+    let mtime = CLINT_MTIME() as *const u64;
     mtimecmp.write_volatile(mtime.read_volatile() + interval);
     let scratch = &mut MSCRATCH0[id];
 
@@ -4,16 +4,22 @@
 // https://opensource.org/licenses/MIT
 
 //! ELF parsing
+//!
+//! Loadable segments are read from file page by page, so there is no limit
+//! on size of binaries. Segments need not be page-aligned, and two segments
+//! may share a page, which is then mapped with permissions of both.
 
-use crate::panic;
-use crate::mem;
-use crate::arch;
-use crate::page;
-use crate::process;
+use crate::page::{self, EntryAttributes};
+use crate::mem::page_down;
 use crate::symbols::*;
-use crate::{info, println};
+use crate::file::FsFile;
+use crate::fs::as_bytes_mut;
+use crate::process::USER_STACK_BEGIN;
+use crate::error::Errno;
+use core::mem::size_of;
 
 #[repr(C)]
+#[derive(Clone, Copy, Default)]
 pub struct ELFHeader {
     pub magic: u32,
     pub elf: [u8; 12],
@@ -33,6 +39,7 @@ pub struct ELFHeader {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy, Default)]
 pub struct ProgramHeader {
     pub ptype: u32,
     pub flags: u32,
@@ -49,75 +56,117 @@ const ELF_PROG_FLAG_EXEC: u32 = 1;
 const ELF_PROG_FLAG_WRITE: u32 = 2;
 const ELF_PROG_FLAG_READ: u32 = 4;
 const ELF_MAGIC: u32 = 0x464C457F;
+const ELF_CLASS_64: u8 = 2;
+const ELF_TYPE_EXEC: u16 = 2;
+const ELF_MACHINE_RISCV: u16 = 243;
 
-pub fn parse_elf(a: &[u8], pgtable: &mut page::Table) -> u64 {
-    let a = a.as_ptr();
-    /* TODO: Use something safer */
-    // peek head of byte array to get ELF information
-    let elfhdr = unsafe { &*(a as *const ELFHeader) };
-    if elfhdr.magic != ELF_MAGIC {
-        panic!("wrong magic number");
+/// Read exactly `buf.len()` bytes at `off` of `f`, returns `ENOEXEC` if file is too short.
+fn read_exact(f: &FsFile, buf: &mut [u8], off: usize) -> Result<(), Errno> {
+    if f.read_at(buf, off)? != buf.len() {
+        return Err(Errno::ENOEXEC);
     }
-    let mut proghdr = unsafe {
-        let offset_u8 = a.offset(elfhdr.phoff as isize);
-        offset_u8 as *const ProgramHeader
-    };
-    for _i in 0..elfhdr.phnum {
-        let hdr: &ProgramHeader = unsafe {
-            let hdr = &*proghdr;
-            proghdr = proghdr.offset(1);
-            hdr
-        };
+    Ok(())
+}
+
+/// Load all loadable segments of ELF file `f` into `pgtable`, and returns entry address
+/// and end address of the highest segment, which is the initial program break.
+///
+/// Returns `ENOEXEC` if `f` is not a valid RISC-V executable, or its segments
+/// overlap with user stack. `pgtable` may be partially filled on error.
+pub fn load_elf(f: &FsFile, pgtable: &mut page::Table) -> Result<(usize, usize), Errno> {
+    let mut elfhdr = ELFHeader::default();
+    read_exact(f, as_bytes_mut(&mut elfhdr), 0)?;
+    if elfhdr.magic != ELF_MAGIC
+        || elfhdr.elf[0] != ELF_CLASS_64
+        || elfhdr.etype != ELF_TYPE_EXEC
+        || elfhdr.machine != ELF_MACHINE_RISCV
+        || elfhdr.phentsize as usize != size_of::<ProgramHeader>() {
+        return Err(Errno::ENOEXEC);
+    }
+    let mut brk = 0;
+    for i in 0..elfhdr.phnum as usize {
+        let mut hdr = ProgramHeader::default();
+        let off = (elfhdr.phoff as usize).checked_add(i * size_of::<ProgramHeader>()).ok_or(Errno::ENOEXEC)?;
+        read_exact(f, as_bytes_mut(&mut hdr), off)?;
         if hdr.ptype != ELF_PROG_LOAD {
             continue;
         }
-        if hdr.memsz < hdr.filesz {
-            panic!("bad elf: memsz");
-        }
-        if hdr.vaddr + hdr.memsz < hdr.vaddr {
-            panic!("bad elf: vaddr");
-        }
-        if hdr.vaddr as usize % PAGE_SIZE != 0 {
-            println!("{:X}", hdr.vaddr);
-            panic!("bad elf: vaddr align")
-        }
-        load_segment(
-            pgtable,
-            hdr.vaddr as usize,
-            a,
-            hdr.off as usize,
-            hdr.filesz as usize,
-        );
-        /* println!(
-            "map segment ELF 0x{:X}~0x{:X} -> MEM 0x{:X}",
-            hdr.off,
-            hdr.off + hdr.filesz,
-            hdr.vaddr
-        ); */
+        brk = brk.max(load_segment(f, pgtable, &hdr)?);
     }
-    elfhdr.entry
+    Ok((elfhdr.entry as usize, brk))
 }
 
-fn load_segment(
-    pgtable: &mut page::Table,
-    vaddr: usize,
-    elf: *const u8,
-    offset: usize,
-    sz: usize,
-) {
-    let num_pages = mem::align_val(sz, PAGE_ORDER) / PAGE_SIZE;
-    for i in 0..num_pages {
-        let mut seg = page::Page::new();
-        let src = elf as *const u8;
-        unsafe {
-            let src = src.add(offset + i * PAGE_SIZE);
-            core::ptr::copy(src, seg.data.as_mut_ptr(), PAGE_SIZE);
+/// Map pages of segment `hdr` with its permissions, and copy its content from `f`.
+/// Returns end address of the segment.
+fn load_segment(f: &FsFile, pgtable: &mut page::Table, hdr: &ProgramHeader) -> Result<usize, Errno> {
+    let vaddr = hdr.vaddr as usize;
+    let filesz = hdr.filesz as usize;
+    let memsz = hdr.memsz as usize;
+    let off = hdr.off as usize;
+    if memsz < filesz {
+        return Err(Errno::ENOEXEC);
+    }
+    let end = vaddr.checked_add(memsz).ok_or(Errno::ENOEXEC)?;
+    if end > USER_STACK_BEGIN {
+        return Err(Errno::ENOEXEC);
+    }
+    let mut flags = EntryAttributes::U as usize;
+    if hdr.flags & (ELF_PROG_FLAG_READ | ELF_PROG_FLAG_WRITE) != 0 {
+        flags |= EntryAttributes::R as usize;
+    }
+    if hdr.flags & ELF_PROG_FLAG_WRITE != 0 {
+        flags |= EntryAttributes::W as usize;
+    }
+    if hdr.flags & ELF_PROG_FLAG_EXEC != 0 {
+        flags |= EntryAttributes::X as usize;
+    }
+    if flags == EntryAttributes::U as usize {
+        return Err(Errno::ENOEXEC);
+    }
+    for pg in (page_down(vaddr)..end).step_by(PAGE_SIZE) {
+        // pages are zeroed when allocated, so `memsz - filesz` bytes
+        // at the end of segment (e.g. `.bss`) are filled with zero.
+        let paddr = pgtable.map_or_add_flags(pg, flags);
+        // part of [vaddr, vaddr + filesz) in this page
+        let begin = vaddr.max(pg);
+        let file_end = (vaddr + filesz).min(pg + PAGE_SIZE);
+        if begin < file_end {
+            let dst = unsafe {
+                core::slice::from_raw_parts_mut((paddr + begin - pg) as *mut u8, file_end - begin)
+            };
+            let file_off = off.checked_add(begin - vaddr).ok_or(Errno::ENOEXEC)?;
+            read_exact(f, dst, file_off)?;
         }
-        use page::EntryAttributes;
-        pgtable.map(
-            vaddr + i * PAGE_SIZE,
-            seg,
-            EntryAttributes::URX as usize
-        );
+    }
+    Ok(end)
+}
+
+pub mod tests {
+    use super::*;
+    use crate::file::O_RDONLY;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("load", test_load),
+            ("load non-elf", test_load_non_elf),
+        ]
+    }
+
+    /// Test loading a user program, whose code is readable but not writable
+    pub fn test_load() {
+        let f = FsFile::open("/test1", O_RDONLY).unwrap();
+        let mut pgtable = box page::Table::new();
+        let (entry, brk) = load_elf(&f, &mut pgtable).unwrap();
+        assert!(entry < brk);
+        let mut code = [0; 4];
+        assert_eq!(pgtable.copy_from_user(&mut code, entry), Ok(()));
+        assert!(pgtable.copy_to_user(entry, &code).is_err());
+    }
+
+    /// Test loading a file which is not ELF
+    pub fn test_load_non_elf() {
+        let f = FsFile::open("/test.txt", O_RDONLY).unwrap();
+        let mut pgtable = box page::Table::new();
+        assert_eq!(load_elf(&f, &mut pgtable), Err(Errno::ENOEXEC));
     }
 }
@@ -0,0 +1,89 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Error codes returned by syscalls
+//!
+//! A syscall returns a non-negative value on success, or `-errno` on
+//! failure. Error codes follow Linux, and are mirrored in
+//! [error module in user crate](../../user/error/index.html).
+
+use crate::page::CopyError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// No such device or address
+    ENXIO = 6,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// Cross-device link
+    EXDEV = 18,
+    /// No such device
+    ENODEV = 19,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// File table overflow
+    ENFILE = 23,
+    /// Too many open files
+    EMFILE = 24,
+    /// File too large
+    EFBIG = 27,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Broken pipe
+    EPIPE = 32,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+}
+
+impl Errno {
+    /// Return value of a syscall failed with this error
+    pub fn as_ret(self) -> i32 {
+        -(self as i32)
+    }
+}
+
+impl From<CopyError> for Errno {
+    fn from(err: CopyError) -> Self {
+        match err {
+            CopyError::BadAddress(_) => Errno::EFAULT,
+            CopyError::BadString => Errno::EINVAL,
+        }
+    }
+}
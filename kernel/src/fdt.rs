@@ -0,0 +1,522 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Flattened device tree
+//!
+//! QEMU passes address of device tree blob in `a1` at boot. Boot hart parses
+//! it in `kinit` before memory is initialized, so the parser doesn't allocate.
+//! Memory ranges and devices found are saved in `Machine`, and the blob is not
+//! used afterwards, as it is in memory later handed out by allocator.
+//!
+//! If there's no valid device tree, devices of QEMU `virt` with 128M memory
+//! are assumed.
+
+use core::convert::TryInto;
+use crate::uart::UART_BASE_ADDR;
+use crate::virtio::VIRTIO_MMIO_BASE;
+use crate::plic::PLIC_BASE;
+use crate::clint::CLINT_BASE;
+use crate::symbols::NCPUS;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Maximum depth of nodes
+const MAX_DEPTH: usize = 16;
+
+/// Maximum number of memory ranges
+pub const MAX_MEMORY: usize = 4;
+
+/// Maximum number of `virtio,mmio` devices
+pub const MAX_VIRTIO: usize = 8;
+
+#[derive(Debug, PartialEq)]
+pub enum FdtError {
+    /// Not a device tree blob
+    BadMagic,
+    /// Structure or strings block ends unexpectedly
+    Truncated,
+    /// Unknown token in structure block
+    BadToken(u32),
+    /// Nodes are nested more than `MAX_DEPTH`
+    TooDeep,
+    /// There's no memory or hart in device tree
+    Incomplete,
+    /// There are more harts than `NCPUS`
+    TooManyHarts(usize),
+}
+
+/// Read a big-endian `u32` at `off`
+fn be32(data: &[u8], off: usize) -> Result<u32, FdtError> {
+    match data.get(off..off + 4) {
+        Some(b) => Ok(u32::from_be_bytes(b.try_into().unwrap())),
+        None => Err(FdtError::Truncated)
+    }
+}
+
+/// Get null-terminated string at `off`, without the terminator
+fn cstr(data: &[u8], off: usize) -> Result<&[u8], FdtError> {
+    let s = data.get(off..).ok_or(FdtError::Truncated)?;
+    match s.iter().position(|c| *c == 0) {
+        Some(len) => Ok(&s[..len]),
+        None => Err(FdtError::Truncated)
+    }
+}
+
+const fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+/// Whether string list property `prop` contains `s`
+fn has_string(prop: &[u8], s: &str) -> bool {
+    prop.split(|c| *c == 0).any(|item| item == s.as_bytes())
+}
+
+/// A node with properties used by kernel
+#[derive(Clone, Copy)]
+struct Node<'a> {
+    name: &'a [u8],
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    reg: &'a [u8],
+    interrupts: &'a [u8],
+    /// `#address-cells` of children
+    address_cells: u32,
+    /// `#size-cells` of children
+    size_cells: u32,
+}
+
+impl<'a> Node<'a> {
+    const fn new(name: &'a [u8]) -> Self {
+        Node {
+            name,
+            compatible: &[],
+            device_type: &[],
+            reg: &[],
+            interrupts: &[],
+            address_cells: 2,
+            size_cells: 1,
+        }
+    }
+
+    /// Iterate `(address, size)` in `reg`, whose cells are given by `parent`
+    fn regs(&self, parent: &Node) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (ac, sc) = (parent.address_cells as usize, parent.size_cells as usize);
+        let read = |cells: &[u8]| cells.chunks(4)
+            .fold(0usize, |val, cell| val << 32 | be32(cell, 0).unwrap_or(0) as usize);
+        self.reg.chunks_exact((ac + sc) * 4).map(move |entry| {
+            let (addr, size) = entry.split_at(ac * 4);
+            (read(addr), read(size))
+        })
+    }
+
+    /// First interrupt of node, or 0 if there's none
+    fn irq(&self) -> u32 {
+        be32(self.interrupts, 0).unwrap_or(0)
+    }
+}
+
+/// Device tree blob
+struct Fdt<'a> {
+    data: &'a [u8],
+    structs: usize,
+    strings: usize,
+}
+
+impl<'a> Fdt<'a> {
+    /// Get device tree blob at `addr`
+    unsafe fn from_addr(addr: usize) -> Result<Self, FdtError> {
+        if addr == 0 || (addr as *const u32).read_volatile() != FDT_MAGIC.to_be() {
+            return Err(FdtError::BadMagic);
+        }
+        let size = u32::from_be((addr as *const u32).add(1).read_volatile()) as usize;
+        let data = core::slice::from_raw_parts(addr as *const u8, size);
+        Ok(Fdt {
+            data,
+            structs: be32(data, 8)? as usize,
+            strings: be32(data, 12)? as usize,
+        })
+    }
+
+    /// Call `visit` with every node and its parent after all properties
+    /// and children of the node are read.
+    fn walk(&self, mut visit: impl FnMut(&Node, &Node)) -> Result<(), FdtError> {
+        let mut stack = [Node::new(&[]); MAX_DEPTH + 1];
+        let mut depth = 0;
+        let mut off = self.structs;
+        loop {
+            let token = be32(self.data, off)?;
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(self.data, off)?;
+                    off = align4(off + name.len() + 1);
+                    if depth == MAX_DEPTH {
+                        return Err(FdtError::TooDeep);
+                    }
+                    depth += 1;
+                    stack[depth] = Node::new(name);
+                }
+                FDT_END_NODE => {
+                    if depth == 0 {
+                        return Err(FdtError::BadToken(token));
+                    }
+                    visit(&stack[depth], &stack[depth - 1]);
+                    depth -= 1;
+                }
+                FDT_PROP => {
+                    let len = be32(self.data, off)? as usize;
+                    let name = cstr(self.data, self.strings + be32(self.data, off + 4)? as usize)?;
+                    let value = self.data.get(off + 8..off + 8 + len).ok_or(FdtError::Truncated)?;
+                    off = align4(off + 8 + len);
+                    let node = &mut stack[depth];
+                    match name {
+                        b"compatible" => node.compatible = value,
+                        b"device_type" => node.device_type = value,
+                        b"reg" => node.reg = value,
+                        b"interrupts" => node.interrupts = value,
+                        b"#address-cells" => node.address_cells = be32(value, 0)?,
+                        b"#size-cells" => node.size_cells = be32(value, 0)?,
+                        _ => {}
+                    }
+                }
+                FDT_NOP => {}
+                FDT_END => return Ok(()),
+                _ => return Err(FdtError::BadToken(token))
+            }
+        }
+    }
+}
+
+/// A memory-mapped device
+#[derive(Clone, Copy, Debug)]
+pub struct Device {
+    pub base: usize,
+    pub size: usize,
+    /// Interrupt number on PLIC
+    pub irq: u32,
+}
+
+impl Device {
+    const fn new(base: usize, size: usize, irq: u32) -> Self {
+        Device { base, size, irq }
+    }
+
+    fn from_node(node: &Node, parent: &Node) -> Option<Self> {
+        node.regs(parent).next().map(|(base, size)| Device::new(base, size, node.irq()))
+    }
+}
+
+/// Slot `i` of virtio transports on QEMU `virt`
+const fn virtio_slot(i: usize) -> Device {
+    Device::new(VIRTIO_MMIO_BASE + i * 0x1000, 0x1000, i as u32 + 1)
+}
+
+/// Memory and devices of machine
+pub struct Machine {
+    /// Memory ranges as `(start, end)`
+    memory: [(usize, usize); MAX_MEMORY],
+    nmemory: usize,
+    /// Number of harts
+    pub ncpus: usize,
+    pub plic: Device,
+    pub clint: Device,
+    /// Serial console
+    pub uart: Device,
+    virtio: [Device; MAX_VIRTIO],
+    nvirtio: usize,
+    /// Whether machine is described by device tree
+    pub from_fdt: bool,
+    /// Why device tree is not used, if it is rejected
+    pub fdt_error: Option<FdtError>,
+}
+
+impl Machine {
+    /// QEMU `virt` with 128M memory
+    const fn qemu_virt() -> Self {
+        Machine {
+            memory: [(0x8000_0000, 0x8800_0000); MAX_MEMORY],
+            nmemory: 1,
+            ncpus: 1,
+            plic: Device::new(PLIC_BASE, 0x40_0000, 0),
+            clint: Device::new(CLINT_BASE, 0x1_0000, 0),
+            uart: Device::new(UART_BASE_ADDR, 0x100, 10),
+            virtio: [
+                virtio_slot(0), virtio_slot(1), virtio_slot(2), virtio_slot(3),
+                virtio_slot(4), virtio_slot(5), virtio_slot(6), virtio_slot(7),
+            ],
+            nvirtio: MAX_VIRTIO,
+            from_fdt: false,
+            fdt_error: None,
+        }
+    }
+
+    /// Read machine from device tree blob at `addr`.
+    /// Devices not in device tree are assumed to be the same as QEMU `virt`.
+    unsafe fn from_fdt(addr: usize) -> Result<Self, FdtError> {
+        let fdt = Fdt::from_addr(addr)?;
+        let mut machine = Machine::qemu_virt();
+        machine.nmemory = 0;
+        machine.ncpus = 0;
+        machine.nvirtio = 0;
+        let mut uart_found = false;
+        fdt.walk(|node, parent| {
+            if has_string(node.device_type, "memory") {
+                for (start, size) in node.regs(parent) {
+                    if machine.nmemory < MAX_MEMORY {
+                        machine.memory[machine.nmemory] = (start, start + size);
+                        machine.nmemory += 1;
+                    }
+                }
+            } else if has_string(node.device_type, "cpu") {
+                machine.ncpus += 1;
+            } else if has_string(node.compatible, "riscv,plic0") {
+                machine.plic = Device::from_node(node, parent).unwrap_or(machine.plic);
+            } else if has_string(node.compatible, "riscv,clint0") {
+                machine.clint = Device::from_node(node, parent).unwrap_or(machine.clint);
+            } else if has_string(node.compatible, "ns16550a") && !uart_found {
+                if let Some(uart) = Device::from_node(node, parent) {
+                    machine.uart = uart;
+                    uart_found = true;
+                }
+            } else if has_string(node.compatible, "virtio,mmio") && machine.nvirtio < MAX_VIRTIO {
+                if let Some(dev) = Device::from_node(node, parent) {
+                    machine.virtio[machine.nvirtio] = dev;
+                    machine.nvirtio += 1;
+                }
+            }
+        })?;
+        if machine.nmemory == 0 || machine.ncpus == 0 {
+            return Err(FdtError::Incomplete);
+        }
+        // per-hart data and stacks are only allocated for `NCPUS` harts
+        if machine.ncpus > NCPUS {
+            return Err(FdtError::TooManyHarts(machine.ncpus));
+        }
+        // QEMU lists devices in descending address
+        machine.virtio[..machine.nvirtio].sort_unstable_by_key(|dev| dev.base);
+        machine.from_fdt = true;
+        Ok(machine)
+    }
+
+    /// Memory ranges as `(start, end)`
+    pub fn memory(&self) -> &[(usize, usize)] {
+        &self.memory[..self.nmemory]
+    }
+
+    /// End of memory range containing `addr`
+    pub fn memory_end(&self, addr: usize) -> Option<usize> {
+        self.memory().iter().find(|(start, end)| (*start..*end).contains(&addr)).map(|(_, end)| *end)
+    }
+
+    /// `virtio,mmio` devices, in ascending address
+    pub fn virtio(&self) -> &[Device] {
+        &self.virtio[..self.nvirtio]
+    }
+}
+
+static mut MACHINE: Machine = Machine::qemu_virt();
+
+/// Get machine information
+pub fn machine() -> &'static Machine {
+    unsafe { &MACHINE }
+}
+
+/// Read machine from device tree blob at `addr`.
+///
+/// This function should only be called in boot hart before other harts read `machine()`.
+pub unsafe fn init(addr: usize) {
+    match Machine::from_fdt(addr) {
+        Ok(machine) => MACHINE = machine,
+        Err(err) => MACHINE.fdt_error = Some(err)
+    }
+}
+
+/// Print memory and devices of machine
+pub fn debug() {
+    use crate::println;
+    let machine = machine();
+    for (start, end) in machine.memory() {
+        println!("memory: 0x{:x} -> 0x{:x}", start, end);
+    }
+    println!("harts: {}", machine.ncpus);
+    println!("PLIC: {:x?}", machine.plic);
+    println!("CLINT: {:x?}", machine.clint);
+    println!("UART: {:x?}", machine.uart);
+    for dev in machine.virtio() {
+        println!("virtio: {:x?}", dev);
+    }
+}
+
+pub mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("parse", test_parse),
+            ("bad magic", test_bad_magic),
+            ("too many harts", test_too_many_harts),
+        ]
+    }
+
+    /// Build device tree blob node by node
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            Builder { structs: Vec::new(), strings: Vec::new() }
+        }
+
+        fn token(&mut self, token: u32) {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+        }
+
+        fn pad(&mut self) {
+            while self.structs.len() % 4 != 0 {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+        }
+
+        fn end(&mut self) {
+            self.token(FDT_END_NODE);
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) {
+            self.token(FDT_PROP);
+            self.token(value.len() as u32);
+            self.token(self.strings.len() as u32);
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.structs.extend_from_slice(value);
+            self.pad();
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+            let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes().to_vec()).collect();
+            self.prop(name, &value);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.token(FDT_END);
+            let header = [
+                FDT_MAGIC,
+                (40 + self.structs.len() + self.strings.len()) as u32,
+                40,
+                40 + self.structs.len() as u32,
+                40,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ];
+            let mut blob: Vec<u8> = header.iter().flat_map(|x| x.to_be_bytes().to_vec()).collect();
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// Test parsing a device tree like QEMU `virt`
+    pub fn test_parse() {
+        let mut b = Builder::new();
+        b.begin("");
+        b.prop_cells("#address-cells", &[2]);
+        b.prop_cells("#size-cells", &[2]);
+        b.begin("memory@80000000");
+        b.prop("device_type", b"memory\0");
+        b.prop_cells("reg", &[0, 0x8000_0000, 0, 0x2000_0000]);
+        b.end();
+        b.begin("cpus");
+        b.prop_cells("#address-cells", &[1]);
+        b.prop_cells("#size-cells", &[0]);
+        for hart in 0..2 {
+            b.begin("cpu");
+            b.prop("device_type", b"cpu\0");
+            b.prop_cells("reg", &[hart]);
+            b.end();
+        }
+        b.end();
+        b.begin("soc");
+        b.prop_cells("#address-cells", &[2]);
+        b.prop_cells("#size-cells", &[2]);
+        for (base, irq) in &[(0x1000_2000, 2), (0x1000_1000, 1)] {
+            b.begin("virtio_mmio");
+            b.prop_cells("interrupts", &[*irq]);
+            b.prop("compatible", b"virtio,mmio\0");
+            b.prop_cells("reg", &[0, *base, 0, 0x1000]);
+            b.end();
+        }
+        b.begin("uart@10000000");
+        b.prop_cells("interrupts", &[10]);
+        b.prop_cells("reg", &[0, 0x1000_0000, 0, 0x100]);
+        b.prop("compatible", b"ns16550a\0");
+        b.end();
+        b.begin("plic@c000000");
+        b.prop("compatible", b"sifive,plic-1.0.0\0riscv,plic0\0");
+        b.prop_cells("reg", &[0, 0x0c00_0000, 0, 0x60_0000]);
+        b.end();
+        b.end();
+        b.end();
+        let blob = b.finish();
+
+        let machine = unsafe { Machine::from_fdt(blob.as_ptr() as usize) }.ok().unwrap();
+        assert_eq!(machine.memory(), &[(0x8000_0000, 0xa000_0000)]);
+        assert_eq!(machine.memory_end(0x8020_0000), Some(0xa000_0000));
+        assert_eq!(machine.ncpus, 2);
+        assert_eq!((machine.uart.base, machine.uart.irq), (0x1000_0000, 10));
+        assert_eq!((machine.plic.base, machine.plic.size), (0x0c00_0000, 0x60_0000));
+        let virtio: Vec<(usize, u32)> = machine.virtio().iter().map(|dev| (dev.base, dev.irq)).collect();
+        assert_eq!(virtio, [(0x1000_1000, 1), (0x1000_2000, 2)]);
+    }
+
+    /// Test that a blob without magic is rejected
+    pub fn test_bad_magic() {
+        let blob = [0u32; 16];
+        assert_eq!(unsafe { Machine::from_fdt(blob.as_ptr() as usize) }.err(), Some(FdtError::BadMagic));
+    }
+
+    /// Test that a device tree with more than `NCPUS` harts is rejected
+    pub fn test_too_many_harts() {
+        let mut b = Builder::new();
+        b.begin("");
+        b.prop_cells("#address-cells", &[2]);
+        b.prop_cells("#size-cells", &[2]);
+        b.begin("memory@80000000");
+        b.prop("device_type", b"memory\0");
+        b.prop_cells("reg", &[0, 0x8000_0000, 0, 0x800_0000]);
+        b.end();
+        b.begin("cpus");
+        b.prop_cells("#address-cells", &[1]);
+        b.prop_cells("#size-cells", &[0]);
+        for hart in 0..NCPUS as u32 + 1 {
+            b.begin("cpu");
+            b.prop("device_type", b"cpu\0");
+            b.prop_cells("reg", &[hart]);
+            b.end();
+        }
+        b.end();
+        b.end();
+        let blob = b.finish();
+
+        let err = unsafe { Machine::from_fdt(blob.as_ptr() as usize) }.err();
+        assert_eq!(err, Some(FdtError::TooManyHarts(NCPUS + 1)));
+    }
+}
@@ -6,16 +6,95 @@
 //! File in core-os including file in filesystem, device, pipe and symbol link
 
 pub mod device;
-pub use device::{Device, Console};
+pub use device::{Device, Console, device_of, consoleintr};
 
 pub mod fsfile;
 pub use fsfile::FsFile;
 
+pub mod pipe;
+pub use pipe::Pipe;
+
 use alloc::boxed::Box;
+use crate::fs::{self, T_DEVICE, T_DIR, T_FILE};
+use crate::error::Errno;
+
+/// Open for reading only
+pub const O_RDONLY: usize = 0x000;
+/// Open for writing only
+pub const O_WRONLY: usize = 0x001;
+/// Open for reading and writing
+pub const O_RDWR: usize = 0x002;
+/// Create file if it doesn't exist
+pub const O_CREATE: usize = 0x200;
+/// Truncate file to zero length
+pub const O_TRUNC: usize = 0x400;
+/// Write to the end of file
+pub const O_APPEND: usize = 0x800;
+
+/// File status, which is copied to user space in `fstat` syscall
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stat {
+    /// Device number of file system
+    pub dev: u32,
+    /// Inode number
+    pub ino: u32,
+    /// Inode type, or 0 for pipes
+    pub typ: u16,
+    /// Number of links to file
+    pub nlink: u16,
+    /// Size of file (bytes)
+    pub size: u32,
+}
 
 /// File in core-os
 pub enum File {
     Device(Box<dyn Device>),
     FsFile(FsFile),
-    Pipe
+    Pipe(Pipe),
+}
+
+impl File {
+    /// Open file of `path` with `mode`, which is a combination of `O_*` flags.
+    /// Device inodes are opened as `File::Device`.
+    ///
+    /// Returns `ENOENT` if file doesn't exist and `O_CREATE` is not set,
+    /// `EISDIR` if a directory is opened for writing or creating,
+    /// `ENOSPC` or `ENFILE` if file can't be created, or `ENODEV` if a device file has no driver.
+    pub fn open(path: &str, mode: usize) -> Result<Self, Errno> {
+        let _op = fs::begin_op();
+        let inode = if mode & O_CREATE != 0 {
+            fs::create(path, T_FILE, 0, 0).map_err(|err| match err {
+                Errno::EEXIST => Errno::EISDIR,
+                err => err
+            })?
+        } else {
+            fs::namei(path)?
+        };
+        let (typ, major) = {
+            let mut ip = inode.lock();
+            if ip.typ == T_DIR && mode & (O_WRONLY | O_RDWR) != 0 {
+                return Err(Errno::EISDIR);
+            }
+            if ip.typ == T_FILE && mode & O_TRUNC != 0 {
+                ip.truncate();
+            }
+            (ip.typ, ip.major)
+        };
+        if typ == T_DEVICE {
+            Ok(File::Device(device_of(major).ok_or(Errno::ENODEV)?))
+        } else {
+            Ok(File::FsFile(FsFile::from_inode(inode, mode)))
+        }
+    }
+
+    /// Get status of file. Inode of a device is not kept after it is opened,
+    /// so only its type is filled.
+    pub fn stat(&self) -> Stat {
+        match self {
+            File::FsFile(f) => f.stat(),
+            File::Device(_) => Stat { dev: 0, ino: 0, typ: T_DEVICE, nlink: 0, size: 0 },
+            File::Pipe(_) => Stat { dev: 0, ino: 0, typ: 0, nlink: 0, size: 0 },
+        }
+    }
 }
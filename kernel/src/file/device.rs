@@ -6,39 +6,94 @@
 //! Device trait for devices such as Console
 
 use crate::uart::UART;
+use crate::spinlock::Mutex;
+use crate::process::{sleep_interruptible, wakeup, my_proc};
+use crate::error::Errno;
+use alloc::boxed::Box;
+
+/// Major device number of console
+pub const CONSOLE: u16 = 1;
+
+/// Get device of major device number `major`
+pub fn device_of(major: u16) -> Option<Box<dyn Device>> {
+    match major {
+        CONSOLE => Some(box Console {}),
+        _ => None
+    }
+}
 
 /// Device trait
 ///
 /// All device should implement their own synchronize mechanisms.
 pub trait Device: Send + Sync {
     /// Read from file to content and returns number of characters (<= `content.len()`) read.
-    fn read(&self, content: &mut [u8]) -> i32;
+    fn read(&self, content: &mut [u8]) -> Result<usize, Errno>;
     /// Write content to file and returns number of characters written.
-    fn write(&self, content: &[u8]) -> i32;
+    fn write(&self, content: &[u8]) -> Result<usize, Errno>;
+}
+
+/// Size of console input buffer
+const INPUT_SIZE: usize = 128;
+
+/// Ring buffer of characters received from UART but not yet read
+struct ConsoleInput {
+    data: [u8; INPUT_SIZE],
+    /// number of bytes read
+    nread: usize,
+    /// number of bytes received
+    nwrite: usize,
+}
+
+static CONSOLE_INPUT: Mutex<ConsoleInput> = Mutex::new(ConsoleInput {
+    data: [0; INPUT_SIZE],
+    nread: 0,
+    nwrite: 0,
+}, "console input");
+
+/// Put a character received from UART into console input, and wake up
+/// readers. Characters are dropped if buffer is full.
+pub fn consoleintr(c: u8) {
+    let mut input = CONSOLE_INPUT.lock();
+    if input.nwrite - input.nread < INPUT_SIZE {
+        let pos = input.nwrite % INPUT_SIZE;
+        input.data[pos] = c;
+        input.nwrite += 1;
+        wakeup(&input.nread as *const _);
+    }
 }
 
 /// Console device
+///
+/// Console is in raw mode: characters are returned as soon as they are
+/// received, and are not echoed.
 pub struct Console {}
 
 impl Device for Console {
-    /// read from console
-    fn read(&self, content: &mut [u8]) -> i32 {
-        let mut uart = UART().lock();
-        for i in 0..content.len() {
-            match uart.get() {
-                Some(ch) => { content[i] = ch; }
-                _ => { return i as i32; }
+    /// read from console, blocks until at least one character is received
+    /// or current process is interrupted by a signal
+    fn read(&self, content: &mut [u8]) -> Result<usize, Errno> {
+        let mut input = CONSOLE_INPUT.lock();
+        while input.nread == input.nwrite {
+            if my_proc().interrupted() {
+                return Err(Errno::EINTR);
             }
+            input = sleep_interruptible(&input.nread as *const _, input);
+        }
+        let mut i = 0;
+        while i < content.len() && input.nread != input.nwrite {
+            content[i] = input.data[input.nread % INPUT_SIZE];
+            input.nread += 1;
+            i += 1;
         }
-        return content.len() as i32;
+        Ok(i)
     }
 
     /// write to console
-    fn write(&self, content: &[u8]) -> i32 {
+    fn write(&self, content: &[u8]) -> Result<usize, Errno> {
         let mut uart = UART().lock();
         for i in 0..content.len() {
             uart.put(content[i]);
         }
-        return content.len() as i32;
+        Ok(content.len())
     }
 }
@@ -5,85 +5,109 @@
 
 //! File on file system
 
-use crate::virtio::{VIRTIO, VirtIO, BSIZE};
+use crate::fs::{self, Inode, BSIZE, MAXOPBLOCKS};
 use crate::{print, println};
 use crate::spinlock::Mutex;
+use super::{File, Stat, O_RDONLY, O_WRONLY, O_RDWR, O_APPEND};
+use crate::error::Errno;
+use alloc::sync::Arc;
 
 pub struct FsFile {
-    offset: usize,
-    sz: usize,
-    rw_offset: Mutex<(usize, usize)>,
+    inode: Arc<Inode>,
+    /// offset shared by reads and writes
+    rw_offset: Mutex<usize>,
     readable: bool,
     writable: bool,
+    append: bool,
 }
 
-const HEADER_SIZE: usize = 1024;
-const FILE_MAX: usize = 1024;
-
 impl FsFile {
-    fn get_file_info(virtio: &mut VirtIO, path: &str) -> Option<(usize, usize)> {
-        for id in 0..FILE_MAX {
-            let b = virtio.read(1, id as u32);
-            let sz = unsafe { core::ptr::read(b.data.as_ptr() as *const usize) };
-            let offset = unsafe { core::ptr::read((b.data.as_ptr() as *const usize).add(1)) };
-            if sz == 0 {
-                break;
-            }
-            let name_sz = {
-                let mut i = 16;
-                loop {
-                    let d = b.data[i];
-                    if d == 0 {
-                        break;
-                    }
-                    i += 1;
-                    if i == b.data.len() { break; }
-                }
-                i - 16
-            };
-            let u8_slice = unsafe { core::slice::from_raw_parts(b.data.as_ptr().add(16), name_sz) };
-            let name = core::str::from_utf8(u8_slice).unwrap();
-            if name == path {
-                return Some((offset, sz));
-            }
+    /// Open file of `inode` with `mode`
+    pub fn from_inode(inode: Arc<Inode>, mode: usize) -> Self {
+        Self {
+            inode,
+            rw_offset: Mutex::new(0, "file rw offset"),
+            readable: mode & O_WRONLY == 0,
+            writable: mode & (O_WRONLY | O_RDWR) != 0,
+            append: mode & O_APPEND != 0,
         }
-        None
     }
 
-    pub fn open(path: &str, mode: usize) -> Self {
-        let virtio = VIRTIO();
-        let (offset, sz) = match Self::get_file_info(virtio, path) {
-            Some(x) => x,
-            None => { panic!("{} not found", path); }
-        };
-        Self {
-            offset,
-            sz,
-            rw_offset: Mutex::new((0, 0), "file rw offset"),
-            readable: true,
-            writable: true,
+    /// Open file of `path` with `mode`. Returns error of `File::open` if file can't be opened,
+    /// or `EINVAL` if it is not a file on file system.
+    pub fn open(path: &str, mode: usize) -> Result<Self, Errno> {
+        match File::open(path, mode)? {
+            File::FsFile(f) => Ok(f),
+            _ => Err(Errno::EINVAL)
         }
     }
 
-    pub fn read(&self, content: &mut [u8]) -> i32 {
-        use crate::info;
-        if !self.readable { return -1; }
-        let virtio = VIRTIO();
-        let read_offset = self.rw_offset.lock().0;
-        let read_sz = (self.sz - read_offset).min(content.len());
-        let result = virtio.read(1, ((self.offset + read_offset) / BSIZE) as u32);
-        // content.copy_from_slice(&result.data[0..content.len()]);
-        for i in 0..read_sz {
-            content[i] = result.data[i];
+    /// Read from current offset and returns number of characters read,
+    /// or `EBADF` if file is not opened for reading.
+    pub fn read(&self, content: &mut [u8]) -> Result<usize, Errno> {
+        if !self.readable { return Err(Errno::EBADF); }
+        // offset is updated with inode locked, so that concurrent reads
+        // won't read the same content
+        let mut ip = self.inode.lock();
+        let read_offset = *self.rw_offset.lock();
+        let read_sz = ip.read(content, read_offset);
+        *self.rw_offset.lock() = read_offset + read_sz;
+        Ok(read_sz)
+    }
+
+    /// Read from offset `off` without changing current offset, and returns number
+    /// of characters read, or `EBADF` if file is not opened for reading.
+    pub fn read_at(&self, content: &mut [u8], off: usize) -> Result<usize, Errno> {
+        if !self.readable { return Err(Errno::EBADF); }
+        Ok(self.inode.lock().read(content, off))
+    }
+
+    /// Get status of file from its inode
+    pub fn stat(&self) -> Stat {
+        let ip = self.inode.lock();
+        Stat {
+            dev: self.inode.dev,
+            ino: self.inode.inum,
+            typ: ip.typ,
+            nlink: ip.nlink,
+            size: ip.size,
         }
-        self.rw_offset.lock().0 = read_offset + read_sz;
-        // println!("off: {:x}, rsz: {:x}, sz: {:x}", read_offset, read_sz, self.sz);
-        return read_sz as i32;
     }
 
-    pub fn write(&self, content: &[u8]) -> i32 {
-        if !self.writable { return -1; }
-        unimplemented!()
+    /// Write at current offset, or the end of file in append mode, and returns
+    /// number of characters written, which is less than `content.len()` if disk
+    /// becomes full. Returns `EBADF` if file is not opened for writing, `EFBIG` if
+    /// nothing can be written as file reaches maximum size, or `ENOSPC` if nothing
+    /// can be written as disk is full.
+    pub fn write(&self, content: &[u8]) -> Result<usize, Errno> {
+        if !self.writable { return Err(Errno::EBADF); }
+        // write a few blocks at a time to avoid exceeding maximum log
+        // transaction size, including inode, indirect blocks, allocation
+        // blocks, and 2 blocks of slop for non-aligned writes.
+        let max = ((MAXOPBLOCKS - 1 - 1 - 2) / 2) * BSIZE;
+        let mut tot = 0;
+        while tot < content.len() {
+            let n = (content.len() - tot).min(max);
+            let _op = fs::begin_op();
+            let mut ip = self.inode.lock();
+            let write_offset = if self.append {
+                ip.size as usize
+            } else {
+                *self.rw_offset.lock()
+            };
+            match ip.write(&content[tot..tot + n], write_offset) {
+                Ok(write_sz) => {
+                    *self.rw_offset.lock() = write_offset + write_sz;
+                    tot += write_sz;
+                    if write_sz != n {
+                        break;
+                    }
+                }
+                Err(err) if tot == 0 => return Err(err),
+                Err(_) => break
+            }
+        }
+        Ok(tot)
     }
 }
 
@@ -95,6 +119,11 @@ pub mod tests {
             ("open", test_open),
             ("read", test_read),
             ("read_elf", test_read_elf),
+            ("open non-existing file", test_open_non_existing),
+            ("write", test_write),
+            ("write modes", test_write_modes),
+            ("stat", test_stat),
+            ("write to full disk", test_full_disk),
         ]
     }
 
@@ -102,22 +131,117 @@ pub mod tests {
 
     /// Test open
     pub fn test_open() {
-        let f = FsFile::open("/test.txt", 0);
+        let f = FsFile::open("/test.txt", 0).unwrap();
     }
 
     /// Test read
     pub fn test_read() {
-        let f = FsFile::open("/test.txt", 0);
+        let f = FsFile::open("/test.txt", 0).unwrap();
         let mut content = [0; 10];
-        assert_eq!(f.read(&mut content), 10);
+        assert_eq!(f.read(&mut content), Ok(10));
         assert_eq!(content, [48, 49, 50, 51, 52, 53, 54, 55, 56, 57]);
     }
 
-    /// Test read
+    /// Test read a file of multiple blocks
     pub fn test_read_elf() {
-        let f = FsFile::open("/test1", 0);
+        let f = FsFile::open("/test1", 0).unwrap();
         let mut content = [0; 1024];
-        while f.read(&mut content) == 1024 {}
+        while f.read(&mut content) == Ok(1024) {}
     }
-}
 
+    /// Test open a file which doesn't exist
+    pub fn test_open_non_existing() {
+        assert_eq!(FsFile::open("/non-existing", 0).err(), Some(Errno::ENOENT));
+        assert_eq!(FsFile::open("/test.txt/a", 0).err(), Some(Errno::ENOENT));
+    }
+
+    /// Test write and read back a file across block boundary
+    pub fn test_write() {
+        use crate::fs::{unlink, BSIZE};
+        use crate::file::{O_CREATE, O_TRUNC};
+        let content = [0x5a; BSIZE + 100];
+        {
+            let f = FsFile::open("/test.write", O_CREATE | O_RDWR).unwrap();
+            assert_eq!(f.write(&content[0..10]), Ok(10));
+            assert_eq!(f.write(&content[10..]), Ok(BSIZE + 90));
+        }
+        {
+            let f = FsFile::open("/test.write", O_RDONLY).unwrap();
+            let mut buf = [0; BSIZE + 200];
+            assert_eq!(f.read(&mut buf), Ok(BSIZE + 100));
+            assert_eq!(&buf[0..BSIZE + 100], &content[..]);
+            assert_eq!(f.write(&content), Err(Errno::EBADF));
+        }
+        {
+            let f = FsFile::open("/test.write", O_WRONLY | O_TRUNC).unwrap();
+            let mut buf = [0; 10];
+            assert_eq!(f.read(&mut buf), Err(Errno::EBADF));
+            assert_eq!(f.write(b"0123"), Ok(4));
+        }
+        assert_eq!(FsFile::open("/test.write", O_RDONLY).unwrap().inode.lock().size, 4);
+        assert_eq!(unlink("/test.write"), Ok(()));
+    }
+
+    /// Test append mode and opening directory for writing
+    pub fn test_write_modes() {
+        use crate::fs::unlink;
+        use crate::file::O_CREATE;
+        {
+            let f = FsFile::open("/test.append", O_CREATE | O_WRONLY).unwrap();
+            assert_eq!(f.write(b"0123"), Ok(4));
+        }
+        {
+            let f = FsFile::open("/test.append", O_WRONLY | O_APPEND).unwrap();
+            assert_eq!(f.write(b"45"), Ok(2));
+        }
+        {
+            let f = FsFile::open("/test.append", O_RDONLY).unwrap();
+            let mut buf = [0; 10];
+            assert_eq!(f.read(&mut buf), Ok(6));
+            assert_eq!(&buf[0..6], b"012345");
+        }
+        assert_eq!(unlink("/test.append"), Ok(()));
+        assert_eq!(FsFile::open("/", O_RDWR).err(), Some(Errno::EISDIR));
+        assert!(File::open("/", O_RDONLY).is_ok());
+    }
+
+    /// Test getting status of files and directories
+    pub fn test_stat() {
+        use crate::fs::{T_FILE, T_DIR, ROOTINO};
+        let st = FsFile::open("/test.txt", O_RDONLY).unwrap().stat();
+        assert_eq!(st.typ, T_FILE);
+        assert_eq!(st.nlink, 1);
+        assert_eq!(st.size, 51);
+        let st = FsFile::open("/", O_RDONLY).unwrap().stat();
+        assert_eq!(st.typ, T_DIR);
+        assert_eq!(st.ino, ROOTINO);
+    }
+
+    /// Test that filling up disk returns errors instead of panicking,
+    /// and space is available again after the file is removed
+    pub fn test_full_disk() {
+        use crate::fs::{create, unlink, T_DIR};
+        use crate::file::O_CREATE;
+        let content = [0x5a; BSIZE * 16];
+        {
+            let f = FsFile::open("/test.full", O_CREATE | O_WRONLY).unwrap();
+            loop {
+                match f.write(&content) {
+                    Ok(n) if n == content.len() => {}
+                    Ok(_) => break,
+                    Err(err) => {
+                        assert_eq!(err, Errno::ENOSPC);
+                        break;
+                    }
+                }
+            }
+            assert_eq!(f.write(&content), Err(Errno::ENOSPC));
+            // a directory needs a block for `.` and `..`
+            assert_eq!(create("/test.full.dir", T_DIR, 0, 0).err(), Some(Errno::ENOSPC));
+            assert_eq!(crate::fs::namei("/test.full.dir").err(), Some(Errno::ENOENT));
+        }
+        assert_eq!(unlink("/test.full"), Ok(()));
+        assert!(create("/test.full.dir", T_DIR, 0, 0).is_ok());
+        assert_eq!(unlink("/test.full.dir"), Ok(()));
+    }
+}
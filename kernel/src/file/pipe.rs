@@ -0,0 +1,163 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Anonymous pipe backed by a kernel ring buffer
+
+use crate::spinlock::Mutex;
+use crate::process::{sleep_interruptible, wakeup, my_proc};
+use crate::error::Errno;
+use alloc::sync::Arc;
+
+/// Size of pipe buffer
+pub const PIPE_SIZE: usize = 512;
+
+/// Ring buffer shared by both ends of a pipe
+struct PipeData {
+    data: [u8; PIPE_SIZE],
+    /// number of bytes read
+    nread: usize,
+    /// number of bytes written
+    nwrite: usize,
+    /// whether read end is still open
+    read_open: bool,
+    /// whether write end is still open
+    write_open: bool,
+}
+
+/// One end of a pipe
+///
+/// An end is closed when it is dropped. As `File` is shared with `Arc`
+/// between duplicated file descriptors and forked processes, this happens
+/// after all file descriptors referring to it are closed.
+pub struct Pipe {
+    data: Arc<Mutex<PipeData>>,
+    writable: bool,
+}
+
+impl Pipe {
+    /// Create a pipe and returns its read end and write end.
+    pub fn new() -> (Self, Self) {
+        let data = Arc::new(Mutex::new(PipeData {
+            data: [0; PIPE_SIZE],
+            nread: 0,
+            nwrite: 0,
+            read_open: true,
+            write_open: true,
+        }, "pipe"));
+        (
+            Self { data: data.clone(), writable: false },
+            Self { data, writable: true }
+        )
+    }
+
+    /// Read from pipe and returns number of characters read.
+    ///
+    /// Blocks until there is data in pipe. Returns 0 if pipe is empty and
+    /// all write ends are closed, `EBADF` if this is the write end, or
+    /// `EINTR` if current process is interrupted by a signal while waiting.
+    pub fn read(&self, content: &mut [u8]) -> Result<usize, Errno> {
+        if self.writable { return Err(Errno::EBADF); }
+        let mut pipe = self.data.lock();
+        while pipe.nread == pipe.nwrite && pipe.write_open {
+            if my_proc().interrupted() {
+                return Err(Errno::EINTR);
+            }
+            pipe = sleep_interruptible(&pipe.nread as *const _, pipe);
+        }
+        let mut i = 0;
+        while i < content.len() && pipe.nread != pipe.nwrite {
+            content[i] = pipe.data[pipe.nread % PIPE_SIZE];
+            pipe.nread += 1;
+            i += 1;
+        }
+        wakeup(&pipe.nwrite as *const _);
+        Ok(i)
+    }
+
+    /// Write content to pipe and returns number of characters written.
+    ///
+    /// Blocks until all content is written. Returns `EPIPE` if all read ends
+    /// are closed, `EBADF` if this is the read end, or `EINTR` if current
+    /// process is interrupted by a signal while waiting.
+    pub fn write(&self, content: &[u8]) -> Result<usize, Errno> {
+        if !self.writable { return Err(Errno::EBADF); }
+        let mut pipe = self.data.lock();
+        let mut i = 0;
+        while i < content.len() {
+            if !pipe.read_open {
+                return Err(Errno::EPIPE);
+            }
+            if pipe.nwrite == pipe.nread + PIPE_SIZE {
+                if my_proc().interrupted() {
+                    return Err(Errno::EINTR);
+                }
+                wakeup(&pipe.nread as *const _);
+                pipe = sleep_interruptible(&pipe.nwrite as *const _, pipe);
+            } else {
+                let idx = pipe.nwrite % PIPE_SIZE;
+                pipe.data[idx] = content[i];
+                pipe.nwrite += 1;
+                i += 1;
+            }
+        }
+        wakeup(&pipe.nread as *const _);
+        Ok(i)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut pipe = self.data.lock();
+        if self.writable {
+            pipe.write_open = false;
+            wakeup(&pipe.nread as *const _);
+        } else {
+            pipe.read_open = false;
+            wakeup(&pipe.nwrite as *const _);
+        }
+    }
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("read and write", test_rw),
+            ("eof", test_eof),
+            ("broken pipe", test_broken_pipe),
+        ]
+    }
+
+    /// Test read and write
+    pub fn test_rw() {
+        let (rx, tx) = Pipe::new();
+        assert_eq!(tx.write(b"hello"), Ok(5));
+        let mut content = [0; 3];
+        assert_eq!(rx.read(&mut content), Ok(3));
+        assert_eq!(&content, b"hel");
+        assert_eq!(rx.read(&mut content), Ok(2));
+        assert_eq!(&content[0..2], b"lo");
+        assert_eq!(rx.write(b"hello"), Err(Errno::EBADF));
+        assert_eq!(tx.read(&mut content), Err(Errno::EBADF));
+    }
+
+    /// Test reading from pipe whose write end is closed
+    pub fn test_eof() {
+        let (rx, tx) = Pipe::new();
+        assert_eq!(tx.write(b"a"), Ok(1));
+        drop(tx);
+        let mut content = [0; 2];
+        assert_eq!(rx.read(&mut content), Ok(1));
+        assert_eq!(rx.read(&mut content), Ok(0));
+    }
+
+    /// Test writing to pipe whose read end is closed
+    pub fn test_broken_pipe() {
+        let (rx, tx) = Pipe::new();
+        drop(rx);
+        assert_eq!(tx.write(b"a"), Err(Errno::EPIPE));
+    }
+}
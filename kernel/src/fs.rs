@@ -0,0 +1,108 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! xv6-style file system
+//!
+//! The file system is organized in layers:
+//! * blocks: read and write disk blocks through buffer cache in `bio`,
+//!   and allocate blocks with the free bit map.
+//! * log: group changes of file system operations into crash-safe transactions.
+//! * inodes: allocate inodes, read and write their content.
+//! * directories: inodes whose content is a list of directory entries.
+//! * path names: look up paths like `/usr/bin/sh`.
+//! * mounts: file systems on other disks are mounted on directories.
+//!
+//! The on-disk layout is described in crate `fs_defs`, which is shared with `mkfs`.
+
+pub use fs_defs::*;
+
+pub mod log;
+pub use log::{begin_op, log_write, LogOp, recover, MAXOPBLOCKS};
+
+pub mod inode;
+pub use inode::*;
+
+pub mod dir;
+pub use dir::*;
+
+pub mod mount;
+pub use mount::{mount, umount};
+
+use crate::bio::bread;
+use crate::error::Errno;
+use crate::{info, panic};
+
+/// Device number of root disk
+pub const ROOTDEV: u32 = 1;
+
+/// Device numbers of disks with file system are below `NDEV`
+pub const NDEV: usize = 10;
+
+/// Super blocks of file systems in use, indexed by device number
+static mut SB: [SuperBlock; NDEV] = [SuperBlock::zero(); NDEV];
+
+/// Get super block of file system on `dev`
+pub fn sb(dev: u32) -> &'static SuperBlock {
+    unsafe { &SB[dev as usize] }
+}
+
+/// Read super block of `dev` and set up its log.
+/// Returns `EINVAL` if there's no file system on `dev`.
+///
+/// As it reads from disk, this function should be called in process context.
+pub fn init(dev: u32) -> Result<(), Errno> {
+    let b = bread(dev, 1);
+    let sb: SuperBlock = read_struct(&b.data, 0);
+    if sb.magic != FSMAGIC {
+        return Err(Errno::EINVAL);
+    }
+    unsafe { SB[dev as usize] = sb; }
+    log::init(dev, &sb);
+    info!("file system on device {}: {} blocks, {} inodes, {} data blocks",
+          dev, sb.size, sb.ninodes, sb.nblocks);
+    Ok(())
+}
+
+/// Zero a block
+fn bzero(dev: u32, blockno: u32) {
+    let mut b = bread(dev, blockno);
+    b.data = [0; BSIZE];
+    log_write(&b);
+}
+
+/// Allocate a zeroed disk block, or returns `None` if disk is full
+pub fn balloc(dev: u32) -> Option<u32> {
+    let sb = sb(dev);
+    let mut b = 0;
+    while b < sb.size {
+        let mut bp = bread(dev, sb.bblock(b));
+        let mut bi = 0;
+        while bi < BPB as u32 && b + bi < sb.size {
+            let m = 1 << (bi % 8);
+            if bp.data[bi as usize / 8] & m == 0 {
+                bp.data[bi as usize / 8] |= m;
+                log_write(&bp);
+                drop(bp);
+                bzero(dev, b + bi);
+                return Some(b + bi);
+            }
+            bi += 1;
+        }
+        b += BPB as u32;
+    }
+    None
+}
+
+/// Free a disk block
+pub fn bfree(dev: u32, blockno: u32) {
+    let mut bp = bread(dev, sb(dev).bblock(blockno));
+    let bi = blockno as usize % BPB;
+    let m = 1 << (bi % 8);
+    if bp.data[bi / 8] & m == 0 {
+        panic!("freeing free block {}", blockno);
+    }
+    bp.data[bi / 8] &= !m;
+    log_write(&bp);
+}
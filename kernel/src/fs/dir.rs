@@ -0,0 +1,336 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Directories and path names
+//!
+//! A directory is an inode of type `T_DIR`, whose content is a sequence of
+//! `Dirent`s. Every directory contains `.` and `..`.
+
+use super::*;
+use crate::process::my_proc;
+use crate::error::Errno;
+use alloc::sync::Arc;
+
+/// Read the directory entry at offset `off`
+fn read_dirent(dp: &mut InodeGuard, off: usize) -> Dirent {
+    let mut de = Dirent::zero();
+    if dp.read(as_bytes_mut(&mut de), off) != DIRENT_SIZE {
+        panic!("read dirent");
+    }
+    de
+}
+
+/// Look for a directory entry in directory `dp`, and return its inode number
+/// and offset if found.
+fn dirfind(dp: &mut InodeGuard, name: &[u8]) -> Option<(u32, usize)> {
+    if dp.typ != T_DIR {
+        panic!("dirlookup not DIR");
+    }
+    let name = &name[0..name.len().min(DIRSIZ)];
+    let mut off = 0;
+    while off < dp.size as usize {
+        let de = read_dirent(dp, off);
+        if de.inum != 0 && de.name() == name {
+            return Some((de.inum as u32, off));
+        }
+        off += DIRENT_SIZE;
+    }
+    None
+}
+
+/// Look for a directory entry in directory `dp`.
+///
+/// Returns the inode and offset of the entry, `ENOENT` if it is not found,
+/// or `ENFILE` if all in-memory inodes are in use.
+pub fn dirlookup(dp: &mut InodeGuard, name: &[u8]) -> Result<(Arc<Inode>, usize), Errno> {
+    let (inum, off) = dirfind(dp, name).ok_or(Errno::ENOENT)?;
+    Ok((iget(dp.inode.dev, inum)?, off))
+}
+
+/// Write a new directory entry (`name`, `inum`) into directory `dp`.
+///
+/// Returns `EEXIST` if `name` already exists, or `ENOSPC` if directory
+/// can't grow as disk is full.
+pub fn dirlink(dp: &mut InodeGuard, name: &[u8], inum: u32) -> Result<(), Errno> {
+    if dirfind(dp, name).is_some() {
+        return Err(Errno::EEXIST);
+    }
+    // look for an empty dirent, or append to the end
+    let mut off = 0;
+    while off < dp.size as usize {
+        if read_dirent(dp, off).inum == 0 {
+            break;
+        }
+        off += DIRENT_SIZE;
+    }
+    let mut de = Dirent::zero();
+    de.inum = inum as u16;
+    de.set_name(name);
+    // a directory entry never crosses block boundary, so it is either written or not
+    if dp.write(as_bytes(&de), off)? != DIRENT_SIZE {
+        panic!("dirlink");
+    }
+    Ok(())
+}
+
+/// Check if directory `dp` contains nothing other than `.` and `..`
+fn is_dir_empty(dp: &mut InodeGuard) -> bool {
+    let mut off = 2 * DIRENT_SIZE;
+    while off < dp.size as usize {
+        if read_dirent(dp, off).inum != 0 {
+            return false;
+        }
+        off += DIRENT_SIZE;
+    }
+    true
+}
+
+/// Split the first element from path.
+///
+/// Returns the element and the rest of path, or `None` if there's no element.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(skip_elem("a/bb/c"), Some(("a", "bb/c")));
+/// assert_eq!(skip_elem("///a//bb"), Some(("a", "bb")));
+/// assert_eq!(skip_elem("a"), Some(("a", "")));
+/// assert_eq!(skip_elem(""), None);
+/// assert_eq!(skip_elem("////"), None);
+/// ```
+fn skip_elem(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return None;
+    }
+    let (elem, rest) = match path.find('/') {
+        Some(pos) => (&path[0..pos], &path[pos..]),
+        None => (path, "")
+    };
+    Some((elem, rest.trim_start_matches('/')))
+}
+
+/// Look up inode of `path`. If `parent` is set, return inode of the parent
+/// directory and the final path element instead.
+///
+/// Lookup crosses mount points in both directions, so an inode on any
+/// mounted file system may be returned.
+///
+/// Returns `ENOENT` if `path` doesn't exist, or `ENFILE` if all in-memory inodes are in use.
+fn namex(path: &str, parent: bool) -> Result<(Arc<Inode>, &str), Errno> {
+    let mut ip = if path.starts_with('/') {
+        iget(ROOTDEV, ROOTINO)?
+    } else {
+        my_proc().cwd.clone().unwrap()
+    };
+    let mut path = path;
+    let mut name = "";
+    while let Some((elem, rest)) = skip_elem(path) {
+        name = elem;
+        path = rest;
+        if name == ".." {
+            ip = mount::leave(ip);
+        }
+        let mut guard = ip.lock();
+        if guard.typ != T_DIR {
+            return Err(Errno::ENOENT);
+        }
+        if parent && skip_elem(path).is_none() {
+            // stop one level early
+            drop(guard);
+            return Ok((ip, name));
+        }
+        let next = dirlookup(&mut guard, name.as_bytes());
+        drop(guard);
+        ip = mount::enter(next?.0)?;
+    }
+    if parent {
+        return Err(Errno::ENOENT);
+    }
+    Ok((ip, name))
+}
+
+/// Look up inode of `path`
+pub fn namei(path: &str) -> Result<Arc<Inode>, Errno> {
+    namex(path, false).map(|(ip, _)| ip)
+}
+
+/// Look up inode of parent directory of `path`, and return it
+/// together with the final path element.
+pub fn nameiparent(path: &str) -> Result<(Arc<Inode>, &str), Errno> {
+    namex(path, true)
+}
+
+/// Create an inode of type `typ` at `path`.
+///
+/// If `typ` is `T_FILE` and `path` is an existing file or device, returns the existing one.
+/// Otherwise, returns `ENOENT` if parent directory doesn't exist, `EEXIST` if `path` already exists,
+/// `ENOSPC` if there are no free inodes or blocks on disk, or `ENFILE` if all in-memory
+/// inodes are in use.
+pub fn create(path: &str, typ: u16, major: u16, minor: u16) -> Result<Arc<Inode>, Errno> {
+    let _op = begin_op();
+    let (dp, name) = nameiparent(path)?;
+    let mut dguard = dp.lock();
+    match dirlookup(&mut dguard, name.as_bytes()) {
+        Ok((ip, _)) => {
+            drop(dguard);
+            let existing = ip.lock().typ;
+            if typ == T_FILE && (existing == T_FILE || existing == T_DEVICE) {
+                return Ok(ip);
+            }
+            return Err(Errno::EEXIST);
+        }
+        Err(Errno::ENOENT) => {}
+        Err(err) => return Err(err)
+    }
+    let ip = ialloc(dp.dev, typ)?;
+    let mut guard = ip.lock();
+    guard.major = major;
+    guard.minor = minor;
+    guard.nlink = 1;
+    guard.update();
+    let linked = if typ == T_DIR {
+        dirlink(&mut guard, b".", ip.inum)
+            .and_then(|_| dirlink(&mut guard, b"..", dp.inum))
+    } else {
+        Ok(())
+    }.and_then(|_| dirlink(&mut dguard, name.as_bytes(), ip.inum));
+    if let Err(err) = linked {
+        // inode and its blocks are freed when it is dropped
+        guard.nlink = 0;
+        guard.update();
+        return Err(err);
+    }
+    if typ == T_DIR {
+        // for ".."
+        dguard.nlink += 1;
+        dguard.update();
+    }
+    drop(guard);
+    Ok(ip)
+}
+
+/// Create a new link `new` for the inode at `old`.
+///
+/// Returns `ENOENT` if `old` or parent of `new` doesn't exist, `EPERM` if `old` is a directory,
+/// `EXDEV` if they are on different devices, `EEXIST` if `new` already exists, or `ENOSPC`
+/// if parent of `new` can't grow as disk is full.
+pub fn link(old: &str, new: &str) -> Result<(), Errno> {
+    let _op = begin_op();
+    let ip = namei(old)?;
+    {
+        let mut guard = ip.lock();
+        if guard.typ == T_DIR {
+            return Err(Errno::EPERM);
+        }
+        guard.nlink += 1;
+        guard.update();
+    }
+    let linked = nameiparent(new).and_then(|(dp, name)| {
+        let mut dguard = dp.lock();
+        if dp.dev != ip.dev {
+            Err(Errno::EXDEV)
+        } else {
+            dirlink(&mut dguard, name.as_bytes(), ip.inum)
+        }
+    });
+    if linked.is_err() {
+        let mut guard = ip.lock();
+        guard.nlink -= 1;
+        guard.update();
+    }
+    linked
+}
+
+/// Remove the directory entry at `path`. The inode will be freed after
+/// all its links and references are gone.
+///
+/// Returns `ENOENT` if `path` doesn't exist, `EINVAL` if it ends with `.` or `..`,
+/// `EBUSY` if it is a mount point, or `ENOTEMPTY` if it is a non-empty directory.
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let _op = begin_op();
+    let (dp, name) = nameiparent(path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EINVAL);
+    }
+    let mut dguard = dp.lock();
+    let (ip, off) = dirlookup(&mut dguard, name.as_bytes())?;
+    if mount::is_mount_point(&ip) {
+        return Err(Errno::EBUSY);
+    }
+    let mut guard = ip.lock();
+    if guard.nlink < 1 {
+        panic!("unlink: nlink < 1");
+    }
+    if guard.typ == T_DIR && !is_dir_empty(&mut guard) {
+        return Err(Errno::ENOTEMPTY);
+    }
+    if dguard.write(as_bytes(&Dirent::zero()), off) != Ok(DIRENT_SIZE) {
+        panic!("unlink: write");
+    }
+    if guard.typ == T_DIR {
+        // for ".."
+        dguard.nlink -= 1;
+        dguard.update();
+    }
+    guard.nlink -= 1;
+    guard.update();
+    Ok(())
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("skip elem", test_skip_elem),
+            ("mkdir and unlink", test_mkdir_unlink),
+            ("link", test_link),
+        ]
+    }
+
+    /// Test splitting path
+    pub fn test_skip_elem() {
+        assert_eq!(skip_elem("a/bb/c"), Some(("a", "bb/c")));
+        assert_eq!(skip_elem("///a//bb"), Some(("a", "bb")));
+        assert_eq!(skip_elem("a"), Some(("a", "")));
+        assert_eq!(skip_elem(""), None);
+        assert_eq!(skip_elem("////"), None);
+    }
+
+    /// Test creating and removing directories
+    pub fn test_mkdir_unlink() {
+        assert!(create("/testdir", T_DIR, 0, 0).is_ok());
+        assert_eq!(create("/testdir", T_DIR, 0, 0).err(), Some(Errno::EEXIST));
+        assert!(create("/testdir/a", T_FILE, 0, 0).is_ok());
+        assert!(create("/testdir/a", T_FILE, 0, 0).is_ok());
+        assert_eq!(create("/testdir/a", T_DIR, 0, 0).err(), Some(Errno::EEXIST));
+        assert_eq!(create("/testdir/b/c", T_FILE, 0, 0).err(), Some(Errno::ENOENT));
+        assert!(namei("/testdir/./a").is_ok());
+        assert!(namei("/testdir/../testdir/a").is_ok());
+        assert_eq!(unlink("/testdir"), Err(Errno::ENOTEMPTY));
+        assert_eq!(unlink("/testdir/."), Err(Errno::EINVAL));
+        assert_eq!(unlink("/testdir/a"), Ok(()));
+        assert_eq!(namei("/testdir/a").err(), Some(Errno::ENOENT));
+        assert_eq!(unlink("/testdir/a"), Err(Errno::ENOENT));
+        assert_eq!(unlink("/testdir"), Ok(()));
+        assert_eq!(namei("/testdir").err(), Some(Errno::ENOENT));
+    }
+
+    /// Test creating links
+    pub fn test_link() {
+        assert_eq!(link("/test.txt", "/test.link"), Ok(()));
+        assert_eq!(link("/test.txt", "/test.link"), Err(Errno::EEXIST));
+        assert_eq!(link("/", "/root.link"), Err(Errno::EPERM));
+        assert_eq!(link("/non-existing", "/test.link2"), Err(Errno::ENOENT));
+        {
+            let ip = namei("/test.link").unwrap();
+            assert_eq!(ip.inum, namei("/test.txt").unwrap().inum);
+            assert_eq!(ip.lock().nlink, 2);
+        }
+        assert_eq!(unlink("/test.link"), Ok(()));
+        assert_eq!(namei("/test.txt").unwrap().lock().nlink, 1);
+    }
+}
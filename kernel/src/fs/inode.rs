@@ -0,0 +1,396 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Inodes
+//!
+//! An inode describes a single unnamed file. In-memory inodes are shared
+//! with `Arc`, and a table of weak references makes sure that there is at
+//! most one in-memory copy of each on-disk inode. When the last reference
+//! to an inode without links is dropped, the inode and its content are
+//! freed on disk. Its table entry is kept until then, so that no other copy
+//! is read from disk while it is being freed.
+
+use super::*;
+use crate::spinlock::Mutex;
+use crate::sleeplock::{SleepLock, SleepLockGuard};
+use crate::process::{sleep, wakeup};
+use alloc::sync::{Arc, Weak};
+use core::ops::{Deref, DerefMut};
+
+/// Maximum number of in-memory inodes
+pub const NINODE: usize = 50;
+
+/// In-memory copy of an inode
+pub struct Inode {
+    /// Device number
+    pub dev: u32,
+    /// Inode number
+    pub inum: u32,
+    data: SleepLock<InodeData>,
+}
+
+struct InodeData {
+    /// whether `dinode` has been read from disk
+    valid: bool,
+    dinode: DInode,
+}
+
+/// Inode locked by current process, through which on-disk inode
+/// can be accessed
+pub struct InodeGuard<'a> {
+    pub inode: &'a Inode,
+    data: SleepLockGuard<'a, InodeData>,
+}
+
+/// Entry of in-memory inode table
+struct ITableEntry {
+    dev: u32,
+    inum: u32,
+    ip: Weak<Inode>,
+    /// set when inode has no links. The entry is kept until the inode is freed
+    /// on disk after its last reference is dropped.
+    orphan: bool,
+}
+
+/// Table of in-memory inodes. There is at most one entry for each on-disk inode.
+static ITABLE: Mutex<[Option<ITableEntry>; NINODE]> = Mutex::new([None; NINODE], "itable");
+
+/// Find the inode with number `inum` on device `dev` and return the in-memory copy.
+/// Does not lock the inode and does not read it from disk. If the last copy is
+/// being freed on disk, wait until it is done.
+///
+/// Returns `ENFILE` if all in-memory inodes are in use.
+pub fn iget(dev: u32, inum: u32) -> Result<Arc<Inode>, Errno> {
+    let mut itable = ITABLE.lock();
+    loop {
+        let mut empty = None;
+        let mut freeing = false;
+        for i in 0..NINODE {
+            match &itable[i] {
+                Some(e) if e.dev == dev && e.inum == inum => {
+                    if let Some(ip) = e.ip.upgrade() {
+                        return Ok(ip);
+                    }
+                    // reuse the entry, so that there's only one entry of this inode
+                    freeing = e.orphan;
+                    empty = Some(i);
+                    break;
+                }
+                Some(e) if e.orphan || e.ip.strong_count() != 0 => {}
+                _ => {
+                    if empty.is_none() {
+                        empty = Some(i);
+                    }
+                }
+            }
+        }
+        if freeing {
+            itable = sleep(&ITABLE as *const _, itable);
+            continue;
+        }
+        let i = empty.ok_or(Errno::ENFILE)?;
+        let ip = Arc::new(Inode {
+            dev,
+            inum,
+            data: SleepLock::new(InodeData { valid: false, dinode: DInode::zero() }, "inode"),
+        });
+        itable[i] = Some(ITableEntry { dev, inum, ip: Arc::downgrade(&ip), orphan: false });
+        return Ok(ip);
+    }
+}
+
+/// Check if any in-memory inode on device `dev` is still referenced or being freed
+pub fn busy(dev: u32) -> bool {
+    ITABLE.lock().iter().any(|entry| match entry {
+        Some(e) => e.dev == dev && (e.ip.strong_count() != 0 || e.orphan),
+        None => false
+    })
+}
+
+/// Mark inode `inum` on device `dev` as having no links
+fn mark_orphan(dev: u32, inum: u32) {
+    let mut itable = ITABLE.lock();
+    if let Some(e) = itable.iter_mut().flatten().find(|e| e.dev == dev && e.inum == inum) {
+        e.orphan = true;
+    }
+}
+
+/// Remove the table entry of inode `inum` on device `dev` after it is freed
+/// on disk, and wake up `iget` waiting for it
+fn release_orphan(dev: u32, inum: u32) {
+    let mut itable = ITABLE.lock();
+    if let Some(entry) = itable.iter_mut().find(|entry| match entry {
+        Some(e) => e.dev == dev && e.inum == inum,
+        None => false
+    }) {
+        *entry = None;
+    }
+    wakeup(&ITABLE as *const _);
+}
+
+/// Allocate an inode of type `typ` on device `dev`.
+///
+/// Returns `ENOSPC` if there are no free inodes on disk,
+/// or `ENFILE` if all in-memory inodes are in use.
+pub fn ialloc(dev: u32, typ: u16) -> Result<Arc<Inode>, Errno> {
+    let sb = sb(dev);
+    for inum in 1..sb.ninodes {
+        let mut b = bread(dev, sb.iblock(inum));
+        let offset = inode_offset(inum);
+        let dinode: DInode = read_struct(&b.data, offset);
+        if dinode.typ == 0 {
+            // get in-memory inode first, so that nothing is changed on disk if it fails
+            let ip = iget(dev, inum)?;
+            let mut dinode = DInode::zero();
+            dinode.typ = typ;
+            write_struct(&mut b.data, offset, &dinode);
+            log_write(&b);
+            return Ok(ip);
+        }
+    }
+    Err(Errno::ENOSPC)
+}
+
+/// Offset of inode `inum` in its inode block
+fn inode_offset(inum: u32) -> usize {
+    (inum as usize % IPB) * core::mem::size_of::<DInode>()
+}
+
+impl Inode {
+    /// Lock the inode, and read it from disk if necessary.
+    pub fn lock(&self) -> InodeGuard {
+        let mut data = self.data.lock();
+        if !data.valid {
+            let b = bread(self.dev, sb(self.dev).iblock(self.inum));
+            data.dinode = read_struct(&b.data, inode_offset(self.inum));
+            data.valid = true;
+            if data.dinode.typ == 0 {
+                panic!("inode {} has no type", self.inum);
+            }
+        }
+        InodeGuard { inode: self, data }
+    }
+}
+
+impl Drop for Inode {
+    /// Free the inode on disk if there are no links to it.
+    fn drop(&mut self) {
+        let data = unsafe { self.data.get() };
+        if data.valid && data.dinode.nlink == 0 {
+            let _op = begin_op();
+            let mut ip = self.lock();
+            ip.truncate();
+            ip.typ = 0;
+            ip.update();
+            // Inode is freed in buffer cache, from which another copy can be read.
+            // Release the entry before leaving the operation, as `ialloc` may be
+            // waiting for it while holding the inode block.
+            release_orphan(self.dev, self.inum);
+        }
+    }
+}
+
+impl Deref for InodeGuard<'_> {
+    type Target = DInode;
+    fn deref(&self) -> &DInode { &self.data.dinode }
+}
+
+impl DerefMut for InodeGuard<'_> {
+    fn deref_mut(&mut self) -> &mut DInode { &mut self.data.dinode }
+}
+
+/// Get block address stored in `addr`, allocating one if `alloc` is set
+fn addr_entry(dev: u32, addr: &mut u32, alloc: bool) -> Option<u32> {
+    if *addr == 0 {
+        if !alloc {
+            return None;
+        }
+        *addr = balloc(dev)?;
+    }
+    Some(*addr)
+}
+
+/// Get `idx`th block address stored in indirect block `blockno`, allocating one if `alloc` is set
+fn indirect_entry(dev: u32, blockno: u32, idx: usize, alloc: bool) -> Option<u32> {
+    let mut b = bread(dev, blockno);
+    let offset = idx * core::mem::size_of::<u32>();
+    let addr: u32 = read_struct(&b.data, offset);
+    if addr != 0 {
+        return Some(addr);
+    }
+    if !alloc {
+        return None;
+    }
+    let addr = balloc(dev)?;
+    write_struct(&mut b.data, offset, &addr);
+    log_write(&b);
+    Some(addr)
+}
+
+/// Free block `blockno`, together with all blocks it refers to if it is an indirect block of `level`
+fn free_indirect(dev: u32, blockno: u32, level: usize) {
+    if level > 0 {
+        let b = bread(dev, blockno);
+        for i in 0..NINDIRECT {
+            let addr: u32 = read_struct(&b.data, i * core::mem::size_of::<u32>());
+            if addr != 0 {
+                free_indirect(dev, addr, level - 1);
+            }
+        }
+    }
+    bfree(dev, blockno);
+}
+
+impl InodeGuard<'_> {
+    /// Write inode to disk. Should be called after every change to on-disk inode.
+    pub fn update(&self) {
+        let mut b = bread(self.inode.dev, sb(self.inode.dev).iblock(self.inode.inum));
+        write_struct(&mut b.data, inode_offset(self.inode.inum), &self.data.dinode);
+        log_write(&b);
+        if self.nlink == 0 && self.typ != 0 {
+            // keep the table entry until inode is freed on drop
+            mark_orphan(self.inode.dev, self.inode.inum);
+        }
+    }
+
+    /// Return disk block address of the `bn`th block in inode.
+    ///
+    /// If there is no such block, `bmap` allocates one when `alloc` is set,
+    /// or returns `None` otherwise. Also returns `None` if disk is full.
+    pub fn bmap(&mut self, bn: usize, alloc: bool) -> Option<u32> {
+        let dev = self.inode.dev;
+        if bn < NDIRECT {
+            return addr_entry(dev, &mut self.addrs[bn], alloc);
+        }
+        let bn = bn - NDIRECT;
+        if bn < NINDIRECT {
+            let indirect = addr_entry(dev, &mut self.addrs[NDIRECT], alloc)?;
+            return indirect_entry(dev, indirect, bn, alloc);
+        }
+        let bn = bn - NINDIRECT;
+        if bn < NDINDIRECT {
+            let dindirect = addr_entry(dev, &mut self.addrs[NDIRECT + 1], alloc)?;
+            let indirect = indirect_entry(dev, dindirect, bn / NINDIRECT, alloc)?;
+            return indirect_entry(dev, indirect, bn % NINDIRECT, alloc);
+        }
+        panic!("bmap: out of range");
+    }
+
+    /// Discard content of inode.
+    pub fn truncate(&mut self) {
+        let dev = self.inode.dev;
+        for i in 0..NDIRECT + 2 {
+            if self.addrs[i] != 0 {
+                let level = if i < NDIRECT { 0 } else { i - NDIRECT + 1 };
+                free_indirect(dev, self.addrs[i], level);
+                self.addrs[i] = 0;
+            }
+        }
+        self.size = 0;
+        self.update();
+    }
+
+    /// Read data from inode at offset `off` into `dst`.
+    ///
+    /// Returns number of bytes read, which is less than `dst.len()`
+    /// if end of file is reached.
+    pub fn read(&mut self, dst: &mut [u8], off: usize) -> usize {
+        let size = self.size as usize;
+        if off > size {
+            return 0;
+        }
+        let n = dst.len().min(size - off);
+        let mut tot = 0;
+        while tot < n {
+            let cur = off + tot;
+            let m = (n - tot).min(BSIZE - cur % BSIZE);
+            let begin = cur % BSIZE;
+            match self.bmap(cur / BSIZE, false) {
+                Some(addr) => {
+                    let b = bread(self.inode.dev, addr);
+                    dst[tot..tot + m].copy_from_slice(&b.data[begin..begin + m]);
+                }
+                None => {
+                    for c in &mut dst[tot..tot + m] {
+                        *c = 0;
+                    }
+                }
+            }
+            tot += m;
+        }
+        n
+    }
+
+    /// Write `src` to inode at offset `off`, growing the inode if necessary.
+    ///
+    /// Returns number of bytes written, which is less than `src.len()` if disk
+    /// becomes full. Returns `EINVAL` if `off` is beyond end of file, `EFBIG` if
+    /// the file would be too large, or `ENOSPC` if nothing is written as disk is full.
+    pub fn write(&mut self, src: &[u8], off: usize) -> Result<usize, Errno> {
+        if off > self.size as usize {
+            return Err(Errno::EINVAL);
+        }
+        if off + src.len() > MAXFILE * BSIZE {
+            return Err(Errno::EFBIG);
+        }
+        let dev = self.inode.dev;
+        let n = src.len();
+        let mut tot = 0;
+        while tot < n {
+            let cur = off + tot;
+            let m = (n - tot).min(BSIZE - cur % BSIZE);
+            let begin = cur % BSIZE;
+            let addr = match self.bmap(cur / BSIZE, true) {
+                Some(addr) => addr,
+                None => break
+            };
+            let mut b = bread(dev, addr);
+            b.data[begin..begin + m].copy_from_slice(&src[tot..tot + m]);
+            log_write(&b);
+            tot += m;
+        }
+        if off + tot > self.size as usize {
+            self.size = (off + tot) as u32;
+        }
+        // write back even if size doesn't change, as `bmap` may have changed `addrs`
+        self.update();
+        if tot == 0 && n != 0 {
+            return Err(Errno::ENOSPC);
+        }
+        Ok(tot)
+    }
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("free orphan", test_free_orphan),
+        ]
+    }
+
+    /// Whether the table entry of inode `inum` on device `dev` is an orphan,
+    /// or `None` if there's no entry
+    fn orphan_of(dev: u32, inum: u32) -> Option<bool> {
+        ITABLE.lock().iter().flatten().find(|e| e.dev == dev && e.inum == inum).map(|e| e.orphan)
+    }
+
+    /// Test that an unlinked inode keeps its table entry until it is freed on disk
+    pub fn test_free_orphan() {
+        let ip = create("/test.orphan", T_FILE, 0, 0).unwrap();
+        let inum = ip.inum;
+        assert_eq!(orphan_of(ROOTDEV, inum), Some(false));
+        assert_eq!(unlink("/test.orphan"), Ok(()));
+        assert_eq!(orphan_of(ROOTDEV, inum), Some(true));
+        // still referenced, so the same copy is returned
+        assert!(Arc::ptr_eq(&iget(ROOTDEV, inum).unwrap(), &ip));
+        drop(ip);
+        assert_eq!(orphan_of(ROOTDEV, inum), None);
+        let b = bread(ROOTDEV, sb(ROOTDEV).iblock(inum));
+        let dinode: DInode = read_struct(&b.data, inode_offset(inum));
+        assert_eq!(dinode.typ, 0);
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Write-ahead log
+//!
+//! A system call that modifies the file system wraps its changes in a
+//! transaction with `begin_op`. Instead of writing blocks to disk directly,
+//! file system code calls `log_write`, and the blocks are written when the
+//! transaction commits:
+//! 1. modified blocks are copied from buffer cache to the log region,
+//! 2. the log header, recording home locations of these blocks, is written.
+//!    This is the commit point.
+//! 3. blocks in the log are installed to their home locations,
+//! 4. the log header is cleared.
+//!
+//! If the kernel crashes after the commit point, `recover` replays the log
+//! on next boot, so the transaction is either fully applied or not at all.
+//!
+//! Transactions of concurrent system calls are grouped together, and the
+//! log is committed when the last of them ends.
+//!
+//! Every device with a file system has its own log region, which is
+//! `[ header block | logged blocks ... ]`. A transaction is committed to
+//! each device it modifies separately, so it is atomic on each device.
+
+use super::*;
+use crate::bio::BufGuard;
+use crate::spinlock::Mutex;
+use crate::process::{sleep, wakeup, my_proc};
+use crate::virtio::disk;
+use crate::error::Errno;
+use crate::{info, panic};
+
+/// Maximum number of blocks written by one file system operation
+pub const MAXOPBLOCKS: usize = 10;
+
+/// Log region of a device
+#[derive(Clone, Copy)]
+struct DevLog {
+    /// block number of log header
+    start: u32,
+    /// maximum number of blocks in a transaction
+    capacity: usize,
+    lh: LogHeader,
+}
+
+struct Log {
+    /// logs of devices with a file system in use, indexed by device number
+    devs: [Option<DevLog>; NDEV],
+    /// number of file system operations in progress
+    outstanding: usize,
+    /// whether the log is being committed
+    committing: bool,
+}
+
+impl Log {
+    /// Check if one more operation might exhaust space of any log
+    fn full(&self) -> bool {
+        self.devs.iter().flatten().any(|dl| {
+            dl.lh.n as usize + (self.outstanding + 1) * MAXOPBLOCKS > dl.capacity
+        })
+    }
+}
+
+static LOG: Mutex<Log> = Mutex::new(Log {
+    devs: [None; NDEV],
+    outstanding: 0,
+    committing: false,
+}, "log");
+
+/// Set up log of `dev` with super block `sb`.
+///
+/// The log should have been recovered with `recover`.
+pub fn init(dev: u32, sb: &SuperBlock) {
+    LOG.lock().devs[dev as usize] = Some(DevLog {
+        start: sb.logstart,
+        capacity: LOGSIZE.min(sb.nlog as usize - 1),
+        lh: LogHeader::zero(),
+    });
+}
+
+/// Stop logging `dev`, after its changes in current transaction are committed.
+///
+/// No file on `dev` should be in use, so that no more changes are made to it.
+pub fn remove(dev: u32) {
+    let mut log = LOG.lock();
+    while log.committing || log.devs[dev as usize].map_or(false, |dl| dl.lh.n != 0) {
+        log = sleep(&LOG as *const _, log);
+    }
+    log.devs[dev as usize] = None;
+}
+
+/// Replay committed transactions in the log of `dev`.
+/// Returns `ENXIO` if there's no such device, or `EINVAL` if there's no file system on it.
+///
+/// This function should be called before the file system is used, i.e. at boot
+/// for root device, or before it is mounted. It reads and writes disk by polling,
+/// without buffer cache.
+pub unsafe fn recover(dev: u32) -> Result<(), Errno> {
+    let virtio = disk(dev).ok_or(Errno::ENXIO)?;
+    let mut buf = box [0; BSIZE];
+    virtio.read_poll(1, &mut buf);
+    let sb: SuperBlock = read_struct(&buf, 0);
+    if sb.magic != FSMAGIC {
+        return Err(Errno::EINVAL);
+    }
+    virtio.read_poll(sb.logstart, &mut buf);
+    let lh: LogHeader = read_struct(&buf, 0);
+    for i in 0..lh.n {
+        virtio.read_poll(sb.logstart + 1 + i, &mut buf);
+        virtio.write_poll(lh.block[i as usize], &buf);
+    }
+    if lh.n != 0 {
+        let mut buf = box [0; BSIZE];
+        write_struct(&mut buf, 0, &LogHeader::zero());
+        virtio.write_poll(sb.logstart, &buf);
+        info!("log: recovered {} blocks on device {}", lh.n, dev);
+    }
+    Ok(())
+}
+
+/// A file system operation in progress. The operation ends when it is dropped.
+pub struct LogOp(());
+
+/// Begin a file system operation, sleep until there is enough space in log.
+///
+/// Operations may be nested in one process, e.g. freeing an inode while
+/// unlinking it. Nested operations are part of the outermost one.
+pub fn begin_op() -> LogOp {
+    let p = my_proc();
+    if p.log_depth == 0 {
+        let mut log = LOG.lock();
+        loop {
+            if log.committing {
+                log = sleep(&LOG as *const _, log);
+            } else if log.full() {
+                // this operation might exhaust log space, wait for commit
+                log = sleep(&LOG as *const _, log);
+            } else {
+                log.outstanding += 1;
+                break;
+            }
+        }
+    }
+    p.log_depth += 1;
+    LogOp(())
+}
+
+impl Drop for LogOp {
+    /// End a file system operation, and commit if this was the last outstanding one.
+    fn drop(&mut self) {
+        let p = my_proc();
+        p.log_depth -= 1;
+        if p.log_depth != 0 {
+            return;
+        }
+        let do_commit = {
+            let mut log = LOG.lock();
+            log.outstanding -= 1;
+            if log.committing {
+                panic!("log committing");
+            }
+            if log.outstanding == 0 {
+                log.committing = true;
+                true
+            } else {
+                // begin_op may be waiting for log space,
+                // and decrementing outstanding has decreased
+                // the amount of reserved space.
+                wakeup(&LOG as *const _);
+                false
+            }
+        };
+        if do_commit {
+            // commit without holding lock, since sleeping with lock is not allowed
+            commit();
+            LOG.lock().committing = false;
+            wakeup(&LOG as *const _);
+        }
+    }
+}
+
+/// Record that buffer `b` has been modified in current transaction,
+/// and pin it in cache until it is installed to disk.
+/// Replaces `b.write()` in file system code.
+pub fn log_write(b: &BufGuard) {
+    let mut log = LOG.lock();
+    if log.outstanding < 1 {
+        panic!("log_write outside of trans");
+    }
+    let dl = match log.devs.get_mut(b.dev as usize) {
+        Some(Some(dl)) => dl,
+        _ => panic!("log_write to device {}", b.dev)
+    };
+    if dl.lh.n as usize >= dl.capacity {
+        panic!("too big a transaction");
+    }
+    let n = dl.lh.n as usize;
+    // log absorption: a block written several times is logged only once
+    if dl.lh.block[0..n].contains(&b.blockno) {
+        return;
+    }
+    dl.lh.block[n] = b.blockno;
+    dl.lh.n += 1;
+    b.pin();
+}
+
+/// Copy modified blocks from cache to log
+fn write_log(dev: u32, start: u32, lh: &LogHeader) {
+    for i in 0..lh.n {
+        let mut to = bread(dev, start + 1 + i);
+        let from = bread(dev, lh.block[i as usize]);
+        to.data = from.data;
+        to.write();
+    }
+}
+
+/// Write log header to disk
+fn write_head(dev: u32, start: u32, lh: &LogHeader) {
+    let mut b = bread(dev, start);
+    write_struct(&mut b.data, 0, lh);
+    b.write();
+}
+
+/// Copy committed blocks from log to their home locations, and unpin them
+fn install_trans(dev: u32, start: u32, lh: &LogHeader) {
+    for i in 0..lh.n {
+        let from = bread(dev, start + 1 + i);
+        let mut to = bread(dev, lh.block[i as usize]);
+        to.data = from.data;
+        to.write();
+        to.unpin();
+    }
+}
+
+/// Commit current transaction to every device. No operation may be in progress.
+fn commit() {
+    for dev in 0..NDEV {
+        let (start, lh) = match LOG.lock().devs[dev] {
+            Some(dl) if dl.lh.n > 0 => (dl.start, dl.lh),
+            _ => continue
+        };
+        let dev = dev as u32;
+        write_log(dev, start, &lh);
+        write_head(dev, start, &lh);
+        install_trans(dev, start, &lh);
+        if let Some(dl) = &mut LOG.lock().devs[dev as usize] {
+            dl.lh.n = 0;
+        }
+        write_head(dev, start, &LogHeader::zero());
+    }
+}
+
+pub mod tests {
+    use super::*;
+    use crate::file::{FsFile, O_CREATE, O_RDWR};
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("recover after crash", test_recover),
+        ]
+    }
+
+    /// Test replaying the log after a simulated crash.
+    ///
+    /// A crash after the commit point should be redone by recovery,
+    /// while a crash before the commit point should leave no effect.
+    pub fn test_recover() {
+        let dev = ROOTDEV;
+        let start = LOG.lock().devs[dev as usize].unwrap().start;
+        let addr = {
+            let f = FsFile::open("/test.crash", O_CREATE | O_RDWR).unwrap();
+            assert_eq!(f.write(&[b'a'; BSIZE]), Ok(BSIZE));
+            let ip = namei("/test.crash").unwrap();
+            let mut guard = ip.lock();
+            guard.bmap(0, false).unwrap()
+        };
+        let virtio = disk(dev).unwrap();
+        let mut buf = box [0; BSIZE];
+        let mut lh = LogHeader::zero();
+        lh.n = 1;
+        lh.block[0] = addr;
+
+        // crash after commit point: logged block and header are on disk
+        virtio.write(start + 1, &[b'b'; BSIZE]);
+        write_struct(&mut buf, 0, &lh);
+        virtio.write(start, &buf);
+        unsafe { recover(dev).unwrap(); }
+        virtio.read(addr, &mut buf);
+        assert_eq!(&buf[..], &[b'b'; BSIZE][..]);
+        virtio.read(start, &mut buf);
+        assert_eq!(read_struct::<LogHeader>(&buf, 0).n, 0);
+
+        // crash before commit point: logged block is on disk, but header is not
+        virtio.write(start + 1, &[b'c'; BSIZE]);
+        unsafe { recover(dev).unwrap(); }
+        virtio.read(addr, &mut buf);
+        assert_eq!(&buf[..], &[b'b'; BSIZE][..]);
+
+        // cached copy of the block is stale, and is discarded together with the file
+        assert_eq!(unlink("/test.crash"), Ok(()));
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Mount table
+//!
+//! The file system on a disk other than root device can be mounted on a
+//! directory, which is called the mount point. While it is mounted, path
+//! lookup goes from the mount point to root directory of the mounted file
+//! system, and `..` in that root directory goes back to parent of the mount
+//! point. The mount point is kept in memory until the file system is unmounted.
+
+use super::*;
+use crate::spinlock::Mutex;
+use crate::sleeplock::SleepLock;
+use crate::virtio::disk;
+use crate::bio;
+use crate::error::Errno;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A mounted file system
+struct Mount {
+    /// device number of file system
+    dev: u32,
+    /// directory on which file system is mounted
+    point: Arc<Inode>,
+}
+
+/// Mounted file systems other than root
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new(), "mounts");
+
+/// Serializes `mount` and `umount`, which sleep while holding no other locks
+static MOUNT_LOCK: SleepLock<()> = SleepLock::new((), "mount");
+
+/// If `ip` is a mount point, return root directory of the file system
+/// mounted on it. Otherwise, return `ip` itself.
+///
+/// Returns `ENFILE` if all in-memory inodes are in use.
+pub fn enter(ip: Arc<Inode>) -> Result<Arc<Inode>, Errno> {
+    let mounts = MOUNTS.lock();
+    match mounts.iter().find(|m| Arc::ptr_eq(&m.point, &ip)) {
+        // look up root while holding the table, so that `umount` sees it in use
+        Some(m) => iget(m.dev, ROOTINO),
+        None => Ok(ip)
+    }
+}
+
+/// If `ip` is root directory of a mounted file system, return its mount point.
+/// Otherwise, return `ip` itself.
+pub fn leave(ip: Arc<Inode>) -> Arc<Inode> {
+    if ip.inum != ROOTINO {
+        return ip;
+    }
+    let mounts = MOUNTS.lock();
+    match mounts.iter().find(|m| m.dev == ip.dev) {
+        Some(m) => m.point.clone(),
+        None => ip
+    }
+}
+
+/// Check if `ip` is a mount point
+pub fn is_mount_point(ip: &Arc<Inode>) -> bool {
+    MOUNTS.lock().iter().any(|m| Arc::ptr_eq(&m.point, ip))
+}
+
+/// Mount file system on device `dev` at directory `path`.
+///
+/// Returns `ENXIO` if there's no such device, `EBUSY` if it is already mounted,
+/// `ENOENT` if `path` doesn't exist, `ENOTDIR` if it is not a directory,
+/// `EBUSY` if it is root directory or a mount point, or `EINVAL` if there's no
+/// file system on `dev`.
+pub fn mount(dev: u32, path: &str) -> Result<(), Errno> {
+    let _guard = MOUNT_LOCK.lock();
+    if dev as usize >= NDEV || disk(dev).is_none() {
+        return Err(Errno::ENXIO);
+    }
+    if dev == ROOTDEV || MOUNTS.lock().iter().any(|m| m.dev == dev) {
+        return Err(Errno::EBUSY);
+    }
+    let ip = namei(path)?;
+    if ip.lock().typ != T_DIR {
+        return Err(Errno::ENOTDIR);
+    }
+    // path lookup has entered root of the file system if `path` is a mount point
+    if ip.inum == ROOTINO {
+        return Err(Errno::EBUSY);
+    }
+    unsafe { recover(dev)?; }
+    init(dev)?;
+    MOUNTS.lock().push(Mount { dev, point: ip });
+    Ok(())
+}
+
+/// Unmount the file system mounted at `path`.
+///
+/// Returns `ENOENT` if `path` doesn't exist, `EINVAL` if it is not a mount point,
+/// or `EBUSY` if files on the file system are still in use, e.g. opened or used
+/// as working directory. Cached blocks of the device are dropped after its log is drained.
+pub fn umount(path: &str) -> Result<(), Errno> {
+    let _guard = MOUNT_LOCK.lock();
+    let dev = {
+        let ip = namei(path)?;
+        if ip.inum != ROOTINO || ip.dev == ROOTDEV {
+            return Err(Errno::EINVAL);
+        }
+        ip.dev
+    };
+    let m = {
+        let mut mounts = MOUNTS.lock();
+        let i = mounts.iter().position(|m| m.dev == dev).ok_or(Errno::EINVAL)?;
+        if busy(dev) {
+            return Err(Errno::EBUSY);
+        }
+        mounts.remove(i)
+    };
+    // mount point is released without holding the table,
+    // as dropping an inode may write to disk
+    drop(m);
+    log::remove(dev);
+    // the disk may be changed before it is mounted again
+    bio::invalidate(dev);
+    Ok(())
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("invalid mount", test_invalid_mount),
+            ("mount data disk", test_mount),
+        ]
+    }
+
+    /// Test that mounting and unmounting fail without changing mount table
+    pub fn test_invalid_mount() {
+        assert_eq!(mount(ROOTDEV, "/"), Err(Errno::EBUSY));
+        assert_eq!(mount(NDEV as u32, "/"), Err(Errno::ENXIO));
+        assert_eq!(mount(0, "/"), Err(Errno::ENXIO));
+        assert_eq!(umount("/"), Err(Errno::EINVAL));
+        assert_eq!(umount("/test.txt"), Err(Errno::EINVAL));
+        assert_eq!(umount("/non-existing"), Err(Errno::ENOENT));
+        if disk(ROOTDEV + 1).is_some() {
+            assert_eq!(mount(ROOTDEV + 1, "/test.txt"), Err(Errno::ENOTDIR));
+            assert_eq!(mount(ROOTDEV + 1, "/"), Err(Errno::EBUSY));
+        }
+        assert!(MOUNTS.lock().is_empty());
+    }
+
+    /// Test mounting the data disk, which has `test.txt`, crossing the mount point,
+    /// and mounting it again after the disk is changed
+    pub fn test_mount() {
+        use crate::file::FsFile;
+        let dev = ROOTDEV + 1;
+        if disk(dev).is_none() {
+            return;
+        }
+        assert!(create("/test.mnt", T_DIR, 0, 0).is_ok());
+        let point = namei("/test.mnt").unwrap().inum;
+        assert_eq!(mount(dev, "/test.mnt"), Ok(()));
+        assert_eq!(mount(dev, "/test.mnt"), Err(Errno::EBUSY));
+        {
+            let ip = namei("/test.mnt").unwrap();
+            assert_eq!((ip.dev, ip.inum), (dev, ROOTINO));
+            let ip = namei("/test.mnt/.").unwrap();
+            assert_eq!((ip.dev, ip.inum), (dev, ROOTINO));
+            let ip = namei("/test.mnt/..").unwrap();
+            assert_eq!((ip.dev, ip.inum), (ROOTDEV, ROOTINO));
+            let ip = namei("/test.mnt/../test.mnt/test.txt").unwrap();
+            assert_eq!(ip.dev, dev);
+        }
+        let blockno = namei("/test.mnt/test.txt").unwrap().lock().addrs[0];
+        {
+            let f = FsFile::open("/test.mnt/test.txt", 0).unwrap();
+            let mut content = [0; 10];
+            assert_eq!(f.read(&mut content), Ok(10));
+            assert_eq!(&content, b"0123456789");
+            assert_eq!(unlink("/test.mnt"), Err(Errno::EBUSY));
+            assert_eq!(umount("/test.mnt"), Err(Errno::EBUSY));
+        }
+        assert_eq!(umount("/test.mnt"), Ok(()));
+        assert_eq!(umount("/test.mnt"), Err(Errno::EINVAL));
+        {
+            let ip = namei("/test.mnt").unwrap();
+            assert_eq!((ip.dev, ip.inum), (ROOTDEV, point));
+            assert_eq!(namei("/test.mnt/test.txt").err(), Some(Errno::ENOENT));
+        }
+        // cached blocks are dropped, so a change made while unmounted is seen
+        let blk = disk(dev).unwrap();
+        let mut data = [0; BSIZE];
+        blk.read_poll(blockno, &mut data);
+        let orig = data;
+        data[..10].copy_from_slice(b"9876543210");
+        blk.write_poll(blockno, &data);
+        assert_eq!(mount(dev, "/test.mnt"), Ok(()));
+        {
+            let f = FsFile::open("/test.mnt/test.txt", 0).unwrap();
+            let mut content = [0; 10];
+            assert_eq!(f.read(&mut content), Ok(10));
+            assert_eq!(&content, b"9876543210");
+        }
+        assert_eq!(umount("/test.mnt"), Ok(()));
+        blk.write_poll(blockno, &orig);
+        assert_eq!(unlink("/test.mnt"), Ok(()));
+        assert!(MOUNTS.lock().is_empty());
+    }
+}
@@ -9,7 +9,8 @@ use riscv::register::*;
 use crate::plic;
 use crate::uart::uartintr;
 use crate::arch;
-use crate::virtio::virtiointr;
+use crate::virtio;
+use crate::fdt::machine;
 use crate::println;
 
 #[derive(PartialEq)]
@@ -25,16 +26,10 @@ pub fn devintr() -> Option<Intr> {
     if cause.is_interrupt() && cause.code() == 9 {
         let plic = crate::plic::PLIC();
         if let Some(interrupt) = plic.next() {
-            match interrupt {
-                plic::UART0_IRQ => {
-                    uartintr();
-                },
-                plic::VIRTIO0_IRQ => {
-                    virtiointr();
-                },
-                _ => {
-                    println!("Unrecognized external interrupt: {}", interrupt);
-                }
+            if interrupt == machine().uart.irq {
+                uartintr();
+            } else if !virtio::intr(interrupt) {
+                println!("Unrecognized external interrupt: {}", interrupt);
             }
             plic.complete(interrupt);
         }
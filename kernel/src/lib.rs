@@ -39,10 +39,16 @@ mod syscall;
 mod start;
 mod jump;
 mod virtio;
+mod bio;
 mod intr;
 mod test;
 mod sleeplock;
 mod file;
+mod fs;
+mod error;
+mod fdt;
+mod signal;
+mod slab;
 
 #[no_mangle]
 extern "C" fn eh_personality() {}
@@ -6,32 +6,69 @@
 //! Allocator implementation
 
 use core::ops::Range;
+use core::mem::size_of;
 use crate::info;
 use crate::{println, panic};
 use crate::symbols::*;
 use crate::spinlock::Mutex;
 use crate::page::EntryAttributes;
 use crate::page::{Table, KERNEL_PGTABLE};
-use crate::uart::UART_BASE_ADDR;
+use crate::fdt::machine;
 use crate::process::*;
 use riscv::{register::*, asm};
 use crate::mem;
 use crate::arch;
 
 
-/// Maximum number of pages. As QEMU and linker script `kernel.ld`
-/// are set to have 128MB of RAM, maximum number of pages can be calculated.
-pub const MAX_PAGE: usize = 128 * 1024 * 1024 / (1 << 12);
+/// Largest order of blocks. A block of order `n` has `2^n` pages, so blocks are at most 8 MiB.
+pub const MAX_ORDER: usize = 11;
 
-/// Frame allocator gives out one or more pages.
+/// Metadata of a frame, which is only meaningful for the first frame of a block
+#[derive(Clone, Copy)]
+struct Frame {
+    /// Reference count of an allocated block. Pages shared by copy-on-write
+    /// page tables have more than one reference, and are only freed after
+    /// the last reference is dropped.
+    refs: u32,
+    /// Order of the block
+    order: u8,
+    /// Whether the block is in a free list
+    free: bool,
+}
+
+/// Links of free list, which are stored in the first frame of every free block
+struct FreeBlock {
+    prev: *mut FreeBlock,
+    next: *mut FreeBlock,
+}
+
+/// Buddy allocator for physical frames.
+///
+/// Memory is managed in blocks of `2^order` pages. A block is split in halves,
+/// which are buddies of each other, to allocate a smaller block, and buddies
+/// are merged when both of them are free. Allocation and free take `O(MAX_ORDER)`.
+///
+/// Metadata of frames is placed at the start of managed memory, so the number
+/// of frames is only limited by memory size. Frames are numbered from an address
+/// aligned to the largest block, so every block is aligned to its size.
 pub struct Allocator {
-    /// A bool array records whether a page is handed out
-    pub page_allocated: [usize; MAX_PAGE],
-    /// Pages are handed out from `base_addr`, which is the start address
-    /// of HEAP.
+    /// Free list of every order
+    free_lists: [*mut FreeBlock; MAX_ORDER + 1],
+    /// Number of free blocks of every order
+    free_count: [usize; MAX_ORDER + 1],
+    /// Metadata of every frame
+    frames: *mut Frame,
+    /// Number of frames
+    nframes: usize,
+    /// First frame which is managed. Frames before it are never free.
+    first: usize,
+    /// Address of frame 0, which is aligned to the largest block
     pub base_addr: usize,
 }
 
+// frames and free blocks are only accessed with allocator locked
+unsafe impl Send for Allocator {}
+
 /// Align an address to upper bound according to specified order.
 pub const fn align_val(val: usize, order: usize) -> usize {
     let o = (1usize << order) - 1;
@@ -48,79 +85,201 @@ pub const fn page_down(val: usize) -> usize {
     align_val_down(val, PAGE_ORDER)
 }
 
+/// Smallest order of blocks which hold `size` bytes
+fn order_of(size: usize) -> usize {
+    let pages = align_val(size.max(1), PAGE_ORDER) / PAGE_SIZE;
+    pages.next_power_of_two().trailing_zeros() as usize
+}
+
 impl Allocator {
     /// Returns a new allocator instance
-    /// 
-    /// `base_addr` should be intialized later.
+    ///
+    /// Memory should be given later in `init`.
     pub const fn new() -> Self {
         Allocator {
+            free_lists: [core::ptr::null_mut(); MAX_ORDER + 1],
+            free_count: [0; MAX_ORDER + 1],
+            frames: core::ptr::null_mut(),
+            nframes: 0,
+            first: 0,
             base_addr: 0,
-            page_allocated: [0; MAX_PAGE],
         }
     }
 
-    fn offset_addr_of(&self, id: usize) -> usize {
-        let addr = self.base_addr + id * PAGE_SIZE;
-        addr
+    /// Manage memory in `[start, end)`, all of which is free.
+    ///
+    /// # Safety
+    ///
+    /// The memory should be unused, and stay valid while allocator is in use.
+    pub unsafe fn init(&mut self, start: usize, end: usize) {
+        let start = align_val(start, PAGE_ORDER);
+        let end = align_val_down(end, PAGE_ORDER);
+        let base_addr = align_val_down(start, PAGE_ORDER + MAX_ORDER);
+        let total = (end - base_addr) / PAGE_SIZE;
+        let meta_pages = align_val(total * size_of::<Frame>(), PAGE_ORDER) / PAGE_SIZE;
+        *self = Allocator::new();
+        self.frames = start as *mut Frame;
+        self.nframes = total;
+        self.first = (start - base_addr) / PAGE_SIZE + meta_pages;
+        self.base_addr = base_addr;
+        for i in 0..self.nframes {
+            *self.frames.add(i) = Frame { refs: 0, order: 0, free: false };
+        }
+        // free memory in largest blocks aligned to their size
+        let mut id = self.first;
+        while id < self.nframes {
+            let mut order = MAX_ORDER;
+            while id % (1 << order) != 0 || id + (1 << order) > self.nframes {
+                order -= 1;
+            }
+            self.push_free(id, order);
+            id += 1 << order;
+        }
     }
 
-    unsafe fn offset_id_of(&self, id: usize) -> *mut u8 {
-        self.offset_addr_of(id) as *mut u8
+    fn frame(&self, id: usize) -> &Frame {
+        unsafe { &*self.frames.add(id) }
+    }
+
+    fn frame_mut(&mut self, id: usize) -> &mut Frame {
+        unsafe { &mut *self.frames.add(id) }
+    }
+
+    fn offset_addr_of(&self, id: usize) -> usize {
+        self.base_addr + id * PAGE_SIZE
     }
 
     fn offset_page_of(&self, page: *mut u8) -> usize {
-        let id = (page as usize - self.base_addr) / PAGE_SIZE;
-        id
+        let addr = page as usize;
+        if addr < self.offset_addr_of(self.first) || addr >= self.offset_addr_of(self.nframes) {
+            panic!("page {:x} not managed by allocator", addr);
+        }
+        (addr - self.base_addr) / PAGE_SIZE
     }
 
-    pub fn allocate(&mut self, size: usize) -> *mut u8 {
-        let page_required = align_val(size, PAGE_ORDER) / PAGE_SIZE;
-        for i in 0..MAX_PAGE {
-            if self.page_allocated[i] == 0 {
-                let mut found = true;
-                for j in 0..page_required {
-                    if self.page_allocated[i + j] != 0 {
-                        found = false;
-                        break;
-                    }
-                }
-                if found {
-                    for j in 0..page_required {
-                        self.page_allocated[i + j] = page_required;
-                    }
-                    unsafe { return self.offset_id_of(i); }
-                }
+    /// Add block `id` to free list of `order`
+    fn push_free(&mut self, id: usize, order: usize) {
+        let block = self.offset_addr_of(id) as *mut FreeBlock;
+        let head = self.free_lists[order];
+        unsafe {
+            (*block).prev = core::ptr::null_mut();
+            (*block).next = head;
+            if !head.is_null() {
+                (*head).prev = block;
             }
         }
-        panic!("no available page")
+        self.free_lists[order] = block;
+        self.free_count[order] += 1;
+        *self.frame_mut(id) = Frame { refs: 0, order: order as u8, free: true };
     }
 
-    pub fn deallocate(&mut self, addr: *mut u8) {
-        let id = self.offset_page_of(addr);
-        let page_stride = self.page_allocated[id];
-        for j in 0..page_stride {
-            self.page_allocated[j + id] = 0;
+    /// Remove free block `id` of `order` from its free list
+    fn remove_free(&mut self, id: usize, order: usize) {
+        let block = self.offset_addr_of(id) as *mut FreeBlock;
+        unsafe {
+            let (prev, next) = ((*block).prev, (*block).next);
+            if prev.is_null() {
+                self.free_lists[order] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
         }
+        self.free_count[order] -= 1;
+        self.frame_mut(id).free = false;
     }
 
-    /// Print page allocation status
-    pub fn debug(&self) {
-        let mut j = 0;
-        loop {
-            let size = self.page_allocated[j];
-            let addr = &self.page_allocated as *const usize;
-            let addr = unsafe { addr.add(j) };
-            if size != 0 {
-                let from = self.offset_addr_of(j);
-                let to = self.offset_addr_of(j + size);
-                println!("{} {:X} {:X}-{:X} (pages: {:X})", j, addr as usize, from, to, size);
-                j += size;
-            } else {
-                j += 1;
+    /// Allocate a block of pages which holds `size` bytes. Its reference count is 1.
+    pub fn allocate(&mut self, size: usize) -> *mut u8 {
+        self.allocate_aligned(size, PAGE_SIZE)
+    }
+
+    /// Allocate a block of pages which holds `size` bytes, whose address
+    /// is a multiple of `align`. Its reference count is 1.
+    pub fn allocate_aligned(&mut self, size: usize, align: usize) -> *mut u8 {
+        // blocks are aligned to their size
+        let order = order_of(size.max(align));
+        if order > MAX_ORDER {
+            panic!("allocation of {} bytes too large", size);
+        }
+        let mut cur = match (order..=MAX_ORDER).find(|o| !self.free_lists[*o].is_null()) {
+            Some(cur) => cur,
+            None => panic!("no available page")
+        };
+        let id = self.offset_page_of(self.free_lists[cur] as *mut u8);
+        self.remove_free(id, cur);
+        // give back upper halves
+        while cur > order {
+            cur -= 1;
+            self.push_free(id + (1 << cur), cur);
+        }
+        *self.frame_mut(id) = Frame { refs: 1, order: order as u8, free: false };
+        self.offset_addr_of(id) as *mut u8
+    }
+
+    /// Drop a reference to allocation at `addr`, and free it if this is the last one.
+    /// Freed block is merged with its buddy if the buddy is free.
+    pub fn deallocate(&mut self, addr: *mut u8) {
+        let mut id = self.offset_page_of(addr);
+        let frame = self.frame_mut(id);
+        if frame.free || frame.refs == 0 {
+            panic!("deallocate: page {:x} not allocated", addr as usize);
+        }
+        if frame.refs > 1 {
+            frame.refs -= 1;
+            return;
+        }
+        frame.refs = 0;
+        let mut order = frame.order as usize;
+        while order < MAX_ORDER {
+            let buddy = id ^ (1 << order);
+            if buddy >= self.nframes {
+                break;
             }
-            if j == MAX_PAGE {
+            let buddy_frame = self.frame(buddy);
+            if !buddy_frame.free || buddy_frame.order as usize != order {
                 break;
             }
+            self.remove_free(buddy, order);
+            id = id.min(buddy);
+            order += 1;
+        }
+        self.push_free(id, order);
+    }
+
+    /// Add a reference to allocation at `addr`, which is then freed after
+    /// one more `deallocate`.
+    pub fn share(&mut self, addr: *mut u8) {
+        let id = self.offset_page_of(addr);
+        let frame = self.frame_mut(id);
+        if frame.free || frame.refs == 0 {
+            panic!("share: page {:x} not allocated", addr as usize);
+        }
+        frame.refs += 1;
+    }
+
+    /// Number of references to allocation at `addr`
+    pub fn ref_count(&self, addr: *mut u8) -> usize {
+        self.frame(self.offset_page_of(addr)).refs as usize
+    }
+
+    /// Number of free blocks of every order
+    pub fn free_blocks(&self) -> [usize; MAX_ORDER + 1] {
+        self.free_count
+    }
+
+    /// Number of free pages
+    pub fn free_pages(&self) -> usize {
+        self.free_count.iter().enumerate().map(|(order, cnt)| cnt << order).sum()
+    }
+
+    /// Print number of free blocks of every order
+    pub fn debug(&self) {
+        println!("{} of {} pages free", self.free_pages(), self.nframes - self.first);
+        for (order, cnt) in self.free_count.iter().enumerate() {
+            println!("order {:2}: {} free", order, cnt);
         }
     }
 }
@@ -131,14 +290,14 @@ static __ALLOC: Mutex<Allocator> = Mutex::new(Allocator::new(), "global allocato
 /// Initialize allocator and kernel page table
 /// This function should only be called in boot hart
 pub unsafe fn init() {
-    // Initialize allocator
-    ALLOC().get().base_addr = align_val(HEAP_START(), PAGE_ORDER);
-
-    // workaround for non-zero data region
-    let mut alloc = ALLOC().get();
-    for i in 0..MAX_PAGE {
-        alloc.page_allocated[i] = 0;
-    }
+    // Initialize allocator with all memory after kernel, whose end is
+    // given by device tree
+    let machine = machine();
+    let heap_end = match machine.memory_end(HEAP_START()) {
+        Some(end) => end,
+        None => panic!("kernel at {:x} not in memory", HEAP_START())
+    };
+    ALLOC().get().init(HEAP_START(), heap_end);
 
     let pgtable: &mut Table = &mut *(&KERNEL_PGTABLE as *const _ as *mut _); // to bypass mut ref
     pgtable.id_map_range(
@@ -166,16 +325,10 @@ pub unsafe fn init() {
         KERNEL_STACK_END(),
         EntryAttributes::RW as usize,
     );
-    pgtable.kernel_map(
-        UART_BASE_ADDR,
-        UART_BASE_ADDR,
-        EntryAttributes::RW as usize,
-    );
-    pgtable.kernel_map(
-        VIRTIO_MMIO_BASE,
-        VIRTIO_MMIO_BASE,
-        EntryAttributes::RW as usize,
-    );
+    // UART and virtio devices
+    for dev in core::iter::once(&machine.uart).chain(machine.virtio()) {
+        pgtable.id_map_range(dev.base, dev.base + dev.size, EntryAttributes::RW as usize);
+    }
     pgtable.kernel_map(
         TRAMPOLINE_START,
         TRAMPOLINE_TEXT_START(),
@@ -183,13 +336,13 @@ pub unsafe fn init() {
     );
     pgtable.id_map_range(
         HEAP_START(),
-        HEAP_START() + HEAP_SIZE(),
+        heap_end,
         EntryAttributes::RW as usize,
     );
     // CLINT
-    pgtable.id_map_range(CLINT_BASE, CLINT_BASE + 0x10000, EntryAttributes::RW as usize);
+    pgtable.id_map_range(machine.clint.base, machine.clint.base + machine.clint.size, EntryAttributes::RW as usize);
     // PLIC
-    pgtable.id_map_range(PLIC_BASE, PLIC_BASE + 0x400000, EntryAttributes::RW as usize);
+    pgtable.id_map_range(machine.plic.base, machine.plic.base + machine.plic.size, EntryAttributes::RW as usize);
 }
 
 pub fn hartinit() {
@@ -205,21 +358,21 @@ pub fn hartinit() {
 pub fn ALLOC() -> &'static Mutex<Allocator> { &__ALLOC }
 
 use core::alloc::{GlobalAlloc, Layout};
-use crate::plic::PLIC_BASE;
-use crate::clint::CLINT_BASE;
 use crate::arch::hart_id;
 use crate::process::my_cpu;
-use crate::virtio::VIRTIO_MMIO_BASE;
+use crate::slab;
 
+/// Kernel heap. Small objects are allocated by slab allocator, and others
+/// by frame allocator.
 struct OsAllocator {}
 
 unsafe impl GlobalAlloc for OsAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        ALLOC().lock().allocate(layout.size())
+        slab::alloc(layout)
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        ALLOC().lock().deallocate(ptr);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        slab::dealloc(ptr, layout)
     }
 }
 
@@ -262,3 +415,96 @@ pub fn debug() {
 pub fn alloc_stack() -> *mut u8 {
     ALLOC().lock().allocate(PAGE_SIZE * 1024)
 }
+
+pub mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("split and merge", test_split_merge),
+            ("allocate all", test_allocate_all),
+            ("ref count", test_ref_count),
+            ("alignment", test_alignment),
+        ]
+    }
+
+    const TEST_PAGES: usize = 64;
+
+    /// Run `f` with an allocator managing `TEST_PAGES` pages taken from global allocator
+    fn with_allocator(f: fn(&mut Allocator)) {
+        let mem = ALLOC().lock().allocate(TEST_PAGES * PAGE_SIZE);
+        let mut alloc = Allocator::new();
+        unsafe { alloc.init(mem as usize, mem as usize + TEST_PAGES * PAGE_SIZE); }
+        f(&mut alloc);
+        ALLOC().lock().deallocate(mem);
+    }
+
+    /// Test that blocks are split for small allocations, and merged when freed
+    pub fn test_split_merge() {
+        with_allocator(|alloc| {
+            let blocks = alloc.free_blocks();
+            let pages = alloc.free_pages();
+            let a = alloc.allocate(PAGE_SIZE);
+            let b = alloc.allocate(PAGE_SIZE * 3);
+            let c = alloc.allocate(PAGE_SIZE * 20);
+            assert_eq!(alloc.free_pages(), pages - 1 - 4 - 32);
+            for addr in &[a, b, c] {
+                assert_eq!(*addr as usize % PAGE_SIZE, 0);
+            }
+            alloc.deallocate(b);
+            alloc.deallocate(a);
+            alloc.deallocate(c);
+            assert_eq!(alloc.free_blocks(), blocks);
+        });
+    }
+
+    /// Test allocating every page one by one
+    pub fn test_allocate_all() {
+        with_allocator(|alloc| {
+            let blocks = alloc.free_blocks();
+            let pages = alloc.free_pages();
+            let mut allocated = Vec::new();
+            for _ in 0..pages {
+                allocated.push(alloc.allocate(PAGE_SIZE) as usize);
+            }
+            assert_eq!(alloc.free_pages(), 0);
+            allocated.sort();
+            allocated.dedup();
+            assert_eq!(allocated.len(), pages);
+            for addr in allocated.iter().rev() {
+                alloc.deallocate(*addr as *mut u8);
+            }
+            assert_eq!(alloc.free_blocks(), blocks);
+        });
+    }
+
+    /// Test that shared allocations are freed after the last reference is dropped
+    pub fn test_ref_count() {
+        with_allocator(|alloc| {
+            let pages = alloc.free_pages();
+            let a = alloc.allocate(PAGE_SIZE * 2);
+            alloc.share(a);
+            assert_eq!(alloc.ref_count(a), 2);
+            alloc.deallocate(a);
+            assert_eq!(alloc.ref_count(a), 1);
+            assert_eq!(alloc.free_pages(), pages - 2);
+            alloc.deallocate(a);
+            assert_eq!(alloc.free_pages(), pages);
+        });
+    }
+
+    /// Test that blocks are aligned to their size
+    pub fn test_alignment() {
+        with_allocator(|alloc| {
+            let a = alloc.allocate(PAGE_SIZE);
+            let b = alloc.allocate_aligned(PAGE_SIZE, PAGE_SIZE * 8);
+            let c = alloc.allocate(PAGE_SIZE * 4);
+            assert_eq!(b as usize % (PAGE_SIZE * 8), 0);
+            assert_eq!(c as usize % (PAGE_SIZE * 4), 0);
+            alloc.deallocate(a);
+            alloc.deallocate(b);
+            alloc.deallocate(c);
+        });
+    }
+}
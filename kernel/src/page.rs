@@ -10,6 +10,8 @@ use crate::spinlock::Mutex;
 use crate::{print, println, panic};
 use crate::symbols::*;
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use crate::process::my_cpu;
 
 const TABLE_ENTRY_CNT: usize = 512;
@@ -34,6 +36,15 @@ impl Page {
     }
 }
 
+/// Error of copying between kernel space and user space
+#[derive(Debug, PartialEq)]
+pub enum CopyError {
+    /// Address is not mapped as a user page with required permission
+    BadAddress(usize),
+    /// String is too long, or is not valid UTF-8
+    BadString,
+}
+
 #[derive(Copy, Clone)]
 #[repr(transparent)]
 pub struct Entry(usize);
@@ -47,6 +58,12 @@ pub struct VPN(usize);
 pub struct PPN(usize);
 
 pub enum EntryAttributes {
+    /// Copy-on-write page, which is in bits reserved for software.
+    /// Such pages are mapped without `W`, and are copied on first write.
+    C = 1 << 8,
+    /// Lazily allocated page, which is in bits reserved for software.
+    /// Such pages are reserved without `V`, and a zeroed page is mapped on first access.
+    L = 1 << 9,
     D = 1 << 7,
     A = 1 << 6,
     G = 1 << 5,
@@ -87,6 +104,12 @@ impl Entry {
     pub fn is_v(&self) -> bool {
         self.0 & EntryAttributes::V as usize != 0
     }
+    pub fn is_cow(&self) -> bool {
+        self.0 & EntryAttributes::C as usize != 0
+    }
+    pub fn is_lazy(&self) -> bool {
+        self.0 & EntryAttributes::L as usize != 0
+    }
     pub fn is_leaf(&self) -> bool {
         self.0 & 0xe != 0
     }
@@ -175,6 +198,24 @@ impl Table {
         if paddr % PAGE_SIZE != 0 {
             panic!("paddr {:x} not aligned", paddr);
         }
+        *self.create_entry(vaddr, level) = Entry::new(paddr, flags | EntryAttributes::V as usize)
+    }
+
+    /// Reserve user page at `vaddr` with `flags` without allocating it.
+    /// A zeroed page is mapped on first access, see `fault_in`.
+    pub fn reserve(&mut self, vaddr: usize, flags: usize) {
+        if flags & EntryAttributes::U as usize == 0 {
+            panic!("you may only reserve user page");
+        }
+        let v = self.create_entry(vaddr, 0);
+        if v.is_v() {
+            panic!("vaddr {:x} already mapped", vaddr);
+        }
+        *v = Entry::new(0, (flags | EntryAttributes::L as usize) & !(EntryAttributes::V as usize));
+    }
+
+    /// Entry of `level` which maps `vaddr`, creating page tables above it if necessary
+    fn create_entry(&mut self, vaddr: usize, level: usize) -> &mut Entry {
         if vaddr % PAGE_SIZE != 0 {
             panic!("vaddr {:x} not aligned", vaddr);
         }
@@ -188,7 +229,7 @@ impl Table {
             let entry = v.paddr().0 as *mut Entry;
             v = unsafe { entry.add(vpn.idx(lvl)).as_mut().unwrap() };
         }
-        *v = Entry::new(paddr, flags | EntryAttributes::V as usize)
+        v
     }
 
     pub fn paddr_of(&self, vaddr: usize) -> Option<usize> {
@@ -204,7 +245,195 @@ impl Table {
             let entry = v.paddr().0 as *mut Entry;
             v = unsafe { entry.add(vpn.idx(lvl)).as_mut().unwrap() };
         }
-        Some(v.paddr().0)
+        if v.is_v() { Some(v.paddr().0) } else { None }
+    }
+
+    /// Map a zeroed user page at page-aligned `vaddr` with `flags`, or add `flags`
+    /// to the user page already mapped there. Returns physical address of the page.
+    pub fn map_or_add_flags(&mut self, vaddr: usize, flags: usize) -> usize {
+        if let Some(v) = self.leaf_of(vaddr) {
+            if v.is_u() {
+                let paddr = v.paddr().0;
+                let v = v as *const Entry as *mut Entry;
+                unsafe { *v = Entry::new(paddr, (*v).flags() | flags); }
+                return paddr;
+            }
+        }
+        let pg = Page::new();
+        let paddr = &*pg as *const Page as usize;
+        self.map(vaddr, pg, flags);
+        paddr
+    }
+
+    /// Unmap user page at `vaddr` and free it, or cancel its reservation.
+    /// Does nothing if no user page is mapped or reserved there.
+    pub fn unmap(&mut self, vaddr: usize) {
+        if let Some(v) = self.entry_of_mut(vaddr) {
+            if v.is_v() && v.is_u() {
+                let _pg = unsafe { Box::from_raw(v.paddr().0 as *mut Page) };
+                *v = Entry(0);
+            } else if v.is_lazy() {
+                *v = Entry(0);
+            }
+        }
+    }
+
+    /// Leaf entry which maps `vaddr`, or `None` if `vaddr` is not mapped
+    fn leaf_of(&self, vaddr: usize) -> Option<&Entry> {
+        self.entry_of(vaddr).filter(|v| v.is_v())
+    }
+
+    /// Level-0 entry for `vaddr`, which may be invalid,
+    /// or `None` if there's no level-0 page table for `vaddr`
+    fn entry_of(&self, vaddr: usize) -> Option<&Entry> {
+        if vaddr >= MAXVA {
+            return None;
+        }
+        let vpn = VPN(vaddr);
+        let mut v = &self.entries[vpn.vpn2()];
+        for lvl in (0..2).rev() {
+            if !v.is_v() || v.is_leaf() {
+                return None;
+            }
+            let entry = v.paddr().0 as *const Entry;
+            v = unsafe { entry.add(vpn.idx(lvl)).as_ref().unwrap() };
+        }
+        Some(v)
+    }
+
+    /// Mutable leaf entry which maps `vaddr`
+    fn leaf_of_mut(&mut self, vaddr: usize) -> Option<&mut Entry> {
+        self.leaf_of(vaddr).map(|v| unsafe { &mut *(v as *const Entry as *mut Entry) })
+    }
+
+    /// Mutable level-0 entry for `vaddr`, which may be invalid
+    fn entry_of_mut(&mut self, vaddr: usize) -> Option<&mut Entry> {
+        self.entry_of(vaddr).map(|v| unsafe { &mut *(v as *const Entry as *mut Entry) })
+    }
+
+    /// Map a zeroed page at page-aligned `vaddr` which is reserved by `reserve`.
+    ///
+    /// Returns physical address of the page, or `BadAddress` if `vaddr` is not
+    /// in a reserved page.
+    pub fn fault_in(&mut self, vaddr: usize) -> Result<usize, CopyError> {
+        let v = match self.entry_of_mut(vaddr) {
+            Some(v) if !v.is_v() && v.is_lazy() => v,
+            _ => return Err(CopyError::BadAddress(vaddr))
+        };
+        let flags = (v.flags() & !(EntryAttributes::L as usize)) | EntryAttributes::V as usize;
+        let paddr = Box::into_raw(Page::new()) as usize;
+        *v = Entry::new(paddr, flags);
+        Ok(paddr)
+    }
+
+    /// Make user page at `vaddr` accessible for read, or for write if `write` is set,
+    /// after a page fault or before kernel accesses it. Reserved page is allocated,
+    /// and copy-on-write page is copied for write.
+    ///
+    /// Returns physical address of `vaddr`, or `BadAddress` if it is still not accessible.
+    pub fn handle_page_fault(&mut self, vaddr: usize, write: bool) -> Result<usize, CopyError> {
+        let perm = if write { EntryAttributes::W as usize } else { EntryAttributes::R as usize };
+        if let Ok(pa) = self.user_paddr(vaddr, perm) {
+            return Ok(pa);
+        }
+        let page = mem::page_down(vaddr);
+        let _ = self.fault_in(page);
+        if write {
+            let _ = self.copy_on_write(page);
+        }
+        self.user_paddr(vaddr, perm)
+    }
+
+    /// Make all user pages in `[addr, addr + len)` accessible as `handle_page_fault` does,
+    /// so that the range can be copied to or from without failure.
+    ///
+    /// Returns `BadAddress` if any page in the range is still not accessible.
+    pub fn fault_in_range(&mut self, addr: usize, len: usize, write: bool) -> Result<(), CopyError> {
+        let mut tot = 0;
+        while tot < len {
+            let va = addr.checked_add(tot).ok_or(CopyError::BadAddress(addr))?;
+            self.handle_page_fault(va, write)?;
+            tot += PAGE_SIZE - va % PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Make copy-on-write user page at `vaddr` writable. The page is copied
+    /// if it is still shared with other page tables.
+    ///
+    /// Returns physical address of the writable page, or `BadAddress` if
+    /// `vaddr` is not in a copy-on-write page.
+    pub fn copy_on_write(&mut self, vaddr: usize) -> Result<usize, CopyError> {
+        let v = match self.leaf_of_mut(vaddr) {
+            Some(v) if v.is_u() && v.is_cow() => v,
+            _ => return Err(CopyError::BadAddress(vaddr))
+        };
+        let paddr = v.paddr().0;
+        let flags = (v.flags() | EntryAttributes::W as usize) & !(EntryAttributes::C as usize);
+        if ALLOC().lock().ref_count(paddr as *mut u8) == 1 {
+            // the other page tables are gone
+            *v = Entry::new(paddr, flags);
+            return Ok(paddr);
+        }
+        let new_paddr = Box::into_raw(v.paddr().clone_page()) as usize;
+        *v = Entry::new(new_paddr, flags);
+        // drop reference to the shared page
+        let _pg = unsafe { Box::from_raw(paddr as *mut Page) };
+        Ok(new_paddr)
+    }
+
+    /// Physical address of user-space `vaddr`, whose page should be mapped
+    /// with `U` bit and all bits in `perm`
+    fn user_paddr(&self, vaddr: usize, perm: usize) -> Result<usize, CopyError> {
+        match self.leaf_of(vaddr) {
+            Some(v) if v.is_u() && v.flags() & perm == perm => Ok(v.paddr().0 + vaddr % PAGE_SIZE),
+            _ => Err(CopyError::BadAddress(vaddr))
+        }
+    }
+
+    /// Copy `dst.len()` bytes from user-space address `src` to `dst`.
+    ///
+    /// The range may span multiple pages, all of which should be readable user pages.
+    /// Reserved pages are allocated before read.
+    pub fn copy_from_user(&mut self, dst: &mut [u8], src: usize) -> Result<(), CopyError> {
+        let mut tot = 0;
+        while tot < dst.len() {
+            let va = src.checked_add(tot).ok_or(CopyError::BadAddress(src))?;
+            let n = (dst.len() - tot).min(PAGE_SIZE - va % PAGE_SIZE);
+            let pa = self.handle_page_fault(va, false)?;
+            unsafe { core::ptr::copy(pa as *const u8, dst[tot..].as_mut_ptr(), n); }
+            tot += n;
+        }
+        Ok(())
+    }
+
+    /// Copy `src` to user-space address `dst`.
+    ///
+    /// The range may span multiple pages, all of which should be writable user pages.
+    /// Reserved pages are allocated, and copy-on-write pages are copied before written.
+    pub fn copy_to_user(&mut self, dst: usize, src: &[u8]) -> Result<(), CopyError> {
+        let mut tot = 0;
+        while tot < src.len() {
+            let va = dst.checked_add(tot).ok_or(CopyError::BadAddress(dst))?;
+            let n = (src.len() - tot).min(PAGE_SIZE - va % PAGE_SIZE);
+            let pa = self.handle_page_fault(va, true)?;
+            unsafe { core::ptr::copy(src[tot..].as_ptr(), pa as *mut u8, n); }
+            tot += n;
+        }
+        Ok(())
+    }
+
+    /// Copy a string of `len` bytes from user-space address `src`.
+    ///
+    /// Fails if `len` is larger than `max`, or the string is not valid UTF-8.
+    pub fn copy_str_from_user(&mut self, src: usize, len: usize, max: usize) -> Result<String, CopyError> {
+        if len > max {
+            return Err(CopyError::BadString);
+        }
+        let mut buf = Vec::new();
+        buf.resize(len, 0);
+        self.copy_from_user(&mut buf, src)?;
+        String::from_utf8(buf).map_err(|_| CopyError::BadString)
     }
 
     fn _walk(&self, level: usize, vpn: usize) {
@@ -286,21 +515,36 @@ impl Table {
         }
     }
 
-    fn clone_walk(&self, level: usize) -> Box<Self> {
+    /// Clone user pages of page table for `fork`. Pages are shared instead of copied:
+    /// writable pages are mapped copy-on-write in both page tables, and read-only
+    /// pages stay read-only. Reserved pages are reserved in both page tables.
+    /// Kernel pages are not cloned.
+    pub fn cow_clone(&mut self) -> Box<Self> {
+        self.cow_walk(2)
+    }
+
+    fn cow_walk(&mut self, level: usize) -> Box<Self> {
         let mut pgtable = Table::new();
         for i in 0..self.len() {
-            let v = &self.entries[i];
+            let v = &mut self.entries[i];
             if v.is_v() {
                 if v.is_leaf() {
                     if v.is_u() {
-                        let pg = v.paddr().clone_page();
-                        pgtable.entries[i] = Entry::new(Box::into_raw(pg) as usize, v.flags());
+                        let mut flags = v.flags();
+                        if v.is_w() || v.is_cow() {
+                            flags = (flags & !(EntryAttributes::W as usize)) | EntryAttributes::C as usize;
+                            *v = Entry::new(v.paddr().0, flags);
+                        }
+                        ALLOC().lock().share(v.paddr().0 as *mut u8);
+                        pgtable.entries[i] = Entry::new(v.paddr().0, flags);
                     }
                 } else {
                     let table = unsafe { (v.paddr().0 as *mut Table).as_mut().unwrap() };
-                    let pg = table.clone_walk(level - 1);
+                    let pg = table.cow_walk(level - 1);
                     pgtable.entries[i] = Entry::new(Box::into_raw(pg) as usize, v.flags());
                 }
+            } else if v.is_lazy() {
+                pgtable.entries[i] = *v;
             }
         }
         box pgtable
@@ -321,6 +565,8 @@ impl Table {
                     let table = unsafe { (v.paddr().0 as *mut Table).as_mut().unwrap() };
                     table.unmap_user();
                 }
+            } else if v.is_lazy() {
+                *v = Entry(0);
             }
         }
     }
@@ -332,11 +578,116 @@ impl Drop for Table {
     }
 }
 
-impl Clone for Box<Table> {
-    fn clone(&self) -> Self {
-        self.clone_walk(2)
-    }
-}
-
 /// Kernel page table
 pub static KERNEL_PGTABLE: Table = Table::new();
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("copy across pages", test_copy_across_pages),
+            ("copy permission", test_copy_permission),
+            ("unmap", test_unmap),
+            ("copy on write", test_copy_on_write),
+            ("lazy allocation", test_lazy_allocation),
+        ]
+    }
+
+    /// Test copying a range which spans two user pages
+    pub fn test_copy_across_pages() {
+        let mut pgtable = box Table::new();
+        pgtable.map(0x1000, Page::new(), EntryAttributes::URW as usize);
+        pgtable.map(0x2000, Page::new(), EntryAttributes::URW as usize);
+        let src = [0x5a; 100];
+        assert_eq!(pgtable.copy_to_user(0x2000 - 50, &src), Ok(()));
+        let mut dst = [0; 100];
+        assert_eq!(pgtable.copy_from_user(&mut dst, 0x2000 - 50), Ok(()));
+        assert_eq!(&dst[..], &src[..]);
+        assert_eq!(pgtable.copy_from_user(&mut dst, 0x3000 - 50), Err(CopyError::BadAddress(0x3000)));
+        assert_eq!(pgtable.copy_from_user(&mut dst, usize::max_value() - 50), Err(CopyError::BadAddress(usize::max_value() - 50)));
+        assert_eq!(pgtable.copy_to_user(0x1000, b"/init"), Ok(()));
+        assert_eq!(pgtable.copy_str_from_user(0x1000, 5, 128), Ok(String::from("/init")));
+        assert_eq!(pgtable.copy_str_from_user(0x1000, 5, 4), Err(CopyError::BadString));
+    }
+
+    /// Test copying to read-only pages and kernel pages
+    pub fn test_copy_permission() {
+        let mut pgtable = box Table::new();
+        pgtable.map(0x1000, Page::new(), EntryAttributes::UR as usize);
+        let kernel_page = Page::new();
+        pgtable.kernel_map(0x2000, &*kernel_page as *const _ as usize, EntryAttributes::RW as usize);
+        let mut dst = [0; 10];
+        assert_eq!(pgtable.copy_from_user(&mut dst, 0x1000), Ok(()));
+        assert_eq!(pgtable.copy_to_user(0x1000, &dst), Err(CopyError::BadAddress(0x1000)));
+        assert_eq!(pgtable.copy_from_user(&mut dst, 0x2000), Err(CopyError::BadAddress(0x2000)));
+        assert_eq!(pgtable.copy_to_user(0x2000, &dst), Err(CopyError::BadAddress(0x2000)));
+    }
+
+    /// Test unmapping a user page
+    pub fn test_unmap() {
+        let mut pgtable = box Table::new();
+        pgtable.map(0x1000, Page::new(), EntryAttributes::URW as usize);
+        pgtable.unmap(0x1000);
+        assert_eq!(pgtable.copy_to_user(0x1000, &[0]), Err(CopyError::BadAddress(0x1000)));
+        pgtable.unmap(0x2000);
+    }
+
+    /// Test sharing pages between cloned page tables, and copying them on write
+    pub fn test_copy_on_write() {
+        let ref_count = |paddr: usize| ALLOC().lock().ref_count(paddr as *mut u8);
+        let mut parent = box Table::new();
+        parent.map(0x1000, Page::new(), EntryAttributes::URW as usize);
+        parent.map(0x2000, Page::new(), EntryAttributes::UR as usize);
+        assert_eq!(parent.copy_to_user(0x1000, b"parent"), Ok(()));
+        let mut child = parent.cow_clone();
+        let (rw_page, ro_page) = (parent.paddr_of(0x1000).unwrap(), parent.paddr_of(0x2000).unwrap());
+        assert_eq!(child.paddr_of(0x1000), Some(rw_page));
+        assert_eq!(ref_count(rw_page), 2);
+        assert_eq!(ref_count(ro_page), 2);
+        // child gets its own copy on write
+        assert_eq!(child.copy_to_user(0x1000, b"child"), Ok(()));
+        assert_ne!(child.paddr_of(0x1000), Some(rw_page));
+        assert_eq!(ref_count(rw_page), 1);
+        let mut buf = [0; 6];
+        assert_eq!(parent.copy_from_user(&mut buf, 0x1000), Ok(()));
+        assert_eq!(&buf, b"parent");
+        // parent is the last owner, so page is not copied
+        assert_eq!(parent.copy_to_user(0x1000, b"p"), Ok(()));
+        assert_eq!(parent.paddr_of(0x1000), Some(rw_page));
+        // read-only pages are never copied
+        assert!(child.copy_to_user(0x2000, b"c").is_err());
+        drop(child);
+        assert_eq!(ref_count(ro_page), 1);
+    }
+
+    /// Test allocating reserved pages on first access
+    pub fn test_lazy_allocation() {
+        let mut pgtable = box Table::new();
+        pgtable.reserve(0x1000, EntryAttributes::URW as usize);
+        pgtable.reserve(0x2000, EntryAttributes::URW as usize);
+        pgtable.reserve(0x3000, EntryAttributes::UR as usize);
+        assert_eq!(pgtable.paddr_of(0x1000), None);
+        let mut child = pgtable.cow_clone();
+        assert_eq!(pgtable.copy_to_user(0x1000, b"lazy"), Ok(()));
+        assert!(pgtable.paddr_of(0x1000).is_some());
+        // a reserved page in child is allocated separately, and is zeroed
+        let mut buf = [0x5a; 4];
+        assert_eq!(child.copy_from_user(&mut buf, 0x1000), Ok(()));
+        assert_eq!(&buf, &[0; 4]);
+        assert_ne!(child.paddr_of(0x1000), pgtable.paddr_of(0x1000));
+        // permission of reserved page is kept
+        assert_eq!(pgtable.copy_to_user(0x3000, b"ro"), Err(CopyError::BadAddress(0x3000)));
+        assert_eq!(pgtable.copy_from_user(&mut buf, 0x3000), Ok(()));
+        // a range is faulted in page by page, up to the first inaccessible one
+        pgtable.reserve(0x4000, EntryAttributes::URW as usize);
+        assert_eq!(pgtable.fault_in_range(0x4000 - 10, 20, true), Err(CopyError::BadAddress(0x3ff6)));
+        assert_eq!(pgtable.fault_in_range(0x4000 - 10, 20, false), Ok(()));
+        assert!(pgtable.paddr_of(0x4000).is_some());
+        assert_eq!(pgtable.fault_in_range(0x4000, 0x1001, false), Err(CopyError::BadAddress(0x5000)));
+        // reservation is cancelled by unmap
+        pgtable.unmap(0x2000);
+        assert_eq!(pgtable.handle_page_fault(0x2000, false), Err(CopyError::BadAddress(0x2000)));
+        assert_eq!(pgtable.handle_page_fault(0x5000, true), Err(CopyError::BadAddress(0x5000)));
+    }
+}
@@ -8,38 +8,38 @@
 use crate::spinlock::Mutex;
 use crate::process::my_cpu;
 use crate::arch::hart_id;
+use crate::fdt::machine;
+use crate::virtio;
+use alloc::vec::Vec;
 
+/// PLIC base address on QEMU RISC-V, used if there's no device tree
 pub const PLIC_BASE: usize = 0x0c00_0000;
-pub const PLIC_PRIORITY: usize = PLIC_BASE + 0x0;
-pub const PLIC_PENDING: usize = PLIC_BASE + 0x1000;
-pub const PLIC_MENABLE_BASE: usize = PLIC_BASE + 0x2000;
-pub const PLIC_SENABLE_BASE: usize = PLIC_BASE + 0x2080;
-pub const PLIC_MPRIORITY_BASE: usize = PLIC_BASE + 0x200000;
-pub const PLIC_SPRIORITY_BASE: usize = PLIC_BASE + 0x201000;
-pub const PLIC_MCLAIM_BASE: usize = PLIC_BASE + 0x200004;
-pub const PLIC_SCLAIM_BASE: usize = PLIC_BASE + 0x201004;
+
+fn base() -> usize { machine().plic.base }
 
 #[allow(non_snake_case)]
-pub const fn PLIC_MENABLE(hart: usize) -> usize { PLIC_MENABLE_BASE + hart * 0x100 }
+pub fn PLIC_PRIORITY() -> usize { base() + 0x0 }
 
 #[allow(non_snake_case)]
-pub const fn PLIC_SENABLE(hart: usize) -> usize { PLIC_SENABLE_BASE + hart * 0x100 }
+pub fn PLIC_PENDING() -> usize { base() + 0x1000 }
 
 #[allow(non_snake_case)]
-pub const fn PLIC_MPRIORITY(hart: usize) -> usize { PLIC_MPRIORITY_BASE + hart * 0x2000 }
+pub fn PLIC_MENABLE(hart: usize) -> usize { base() + 0x2000 + hart * 0x100 }
 
 #[allow(non_snake_case)]
-pub const fn PLIC_SPRIORITY(hart: usize) -> usize { PLIC_SPRIORITY_BASE + hart * 0x2000 }
+pub fn PLIC_SENABLE(hart: usize) -> usize { base() + 0x2080 + hart * 0x100 }
 
 #[allow(non_snake_case)]
-pub const fn PLIC_MCLAIM(hart: usize) -> usize { PLIC_MCLAIM_BASE + hart * 0x2000 }
+pub fn PLIC_MPRIORITY(hart: usize) -> usize { base() + 0x200000 + hart * 0x2000 }
 
 #[allow(non_snake_case)]
-pub const fn PLIC_SCLAIM(hart: usize) -> usize { PLIC_SCLAIM_BASE + hart * 0x2000 }
+pub fn PLIC_SPRIORITY(hart: usize) -> usize { base() + 0x201000 + hart * 0x2000 }
 
-pub const UART0_IRQ: u32 = 10;
+#[allow(non_snake_case)]
+pub fn PLIC_MCLAIM(hart: usize) -> usize { base() + 0x200004 + hart * 0x2000 }
 
-pub const VIRTIO0_IRQ: u32 = 1;
+#[allow(non_snake_case)]
+pub fn PLIC_SCLAIM(hart: usize) -> usize { base() + 0x201004 + hart * 0x2000 }
 
 pub struct Plic {}
 
@@ -82,7 +82,7 @@ impl Plic {
 
     /// Initialize PLIC. Enable interrupt.
     pub unsafe fn init(&mut self, id: u32) {
-        let enables = PLIC_BASE as *mut u32;
+        let enables = PLIC_PRIORITY() as *mut u32;
         enables.add(id as usize).write_volatile(1);
     }
 
@@ -90,7 +90,7 @@ impl Plic {
     ///
     /// Should only be called with lock.
     pub unsafe fn is_pending(&mut self, id: u32) -> bool {
-        let pend = PLIC_PENDING as *const u32;
+        let pend = PLIC_PENDING() as *const u32;
         let actual_id = 1 << id;
         let pend_ids;
         pend_ids = pend.read_volatile();
@@ -152,15 +152,25 @@ pub fn PLIC() -> &'static mut Plic { unsafe { &mut __PLIC } }
 /// This function should only be called from boot hart
 pub unsafe fn init() {
     let plic = PLIC();
-    plic.init(UART0_IRQ);
-    plic.init(VIRTIO0_IRQ);
+    for irq in &device_irqs() {
+        plic.init(*irq);
+    }
 }
 
 pub fn hartinit() {
     let plic = PLIC();
-    plic.enable(UART0_IRQ);
-    plic.enable(VIRTIO0_IRQ);
+    for irq in &device_irqs() {
+        plic.enable(*irq);
+    }
     plic.set_threshold(0);
-    plic.set_priority(UART0_IRQ, 1);
-    plic.set_priority(VIRTIO0_IRQ, 1);
+    for irq in &device_irqs() {
+        plic.set_priority(*irq, 1);
+    }
+}
+
+/// Interrupts of UART and virtio devices with drivers
+fn device_irqs() -> Vec<u32> {
+    let mut irqs = virtio::irqs();
+    irqs.push(machine().uart.irq);
+    irqs
 }
@@ -21,7 +21,7 @@ pub fn _print(args: fmt::Arguments) {
 pub fn _panic_print(args: fmt::Arguments) {
     use core::fmt::Write;
     use crate::uart::*;
-	let mut uart = Uart::new(UART_BASE_ADDR);
+	let mut uart = Uart::new(crate::fdt::machine().uart.base);
 	uart.write_fmt(args).unwrap();
 }
 
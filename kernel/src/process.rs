@@ -115,7 +115,57 @@ pub fn my_proc() -> &'static mut Process {
     proc_cpu.process.as_mut().unwrap()
 }
 
+/// Get process `pid` if it is running on any hart.
+///
+/// The process is accessed without synchronization, so it should only be
+/// inspected, or have a flag set which it will check later.
+pub fn running_proc(pid: i32) -> Option<&'static mut Process> {
+    (0..NCPUS)
+        .filter_map(|i| unsafe { CPUS[i].process.as_deref_mut() })
+        .find(|p| p.pid == pid)
+}
+
 use crate::println;
+use alloc::vec::Vec;
+
+/// Information of a process, which is copied to user space in `procinfo` syscall
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ProcInfo {
+    pub pid: i32,
+    /// pid of parent, or -1 if there's none
+    pub ppid: i32,
+    /// `ProcessState` as integer
+    pub state: u32,
+    /// name of program, padded with 0
+    pub name: [u8; PROC_NAME_LEN],
+}
+
+/// Get information of all processes.
+///
+/// Processes running on other harts are read without synchronization,
+/// so their information may be slightly outdated.
+pub fn proc_info() -> Vec<ProcInfo> {
+    let parents = PROCS_PARENT.lock();
+    let pool = PROCS_POOL.lock();
+    let mut infos = Vec::new();
+    for pid in 0..NMAXPROCS {
+        let p = match &pool[pid] {
+            ProcInPool::Pooling(p) => Some(&**p),
+            ProcInPool::Scheduled | ProcInPool::BeingSlept => running_proc(pid as i32).map(|p| &*p),
+            ProcInPool::NoProc => None
+        };
+        if let Some(p) = p {
+            infos.push(ProcInfo {
+                pid: p.pid,
+                ppid: parents[pid].unwrap_or(-1),
+                state: p.state as u32,
+                name: p.name,
+            });
+        }
+    }
+    infos
+}
 
 pub fn debug() {
     for i in 0..NCPUS {
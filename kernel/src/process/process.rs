@@ -11,15 +11,21 @@ use crate::arch;
 use crate::println;
 use crate::trap::usertrapret;
 use alloc::boxed::Box;
-use crate::process::{put_back_proc, my_proc, PROCS_POOL, my_cpu, sched, ProcInPool};
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::process::{put_back_proc, my_proc, PROCS_POOL, my_cpu, sched, ProcInPool, running_proc};
 use crate::page::{Page, Table, EntryAttributes};
 use crate::process::Register::a0;
 use crate::jump::*;
 use crate::spinlock::{Mutex, MutexGuard};
 use alloc::sync::Arc;
-use crate::file::{File, FsFile};
+use crate::file::{File, FsFile, O_RDONLY};
+use crate::fs::{self, Inode};
+use crate::error::Errno;
+use crate::signal::{Signal, SigAction, NSIG, SIGNAL_LOCK};
+use core::sync::atomic::AtomicU32;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 #[derive(Debug)]
 pub enum ProcessState {
     UNUSED,
@@ -33,6 +39,12 @@ pub enum ProcessState {
 #[repr(align(4096))]
 pub struct Process {
     pub pgtable: Box<page::Table>,
+    /// program break, which is end of program and heap.
+    /// User memory is `[0, brk)` and user stack.
+    pub brk: usize,
+    /// Start of heap, which is the initial program break.
+    /// Heap pages in `[heap_start, brk)` are allocated on first access.
+    pub heap_start: usize,
     pub trapframe: Box<TrapFrame>,
     pub context: Box<Context>,
     pub state: ProcessState,
@@ -42,8 +54,28 @@ pub struct Process {
     pub channel: usize,
     pub drop_on_put_back: Option<MutexGuard<'static, ()>>,
     pub files: [Option<Arc<File>>; 256],
+    /// exit status, which will be delivered to parent in `wait`
+    pub exit_status: i32,
+    /// set by `SIGKILL`. Process exits when it enters or leaves kernel with this set.
+    pub killed: bool,
+    /// signals sent but not yet delivered, one bit for each signal.
+    /// It may be set by other harts while this process is running.
+    pub sig_pending: AtomicU32,
+    /// signals which are not delivered until unblocked
+    pub sig_blocked: u32,
+    /// action of every signal, indexed by signal number
+    pub sig_actions: [SigAction; NSIG],
+    /// current working directory
+    pub cwd: Option<Arc<Inode>>,
+    /// depth of nested file system operations, see `fs::begin_op`
+    pub log_depth: usize,
+    /// name of program, padded with 0
+    pub name: [u8; PROC_NAME_LEN],
 }
 
+/// Maximum length of process name
+pub const PROC_NAME_LEN: usize = 16;
+
 impl Process {
     pub fn new(pid: i32) -> Self {
         Self::from_exist(pid, box page::Table::new(), box TrapFrame::zero())
@@ -59,6 +91,8 @@ impl Process {
         let mut p = Self {
             trapframe,
             pgtable,
+            brk: 0,
+            heap_start: 0,
             context: box Context::zero(),
             state: ProcessState::UNUSED,
             kstack: kstack,
@@ -67,27 +101,46 @@ impl Process {
             channel: 0,
             drop_on_put_back: None,
             files: [None; 256],
+            exit_status: 0,
+            killed: false,
+            sig_pending: AtomicU32::new(0),
+            sig_blocked: 0,
+            sig_actions: [SigAction::DEFAULT; NSIG],
+            cwd: None,
+            log_depth: 0,
+            name: [0; PROC_NAME_LEN],
         };
 
-        // map trampoline
-        p.pgtable.kernel_map(
-            TRAMPOLINE_START,
-            TRAMPOLINE_TEXT_START(),
-            page::EntryAttributes::RX as usize,
-        );
-
-        let trapframe = &*p.trapframe as *const _ as usize;
-        // map trapframe
-        p.pgtable.kernel_map(
-            TRAPFRAME_START,
-            trapframe,
-            page::EntryAttributes::RW as usize,
-        );
+        map_kernel_pages(&mut p.pgtable, &p.trapframe);
         p.context.regs[ContextRegisters::ra as usize] = forkret as usize;
         p.context.regs[ContextRegisters::sp as usize] = p.kstack + PAGE_SIZE;
 
         p
     }
+
+    /// Set name of process, which is truncated to `PROC_NAME_LEN` bytes
+    pub fn set_name(&mut self, name: &str) {
+        let len = name.len().min(PROC_NAME_LEN);
+        self.name = [0; PROC_NAME_LEN];
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+}
+
+/// Map trampoline and `trapframe` of a process into its `pgtable`
+fn map_kernel_pages(pgtable: &mut Table, trapframe: &TrapFrame) {
+    // map trampoline
+    pgtable.kernel_map(
+        TRAMPOLINE_START,
+        TRAMPOLINE_TEXT_START(),
+        page::EntryAttributes::RX as usize,
+    );
+
+    // map trapframe
+    pgtable.kernel_map(
+        TRAPFRAME_START,
+        trapframe as *const _ as usize,
+        page::EntryAttributes::RW as usize,
+    );
 }
 
 impl Drop for Process {
@@ -96,8 +149,21 @@ impl Drop for Process {
     }
 }
 
+/// Whether root file system has been initialized
+static mut FS_INITIALIZED: bool = false;
+
 #[no_mangle]
 pub extern "C" fn forkret() -> ! {
+    // File system can only be initialized in process context,
+    // as it sleeps while reading from disk.
+    unsafe {
+        if !FS_INITIALIZED {
+            FS_INITIALIZED = true;
+            if let Err(err) = fs::init(fs::ROOTDEV) {
+                panic!("cannot mount root file system: {:?}", err);
+            }
+        }
+    }
     usertrapret()
 }
 
@@ -120,10 +186,17 @@ pub fn init_proc() {
     let mut page = Page::new();
     page.data[0..content.len()].copy_from_slice(content);
     p.pgtable.map(0, page, EntryAttributes::URX as usize);
+    p.brk = PAGE_SIZE;
+    p.heap_start = PAGE_SIZE;
+    p.set_name("initcode");
     // map user stack
-    let sp = map_stack(&mut p.pgtable, 0x80001000);
+    let sp = map_stack(&mut p.pgtable);
     p.trapframe.epc = 0;
     p.trapframe.regs[Register::sp as usize] = sp;
+    p.cwd = match fs::iget(fs::ROOTDEV, fs::ROOTINO) {
+        Ok(ip) => Some(ip),
+        Err(err) => panic!("init_proc: {:?}", err)
+    };
     p.state = ProcessState::RUNNABLE;
     put_back_proc(box p);
 }
@@ -139,14 +212,13 @@ pub fn find_available_pid() -> Option<i32> {
     None
 }
 
-pub fn fork() -> i32 {
+/// fork syscall
+///
+/// Returns pid of the child, or `EAGAIN` if process table is full.
+pub fn fork() -> Result<i32, Errno> {
     let p = my_proc();
-    let f_pid = find_available_pid();
-    if f_pid.is_none() {
-        panic!("pid unavailable");
-    }
-    let f_pid = f_pid.unwrap();
-    let pgtable = p.pgtable.clone();
+    let f_pid = find_available_pid().ok_or(Errno::EAGAIN)?;
+    let pgtable = p.pgtable.cow_clone();
     let trapframe = box *p.trapframe.clone();
     let mut fork_p = Process::from_exist(f_pid, pgtable, trapframe);
     for i in 0..fork_p.files.len() {
@@ -155,79 +227,314 @@ pub fn fork() -> i32 {
             None => None
         }
     }
+    fork_p.cwd = p.cwd.clone();
+    fork_p.brk = p.brk;
+    fork_p.heap_start = p.heap_start;
+    fork_p.name = p.name;
+    fork_p.sig_blocked = p.sig_blocked;
+    fork_p.sig_actions = p.sig_actions;
     fork_p.trapframe.regs[a0 as usize] = 0;
     fork_p.state = ProcessState::RUNNABLE;
+    PROCS_PARENT.lock()[f_pid as usize] = Some(p.pid);
     put_back_proc(box fork_p);
-    f_pid
+    Ok(f_pid)
 }
 
-pub const USER_STACK_PAGE: usize = 4;
-
-/// map user stack in `pgtable` at `stack_begin` and returns `sp`
-pub fn map_stack(pgtable: &mut Table, stack_begin: usize) -> usize {
-    for i in 0..USER_STACK_PAGE {
-        let stack = page::Page::new();
-        pgtable.map(
-            stack_begin + i * PAGE_SIZE,
-            stack,
-            page::EntryAttributes::URW as usize,
-        );
+/// Maximum number of pages of user stack. Stack grows on page fault up to this limit.
+pub const USER_STACK_MAX_PAGE: usize = 256;
+
+/// Guard page below user stack, which is never mapped so that stack overflow faults.
+/// User programs and heap should be below it.
+pub const USER_STACK_BEGIN: usize = 0x80001000;
+
+/// Top of user stack
+pub const USER_STACK_TOP: usize = USER_STACK_BEGIN + PAGE_SIZE * (USER_STACK_MAX_PAGE + 1);
+
+/// Reserve user stack in `pgtable` above the guard page and returns `sp`.
+/// Stack pages are allocated on first access.
+pub fn map_stack(pgtable: &mut Table) -> usize {
+    for pg in (USER_STACK_BEGIN + PAGE_SIZE..USER_STACK_TOP).step_by(PAGE_SIZE) {
+        pgtable.reserve(pg, page::EntryAttributes::URW as usize);
     }
+    USER_STACK_TOP
+}
 
-    stack_begin + PAGE_SIZE * USER_STACK_PAGE
+/// Maximum number of arguments passed to `exec`
+pub const MAXARG: usize = 10;
+
+/// Maximum length of an argument passed to `exec`
+pub const MAXARGLEN: usize = 256;
+
+/// Copy `args` onto user stack whose top is `sp`, followed by `argv`,
+/// an array of (pointer, length) of each argument. Returns new `sp`,
+/// which is also the address of `argv`.
+fn push_args(pgtable: &mut Table, sp: usize, args: &[String]) -> usize {
+    // user stack has room for `MAXARG` arguments of `MAXARGLEN` bytes,
+    // so copying won't fail.
+    let mut sp = sp;
+    let mut argv = Vec::new();
+    for arg in args {
+        sp -= arg.len();
+        pgtable.copy_to_user(sp, arg.as_bytes()).unwrap();
+        argv.push((sp, arg.len()));
+    }
+    // stack pointer should be 16-byte aligned
+    sp = (sp - argv.len() * 16) & !0xf;
+    for (i, (ptr, len)) in argv.iter().enumerate() {
+        pgtable.copy_to_user(sp + i * 16, &ptr.to_ne_bytes()).unwrap();
+        pgtable.copy_to_user(sp + i * 16 + 8, &len.to_ne_bytes()).unwrap();
+    }
+    sp
 }
 
 /// exec syscall
-pub fn exec(path: &str) {
+///
+/// `args` are passed to the new program with `argc` in `a0`, and `argv` in `a1`.
+/// Returns `argc`, which ends up in `a0` as the return value of syscall.
+///
+/// Returns error of opening `path`, or `ENOEXEC` if it is not a valid executable.
+/// Current process image is kept on error.
+pub fn exec(path: &str, args: &[String]) -> Result<usize, Errno> {
     let p = my_proc();
     info!("loading elf {}", path);
-    let mut content: Box<[u8; 131072]> = box [0; 131072];
-    {
-        let f = FsFile::open(path, 0);
-        let mut blk = [0; 1024];
-        let mut i = 0;
-        while f.read(&mut blk) == 1024 {
-            // content[i..i + 1024].copy_from_slice(&blk);
-            for j in 0..1024 {
-                content[i + j] = blk[j];
-            }
-            i += 1024;
-            if i + 1024 >= content.len() {
-                panic!("elf file too large!");
-            }
-        }
-    }
-    info!("parsing...");
-    p.pgtable.unmap_user();
-    let entry = crate::elf::parse_elf(
-        &*content,
-        &mut p.pgtable,
-    );
-    info!("done");
-    // map user stack
-    let sp = map_stack(&mut p.pgtable, 0x80001000);
-    p.trapframe.epc = entry as usize;
+    let f = FsFile::open(path, O_RDONLY)?;
+    // build the new image in a new page table, so that current one
+    // is untouched if loading fails.
+    let mut pgtable = box Table::new();
+    map_kernel_pages(&mut pgtable, &p.trapframe);
+    let (entry, brk) = crate::elf::load_elf(&f, &mut pgtable)?;
+    let sp = map_stack(&mut pgtable);
+    let sp = push_args(&mut pgtable, sp, args);
+    // old page table and user pages are freed here
+    p.pgtable = pgtable;
+    p.brk = brk;
+    p.heap_start = brk;
+    p.trapframe.epc = entry;
     p.trapframe.regs[Register::sp as usize] = sp;
+    p.trapframe.regs[Register::a1 as usize] = sp;
+    p.set_name(path.rsplit('/').next().unwrap_or(path));
+    p.reset_signal_handlers();
+    Ok(args.len())
+}
+
+/// sbrk syscall
+///
+/// Grow or shrink heap of current process by `increment` bytes, and returns the old break.
+/// Pages are reserved `URW` as the break grows and allocated on first access,
+/// and are freed as it shrinks.
+/// Returns `ENOMEM` if the new break would be below start of heap, or run into the
+/// guard page of user stack.
+pub fn sbrk(increment: isize) -> Result<usize, Errno> {
+    let p = my_proc();
+    let old = p.brk;
+    let new = if increment >= 0 {
+        old.checked_add(increment as usize)
+    } else {
+        old.checked_sub(increment.wrapping_neg() as usize)
+    }.ok_or(Errno::ENOMEM)?;
+    if new < p.heap_start || new > USER_STACK_BEGIN {
+        return Err(Errno::ENOMEM);
+    }
+    let (old_end, new_end) = (mem::align_val(old, PAGE_ORDER), mem::align_val(new, PAGE_ORDER));
+    for pg in (old_end..new_end).step_by(PAGE_SIZE) {
+        p.pgtable.reserve(pg, EntryAttributes::URW as usize);
+    }
+    for pg in (new_end..old_end).step_by(PAGE_SIZE) {
+        p.pgtable.unmap(pg);
+    }
+    p.brk = new;
+    Ok(old)
 }
 
+/// Parent pid of every process, indexed by pid.
+///
+/// `None` means the slot is unused, or the process is init.
+///
+/// Parent of a process may be changed by another process when reparenting,
+/// and a running process can't be inspected from `PROCS_POOL`, so parents are
+/// recorded here instead of in `Process`. This lock also serves as the lock
+/// `wait` sleeps on, so that a child can't exit between its parent scanning
+/// for zombies and going to sleep.
+pub static PROCS_PARENT: Mutex<[Option<i32>; NMAXPROCS]> = Mutex::new([None; NMAXPROCS], "proc parent");
+
 /// exit syscall
+///
+/// All files are closed and all children are given to init. This process
+/// then becomes a zombie until its parent reaps it in `wait`.
 pub fn exit(status: i32) -> ! {
     {
         let p = my_proc();
         if p.pid == 0 {
             panic!("init exiting");
         }
+        for f in p.files.iter_mut() {
+            *f = None;
+        }
+        p.cwd = None;
+
+        let mut parents = PROCS_PARENT.lock();
+        // reparent orphans to init
+        let mut orphaned = false;
+        for i in 0..NMAXPROCS {
+            if parents[i] == Some(p.pid) {
+                parents[i] = Some(0);
+                orphaned = true;
+            }
+        }
+        if orphaned {
+            wakeup(&parents[0] as *const _);
+        }
+
+        p.exit_status = status;
         p.state = ProcessState::ZOMBIE;
+        let parent = parents[p.pid as usize].unwrap();
+        wakeup(&parents[parent as usize] as *const _);
+
+        // parent can't reap this process until it is put back into `PROCS_POOL`,
+        // and a zombie is never woken up by signals
+        if !mark_being_slept(p, false) {
+            panic!("exit: not being slept");
+        }
     }
     arch::intr_off();
     sched();
     unreachable!();
 }
 
+/// wait syscall
+///
+/// Wait for child `pid` to exit, or any child if `pid` is -1. The child is
+/// removed from `PROCS_POOL` and all its resources are freed.
+///
+/// Returns pid and exit status of the child, `ECHILD` if there is no such child,
+/// or `EINTR` if current process is interrupted by a signal while waiting.
+pub fn wait(pid: i32) -> Result<(i32, i32), Errno> {
+    let me = my_proc().pid;
+    let mut parents = PROCS_PARENT.lock();
+    loop {
+        let mut has_child = false;
+        {
+            let mut pool = PROCS_POOL.lock();
+            let mut i = 0;
+            while i < NMAXPROCS {
+                if parents[i] != Some(me) || (pid != -1 && pid != i as i32) {
+                    i += 1;
+                    continue;
+                }
+                has_child = true;
+                match &pool[i] {
+                    ProcInPool::Pooling(child) if child.state == ProcessState::ZOMBIE => {
+                        let child = core::mem::replace(&mut pool[i], ProcInPool::NoProc);
+                        parents[i] = None;
+                        if let ProcInPool::Pooling(child) = child {
+                            // page table, kernel stack and trapframe of child are dropped here
+                            return Ok((i as i32, child.exit_status));
+                        }
+                        unreachable!();
+                    }
+                    ProcInPool::BeingSlept => {
+                        // child may be exiting, wait until it is put back
+                        let weak_lock = pool.into_weak();
+                        PROCS_POOL_SLEEP.lock();
+                        pool = weak_lock.into_guard();
+                    }
+                    _ => { i += 1; }
+                }
+            }
+        }
+        if !has_child {
+            return Err(Errno::ECHILD);
+        }
+        if my_proc().interrupted() {
+            return Err(Errno::EINTR);
+        }
+        parents = sleep_interruptible(&parents[me as usize] as *const _, parents);
+    }
+}
+
+/// kill syscall
+///
+/// Send `sig` to process `pid`, and wake it up if it is sleeping. It is delivered
+/// when the process next returns to user space, see `signal` module. A process
+/// receiving `SIGKILL` exits with its status when it next traps into kernel or
+/// returns to user space.
+///
+/// Returns `EPERM` if `pid` is init, or `ESRCH` if there is no such process.
+pub fn kill(pid: i32, sig: Signal) -> Result<(), Errno> {
+    if pid == 0 {
+        return Err(Errno::EPERM);
+    }
+    if pid < 0 || pid as usize >= NMAXPROCS {
+        return Err(Errno::ESRCH);
+    }
+    let _sig_lock = SIGNAL_LOCK.lock();
+    let mut pool = PROCS_POOL.lock();
+    loop {
+        match &mut pool[pid as usize] {
+            ProcInPool::Pooling(p) => {
+                p.send_signal(sig);
+                if p.state == ProcessState::SLEEPING {
+                    p.state = ProcessState::RUNNABLE;
+                }
+                return Ok(());
+            }
+            ProcInPool::Scheduled => {
+                // process may be moving between `PROCS_POOL` and a hart, retry until it is found.
+                // It checks for signals with `PROCS_POOL` locked before sleeping interruptibly,
+                // so the signal is not lost if it is about to sleep.
+                if let Some(p) = running_proc(pid) {
+                    p.send_signal(sig);
+                    return Ok(());
+                }
+                let weak_lock = pool.into_weak();
+                pool = weak_lock.into_guard();
+            }
+            ProcInPool::BeingSlept => {
+                // wait until it is put back, so that it can be woken up
+                let weak_lock = pool.into_weak();
+                PROCS_POOL_SLEEP.lock();
+                pool = weak_lock.into_guard();
+            }
+            ProcInPool::NoProc => return Err(Errno::ESRCH)
+        }
+    }
+}
+
+/// Exit current process with status of `SIGKILL` if it is killed by `SIGKILL`
+pub fn exit_if_killed() {
+    if my_proc().killed {
+        exit(Signal::SIGKILL.exit_status());
+    }
+}
 
 /// A Mutex that will be locked if a process is being slept but not yet put back into `PROCS_POOL`.
 pub static PROCS_POOL_SLEEP: Mutex<()> = Mutex::new((), "proc pool sleep");
 
+/// Set `p` in `PROCS_POOL` as being slept and hold `PROCS_POOL_SLEEP` until
+/// it is put back, avoiding lost-wakeup issue.
+///
+/// If `interruptible` is set and `p` is interrupted by a signal, `p` is not
+/// marked and false is returned.
+fn mark_being_slept(p: &mut Process, interruptible: bool) -> bool {
+    {
+        let mut pool = PROCS_POOL.lock();
+        // `kill` signals a scheduled process with `PROCS_POOL` locked, so a signal sent
+        // before this check is seen here, and a signal sent after it finds the process
+        // being slept, and wakes it up after it is put back.
+        if interruptible && p.interrupted() {
+            return false;
+        }
+        let p_in_pool = &mut pool[p.pid as usize];
+        match p_in_pool {
+            ProcInPool::Scheduled => {}
+            _ => panic!("invalid proc pool state")
+        }
+        *p_in_pool = ProcInPool::BeingSlept;
+    }
+    p.drop_on_put_back = Some(PROCS_POOL_SLEEP.lock());
+    true
+}
+
 /// put this process into sleep state
 ///
 /// `channel` is an identifier of sleep lock channel. `wakeup` should be called with the same
@@ -242,21 +549,28 @@ pub static PROCS_POOL_SLEEP: Mutex<()> = Mutex::new((), "proc pool sleep");
 /// To avoid the lost wakeup issue, process must hold a global lock `PROCS_POOL_SLEEP`.
 /// This lock will be dropped after the process is put back into process pool.
 pub fn sleep<T, U>(channel: *const T, lck: MutexGuard<U>) -> MutexGuard<U> {
+    sleep_on(channel, lck, false)
+}
+
+/// put this process into sleep state unless it is interrupted by a signal
+///
+/// Same as `sleep`, but returns immediately if current process is killed or has
+/// a pending signal, which is checked atomically with going to sleep. Caller
+/// should check `interrupted` before calling it and after it returns.
+pub fn sleep_interruptible<T, U>(channel: *const T, lck: MutexGuard<U>) -> MutexGuard<U> {
+    sleep_on(channel, lck, true)
+}
+
+fn sleep_on<T, U>(channel: *const T, lck: MutexGuard<U>, interruptible: bool) -> MutexGuard<U> {
     let p = my_proc();
     p.channel = channel as *const _ as usize;
     p.state = ProcessState::SLEEPING;
 
-    // set proc in proc pool as being slept, avoiding lost-wakeup issue
-    {
-        let mut pool = PROCS_POOL.lock();
-        let p_in_pool = &mut pool[p.pid as usize];
-        match p_in_pool {
-            ProcInPool::Scheduled => {}
-            _ => panic!("invalid proc pool state")
-        }
-        *p_in_pool = ProcInPool::BeingSlept;
+    if !mark_being_slept(p, interruptible) {
+        p.channel = 0;
+        p.state = ProcessState::RUNNING;
+        return lck;
     }
-    p.drop_on_put_back = Some(PROCS_POOL_SLEEP.lock());
 
     // temporarily unlock spinlock
     let weak_lock = lck.into_weak();
@@ -0,0 +1,346 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Signals
+//!
+//! A signal sent by `kill` is recorded as pending in the target process, and
+//! is delivered when the process next returns to user space. Depending on
+//! its action, a signal is ignored, terminates or stops the process, or
+//! runs a handler in user space.
+//!
+//! To run a handler, a `SigFrame` holding user registers is pushed onto user
+//! stack, and the process returns to handler with signal number in `a0`. The
+//! handler returns to its restorer, which calls `sigreturn` to restore registers
+//! from the frame.
+//!
+//! A process terminated by signal exits with `128 + signal`, as shells report it.
+//! Signal numbers follow Linux, and are mirrored in
+//! [signal module in user crate](../../user/signal/index.html).
+
+use crate::process::{Process, Register, my_proc, exit, sleep};
+use crate::spinlock::Mutex;
+use crate::error::Errno;
+use crate::fs::{as_bytes, as_bytes_mut};
+use core::sync::atomic::Ordering;
+use core::mem::size_of;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Signal {
+    /// Hangup
+    SIGHUP = 1,
+    /// Interrupt
+    SIGINT = 2,
+    /// Quit
+    SIGQUIT = 3,
+    /// Illegal instruction
+    SIGILL = 4,
+    /// Breakpoint
+    SIGTRAP = 5,
+    /// Abort
+    SIGABRT = 6,
+    /// Misaligned memory access
+    SIGBUS = 7,
+    /// Arithmetic error
+    SIGFPE = 8,
+    /// Kill, which can't be caught or blocked
+    SIGKILL = 9,
+    /// User-defined signal 1
+    SIGUSR1 = 10,
+    /// Invalid memory access
+    SIGSEGV = 11,
+    /// User-defined signal 2
+    SIGUSR2 = 12,
+    /// Write to pipe with no readers
+    SIGPIPE = 13,
+    /// Alarm clock
+    SIGALRM = 14,
+    /// Termination
+    SIGTERM = 15,
+    /// Child stopped or exited
+    SIGCHLD = 17,
+    /// Continue if stopped
+    SIGCONT = 18,
+    /// Stop, which can't be caught or blocked
+    SIGSTOP = 19,
+    /// Stop from terminal
+    SIGTSTP = 20,
+}
+
+/// Number of signal numbers, including the unused 0
+pub const NSIG: usize = 32;
+
+const SIGNALS: [Signal; 19] = [
+    Signal::SIGHUP, Signal::SIGINT, Signal::SIGQUIT, Signal::SIGILL, Signal::SIGTRAP,
+    Signal::SIGABRT, Signal::SIGBUS, Signal::SIGFPE, Signal::SIGKILL, Signal::SIGUSR1,
+    Signal::SIGSEGV, Signal::SIGUSR2, Signal::SIGPIPE, Signal::SIGALRM, Signal::SIGTERM,
+    Signal::SIGCHLD, Signal::SIGCONT, Signal::SIGSTOP, Signal::SIGTSTP,
+];
+
+/// Signals whose action can't be changed, and which can't be blocked
+const UNCATCHABLE: u32 = Signal::SIGKILL.bit() | Signal::SIGSTOP.bit();
+
+/// Signals which stop a process by default
+const STOP_SIGNALS: u32 = Signal::SIGSTOP.bit() | Signal::SIGTSTP.bit();
+
+/// Action taken when a signal with `SIG_DFL` is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+impl Signal {
+    /// Get signal numbered `signum`
+    pub fn from_signum(signum: i32) -> Option<Self> {
+        SIGNALS.iter().copied().find(|sig| *sig as i32 == signum)
+    }
+
+    /// Bit of this signal in a signal mask
+    pub const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    /// Exit status of a process terminated by this signal
+    pub fn exit_status(self) -> i32 {
+        128 + self as i32
+    }
+
+    pub fn default_action(self) -> DefaultAction {
+        match self {
+            Signal::SIGCHLD => DefaultAction::Ignore,
+            Signal::SIGCONT => DefaultAction::Continue,
+            Signal::SIGSTOP | Signal::SIGTSTP => DefaultAction::Stop,
+            _ => DefaultAction::Terminate
+        }
+    }
+}
+
+/// Handler which takes default action of signal
+pub const SIG_DFL: usize = 0;
+/// Handler which ignores signal
+pub const SIG_IGN: usize = 1;
+
+/// Action of a signal, which is passed from user space in `sigaction` syscall
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN`, or address of handler in user space
+    pub handler: usize,
+    /// address which handler returns to, which should call `sigreturn`
+    pub restorer: usize,
+    /// signals blocked while handler is running, in addition to the signal itself
+    pub mask: u32,
+}
+
+impl SigAction {
+    pub const DEFAULT: Self = SigAction { handler: SIG_DFL, restorer: 0, mask: 0 };
+
+    /// Whether delivering `sig` with this action does nothing
+    fn ignores(&self, sig: Signal) -> bool {
+        match self.handler {
+            SIG_IGN => true,
+            SIG_DFL => match sig.default_action() {
+                DefaultAction::Ignore | DefaultAction::Continue => true,
+                _ => false
+            },
+            _ => false
+        }
+    }
+}
+
+/// Frame pushed onto user stack before running a handler
+#[repr(C)]
+struct SigFrame {
+    /// user registers when signal is delivered
+    regs: [usize; 32],
+    /// user pc when signal is delivered
+    epc: usize,
+    /// blocked signals before handler runs
+    blocked: u32,
+    /// signal number
+    sig: u32,
+}
+
+/// Lock held while sending a signal, so that a stopped process won't miss
+/// `SIGCONT` between checking for it and going to sleep.
+pub static SIGNAL_LOCK: Mutex<()> = Mutex::new((), "signal");
+
+impl Process {
+    /// Mark `sig` as pending. Pending stop signals are discarded by `SIGCONT`, and vice versa.
+    /// `SIGKILL` kills the process directly.
+    ///
+    /// Caller should hold `SIGNAL_LOCK`, and wake up the process if it is sleeping.
+    pub fn send_signal(&mut self, sig: Signal) {
+        if sig == Signal::SIGKILL {
+            self.killed = true;
+            return;
+        }
+        if sig == Signal::SIGCONT {
+            self.sig_pending.fetch_and(!STOP_SIGNALS, Ordering::SeqCst);
+        } else if STOP_SIGNALS & sig.bit() != 0 {
+            self.sig_pending.fetch_and(!Signal::SIGCONT.bit(), Ordering::SeqCst);
+        }
+        self.sig_pending.fetch_or(sig.bit(), Ordering::SeqCst);
+    }
+
+    /// Whether `sig` would run a handler if it is sent to this process
+    pub fn catches(&self, sig: Signal) -> bool {
+        let handler = self.sig_actions[sig as usize].handler;
+        self.sig_blocked & sig.bit() == 0 && handler != SIG_DFL && handler != SIG_IGN
+    }
+
+    /// Next pending signal which is not blocked
+    fn next_signal(&self) -> Option<Signal> {
+        let pending = self.sig_pending.load(Ordering::SeqCst) & !self.sig_blocked;
+        if pending == 0 {
+            None
+        } else {
+            Signal::from_signum(pending.trailing_zeros() as i32)
+        }
+    }
+
+    /// Whether this process should be interrupted from sleeping, because it is
+    /// killed or there's a pending signal which will not be ignored.
+    pub fn interrupted(&self) -> bool {
+        if self.killed {
+            return true;
+        }
+        let pending = self.sig_pending.load(Ordering::SeqCst) & !self.sig_blocked;
+        SIGNALS.iter().any(|sig| {
+            pending & sig.bit() != 0 && !self.sig_actions[*sig as usize].ignores(*sig)
+        })
+    }
+
+    /// Reset handlers to `SIG_DFL` in `exec`, as they are not in the new program.
+    /// Ignored signals stay ignored.
+    pub fn reset_signal_handlers(&mut self) {
+        for act in self.sig_actions.iter_mut() {
+            if act.handler != SIG_IGN {
+                *act = SigAction::DEFAULT;
+            }
+        }
+    }
+}
+
+/// Stop current process until it is continued by `SIGCONT` or killed
+fn stop(p: &mut Process) {
+    let mut lock = SIGNAL_LOCK.lock();
+    while !p.killed && p.sig_pending.load(Ordering::SeqCst) & Signal::SIGCONT.bit() == 0 {
+        lock = sleep(&p.sig_pending as *const _, lock);
+    }
+}
+
+/// Push a `SigFrame` onto user stack and make current process run handler of `sig`
+/// when it returns to user space. Returns `EFAULT` if user stack is not writable.
+fn setup_frame(p: &mut Process, sig: Signal) -> Result<(), Errno> {
+    let act = p.sig_actions[sig as usize];
+    let frame = SigFrame {
+        regs: p.trapframe.regs,
+        epc: p.trapframe.epc,
+        blocked: p.sig_blocked,
+        sig: sig as u32,
+    };
+    let sp = p.trapframe.regs[Register::sp as usize];
+    let sp = sp.checked_sub(size_of::<SigFrame>()).ok_or(Errno::EFAULT)? & !0xf;
+    p.pgtable.copy_to_user(sp, as_bytes(&frame))?;
+    p.sig_blocked |= (act.mask | sig.bit()) & !UNCATCHABLE;
+    let tf = &mut p.trapframe;
+    tf.regs[Register::sp as usize] = sp;
+    tf.regs[Register::ra as usize] = act.restorer;
+    tf.regs[Register::a0 as usize] = sig as usize;
+    tf.regs[Register::a1 as usize] = sp;
+    tf.epc = act.handler;
+    Ok(())
+}
+
+/// Deliver pending signals of current process before it returns to user space.
+///
+/// Signals with default action terminate or stop the process, or are discarded.
+/// At most one handler is set up each time. Returns early if the process is killed.
+pub fn deliver() {
+    let p = my_proc();
+    while !p.killed {
+        let sig = match p.next_signal() {
+            Some(sig) => sig,
+            None => return
+        };
+        p.sig_pending.fetch_and(!sig.bit(), Ordering::SeqCst);
+        let act = p.sig_actions[sig as usize];
+        match act.handler {
+            SIG_IGN => {}
+            SIG_DFL => match sig.default_action() {
+                DefaultAction::Terminate => exit(sig.exit_status()),
+                DefaultAction::Stop => stop(p),
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+            },
+            _ => {
+                if setup_frame(p, sig).is_err() {
+                    // handler can't run without a stack
+                    exit(Signal::SIGSEGV.exit_status());
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// sigaction syscall
+///
+/// Set action of `signum` to `act` if it is given, and returns the previous action.
+/// Returns `EINVAL` if `signum` is invalid, or is `SIGKILL` or `SIGSTOP` while setting action.
+pub fn sigaction(signum: i32, act: Option<SigAction>) -> Result<SigAction, Errno> {
+    let sig = Signal::from_signum(signum).ok_or(Errno::EINVAL)?;
+    let p = my_proc();
+    let old = p.sig_actions[sig as usize];
+    if let Some(act) = act {
+        if UNCATCHABLE & sig.bit() != 0 {
+            return Err(Errno::EINVAL);
+        }
+        p.sig_actions[sig as usize] = act;
+    }
+    Ok(old)
+}
+
+/// Block signals in `set`
+pub const SIG_BLOCK: i32 = 0;
+/// Unblock signals in `set`
+pub const SIG_UNBLOCK: i32 = 1;
+/// Block exactly signals in `set`
+pub const SIG_SETMASK: i32 = 2;
+
+/// sigprocmask syscall
+///
+/// Change blocked signals of current process according to `how`, and returns
+/// the previous mask. `SIGKILL` and `SIGSTOP` are never blocked.
+pub fn sigprocmask(how: i32, set: u32) -> Result<u32, Errno> {
+    let p = my_proc();
+    let old = p.sig_blocked;
+    p.sig_blocked = match how {
+        SIG_BLOCK => old | set,
+        SIG_UNBLOCK => old & !set,
+        SIG_SETMASK => set,
+        _ => return Err(Errno::EINVAL)
+    } & !UNCATCHABLE;
+    Ok(old)
+}
+
+/// sigreturn syscall
+///
+/// Restore registers and blocked signals from the `SigFrame` at user `sp`, which is
+/// where the handler returns. Returns the restored `a0`, so that it is not
+/// overwritten by the return value of this syscall.
+pub fn sigreturn() -> Result<usize, Errno> {
+    let p = my_proc();
+    let mut frame = SigFrame { regs: [0; 32], epc: 0, blocked: 0, sig: 0 };
+    let sp = p.trapframe.regs[Register::sp as usize];
+    p.pgtable.copy_from_user(as_bytes_mut(&mut frame), sp)?;
+    p.trapframe.regs = frame.regs;
+    p.trapframe.epc = frame.epc;
+    p.sig_blocked = frame.blocked & !UNCATCHABLE;
+    Ok(frame.regs[Register::a0 as usize])
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Slab allocator for small kernel objects
+//!
+//! Objects of 8 bytes to 2 KiB are rounded up to power-of-two size classes.
+//! Objects of a class are carved from pages taken from frame allocator, so
+//! an object is always aligned to its size. Larger objects, and objects with
+//! an alignment larger than 2 KiB, are allocated by frame allocator directly.
+//!
+//! Every hart caches some free objects of each class, which are allocated
+//! and freed without locking. When a cache runs empty or full, a batch of
+//! objects is moved from or to the depot shared by all harts. Pages of slabs
+//! are never given back to frame allocator.
+//!
+//! In debug build, free objects are poisoned. Freeing an object twice, or
+//! writing to an object after it is freed, is detected and panics.
+
+use core::alloc::Layout;
+use crate::mem::ALLOC;
+use crate::symbols::{PAGE_SIZE, NCPUS};
+use crate::spinlock::Mutex;
+use crate::process::my_cpu;
+use crate::arch::hart_id;
+use crate::{panic, println};
+
+/// Order of the smallest class
+const MIN_ORDER: usize = 3;
+
+/// Order of the largest class
+const MAX_ORDER: usize = 11;
+
+/// Number of size classes
+const NCLASSES: usize = MAX_ORDER - MIN_ORDER + 1;
+
+/// Smallest object. In debug build, a free object holds a magic number
+/// in addition to the link of free list.
+#[cfg(debug_assertions)]
+const MIN_SIZE: usize = 16;
+#[cfg(not(debug_assertions))]
+const MIN_SIZE: usize = 1 << MIN_ORDER;
+
+/// Maximum number of free objects of a class in each hart cache
+const CPU_CACHE_SIZE: usize = 32;
+
+/// Number of objects moved between hart cache and depot at a time
+const BATCH: usize = CPU_CACHE_SIZE / 2;
+
+/// Link of free list, stored at the start of every free object
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// A list of free objects of the same class
+#[derive(Clone, Copy)]
+struct FreeList {
+    head: *mut FreeObject,
+    len: usize,
+}
+
+impl FreeList {
+    const fn new() -> Self {
+        FreeList { head: core::ptr::null_mut(), len: 0 }
+    }
+
+    fn push(&mut self, obj: *mut FreeObject) {
+        unsafe { (*obj).next = self.head; }
+        self.head = obj;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<*mut FreeObject> {
+        if self.head.is_null() {
+            return None;
+        }
+        let obj = self.head;
+        self.head = unsafe { (*obj).next };
+        self.len -= 1;
+        Some(obj)
+    }
+
+    /// Move `n` objects to `other`
+    fn move_to(&mut self, other: &mut FreeList, n: usize) {
+        for _ in 0..n {
+            match self.pop() {
+                Some(obj) => other.push(obj),
+                None => return
+            }
+        }
+    }
+}
+
+/// Free objects shared by all harts
+struct Depot {
+    lists: [FreeList; NCLASSES],
+    /// Number of pages taken from frame allocator of every class
+    pages: [usize; NCLASSES],
+}
+
+// free objects are only accessed with depot locked
+unsafe impl Send for Depot {}
+
+impl Depot {
+    const fn new() -> Self {
+        Depot {
+            lists: [FreeList::new(); NCLASSES],
+            pages: [0; NCLASSES],
+        }
+    }
+
+    /// Carve a new page into free objects of `class`
+    fn grow(&mut self, class: usize) {
+        let size = size_of_class(class);
+        let page = ALLOC().lock().allocate(PAGE_SIZE) as usize;
+        unsafe { core::ptr::write_bytes(page as *mut u8, 0, PAGE_SIZE); }
+        for obj in (page..page + PAGE_SIZE).step_by(size) {
+            unsafe { poison_free(obj as *mut u8, size); }
+            self.lists[class].push(obj as *mut FreeObject);
+        }
+        self.pages[class] += 1;
+    }
+}
+
+static DEPOT: Mutex<Depot> = Mutex::new(Depot::new(), "slab depot");
+
+/// Free objects cached by a hart, which are only accessed on that hart
+/// with interrupt disabled
+#[derive(Clone, Copy)]
+struct CpuCache {
+    lists: [FreeList; NCLASSES],
+}
+
+static mut CPU_CACHES: [CpuCache; NCPUS] = [CpuCache { lists: [FreeList::new(); NCLASSES] }; NCPUS];
+
+/// Size class of `layout`, or `None` if it should be allocated by frame allocator
+fn class_of(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_SIZE).next_power_of_two();
+    let order = size.trailing_zeros() as usize;
+    if order > MAX_ORDER {
+        None
+    } else {
+        Some(order - MIN_ORDER)
+    }
+}
+
+const fn size_of_class(class: usize) -> usize {
+    1 << (class + MIN_ORDER)
+}
+
+/// Allocate memory for `layout`
+pub unsafe fn alloc(layout: Layout) -> *mut u8 {
+    let class = match class_of(&layout) {
+        Some(class) => class,
+        None => return ALLOC().lock().allocate_aligned(layout.size(), layout.align())
+    };
+    let _intr_lock = my_cpu().intr_lock.lock();
+    let cache = &mut CPU_CACHES[hart_id()].lists[class];
+    if cache.len == 0 {
+        let mut depot = DEPOT.lock();
+        if depot.lists[class].len < BATCH {
+            depot.grow(class);
+        }
+        depot.lists[class].move_to(cache, BATCH);
+    }
+    let obj = cache.pop().unwrap() as *mut u8;
+    check_poison(obj, size_of_class(class));
+    obj
+}
+
+/// Free memory at `ptr` allocated for `layout`
+pub unsafe fn dealloc(ptr: *mut u8, layout: Layout) {
+    let class = match class_of(&layout) {
+        Some(class) => class,
+        None => return ALLOC().lock().deallocate(ptr)
+    };
+    poison_free(ptr, size_of_class(class));
+    let _intr_lock = my_cpu().intr_lock.lock();
+    let cache = &mut CPU_CACHES[hart_id()].lists[class];
+    cache.push(ptr as *mut FreeObject);
+    if cache.len > CPU_CACHE_SIZE {
+        cache.move_to(&mut DEPOT.lock().lists[class], BATCH);
+    }
+}
+
+/// Magic number at the second word of a free object
+#[cfg(debug_assertions)]
+const FREE_MAGIC: usize = 0x5ab_f7ee_5ab_f7ee;
+
+/// Byte filled in the rest of a free object
+#[cfg(debug_assertions)]
+const POISON_FREE: u8 = 0x6b;
+
+/// Byte filled in a newly allocated object
+#[cfg(debug_assertions)]
+const POISON_ALLOC: u8 = 0xa5;
+
+/// Mark object at `obj` of `size` bytes as free. Panics if it is already free.
+#[cfg(debug_assertions)]
+unsafe fn poison_free(obj: *mut u8, size: usize) {
+    let magic = (obj as *mut usize).add(1);
+    if *magic == FREE_MAGIC {
+        panic!("slab: double free of {:x}", obj as usize);
+    }
+    *magic = FREE_MAGIC;
+    core::ptr::write_bytes(obj.add(16), POISON_FREE, size - 16);
+}
+
+#[cfg(not(debug_assertions))]
+unsafe fn poison_free(_obj: *mut u8, _size: usize) {}
+
+/// Check that free object at `obj` of `size` bytes is not modified,
+/// and poison it for allocation.
+#[cfg(debug_assertions)]
+unsafe fn check_poison(obj: *mut u8, size: usize) {
+    let magic = *(obj as *const usize).add(1);
+    let data = core::slice::from_raw_parts(obj.add(16), size - 16);
+    if magic != FREE_MAGIC || data.iter().any(|b| *b != POISON_FREE) {
+        panic!("slab: object {:x} of {} bytes modified after free", obj as usize, size);
+    }
+    core::ptr::write_bytes(obj, POISON_ALLOC, size);
+}
+
+#[cfg(not(debug_assertions))]
+unsafe fn check_poison(_obj: *mut u8, _size: usize) {}
+
+/// Print pages and free objects in depot of every class
+pub fn debug() {
+    let depot = DEPOT.lock();
+    for class in 0..NCLASSES {
+        println!("{:4} bytes: {} pages, {} free in depot",
+                 size_of_class(class), depot.pages[class], depot.lists[class].len);
+    }
+}
+
+pub mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use crate::mem::page_down;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("small objects", test_small_objects),
+            ("alignment", test_alignment),
+        ]
+    }
+
+    /// Test that small objects share pages
+    pub fn test_small_objects() {
+        let objs: Vec<Box<[u8; 32]>> = (0..16).map(|i| Box::new([i; 32])).collect();
+        let mut pages: Vec<usize> = objs.iter().map(|obj| page_down(&**obj as *const _ as usize)).collect();
+        pages.sort();
+        pages.dedup();
+        assert!(pages.len() < objs.len());
+        for (i, obj) in objs.iter().enumerate() {
+            assert!(obj.iter().all(|b| *b == i as u8));
+        }
+    }
+
+    /// Test that alignment of layout is honoured
+    pub fn test_alignment() {
+        for (size, align) in &[(8, 8), (24, 64), (100, 512), (16, 4096), (3000, 8), (4096, PAGE_SIZE * 4)] {
+            let layout = Layout::from_size_align(*size, *align).unwrap();
+            unsafe {
+                let ptr = alloc(layout);
+                assert_eq!(ptr as usize % align, 0);
+                core::ptr::write_bytes(ptr, 0, *size);
+                dealloc(ptr, layout);
+            }
+        }
+    }
+}
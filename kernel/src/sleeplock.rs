@@ -8,6 +8,8 @@
 use crate::spinlock::{Mutex, MutexGuard};
 use crate::process::{sleep, my_proc, wakeup};
 use crate::info;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
 
 /// locked, pid
 struct SleepLockInfo {
@@ -21,46 +23,80 @@ impl SleepLockInfo {
     }
 }
 
-pub struct SleepLock {
+/// A lock which may be held across disk operations and other sleeps.
+///
+/// Unlike `Mutex`, holding a `SleepLock` doesn't disable interrupt,
+/// so it may only be used in process context.
+pub struct SleepLock<T: ?Sized> {
     spin: Mutex<SleepLockInfo>,
     name: &'static str,
+    data: UnsafeCell<T>,
 }
 
-pub struct SleepLockGuard<'a> {
-    lock: &'a SleepLock,
+/// A guard to which the protected data can be accessed
+///
+/// When the guard falls out of scope it will release the lock and
+/// wake up processes waiting for it.
+pub struct SleepLockGuard<'a, T: ?Sized + 'a> {
+    lock: &'a SleepLock<T>,
+    data: &'a mut T,
 }
 
-impl SleepLock {
-    pub const fn new(name: &'static str) -> Self {
+unsafe impl<T: ?Sized + Send> Sync for SleepLock<T> {}
+
+unsafe impl<T: ?Sized + Send> Send for SleepLock<T> {}
+
+impl<T> SleepLock<T> {
+    pub const fn new(data: T, name: &'static str) -> Self {
         Self {
             spin: Mutex::new(SleepLockInfo::new(false, 0), "sleep lock"),
             name,
+            data: UnsafeCell::new(data),
         }
     }
+}
 
-    pub fn acquire(&mut self) -> SleepLockGuard {
+impl<T: ?Sized> SleepLock<T> {
+    /// Lock and return a guard, sleep if the lock is held by others
+    pub fn lock(&self) -> SleepLockGuard<T> {
         let mut lk = self.spin.lock();
         while lk.locked {
-            lk = sleep(&self, lk);
+            lk = sleep(self as *const _ as *const u8, lk);
         }
         lk.locked = true;
         lk.pid = my_proc().pid;
         SleepLockGuard {
-            lock: self
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
         }
     }
 
+    /// Test if lock is held by current process
     pub fn holding(&self) -> bool {
         let lk = self.spin.lock();
         lk.locked && lk.pid == my_proc().pid
     }
+
+    /// Directly get data regardless whether it is locked or not
+    pub unsafe fn get(&self) -> &mut T {
+        &mut *self.data.get()
+    }
+}
+
+impl<'a, T: ?Sized> Deref for SleepLockGuard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T { &*self.data }
+}
+
+impl<'a, T: ?Sized> DerefMut for SleepLockGuard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T { &mut *self.data }
 }
 
-impl Drop for SleepLockGuard<'_> {
+impl<T: ?Sized> Drop for SleepLockGuard<'_, T> {
     fn drop(&mut self) {
         let mut lk = self.lock.spin.lock();
         lk.locked = false;
         lk.pid = 0;
-        wakeup(&self.lock);
+        wakeup(self.lock as *const _ as *const u8);
     }
 }
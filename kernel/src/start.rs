@@ -7,14 +7,28 @@
 
 use riscv::{asm, register::*};
 use crate::arch::{hart_id, wait_forever};
-use crate::{clint, plic, mem, uart, process, spinlock, trap, virtio};
-use crate::info;
+use crate::{clint, plic, mem, uart, process, spinlock, trap, virtio, fs, fdt};
+use crate::arch::__sync_synchronize;
+use crate::{info, warn, panic};
 use crate::jump::*;
 
+/// Set when boot hart has read device tree
+static mut FDT_READY: bool = false;
+
 /// Initialize kernel page table and drivers in machine mode,
 /// and prepare to switch to supervisor mode
+///
+/// `fdt` is address of device tree blob, which is read by boot hart.
 #[no_mangle]
-unsafe extern "C" fn kinit() {
+unsafe extern "C" fn kinit(fdt: usize) {
+    if mhartid::read() == 0 {
+        fdt::init(fdt);
+        __sync_synchronize();
+        FDT_READY = true;
+    } else {
+        while !core::ptr::read_volatile(&FDT_READY) {}
+        __sync_synchronize();
+    }
     // next mode is supervisor mode
     mstatus::set_mpp(mstatus::MPP::Supervisor);
     // mret jump to kmain
@@ -29,8 +43,10 @@ unsafe extern "C" fn kinit() {
     // save cpuid to tp
     asm!("csrr a1, mhartid");
     asm!("mv tp, a1");
-    // set up timer interrupt
-    clint::timer_init();
+    // set up timer interrupt, except on harts to be parked in `kmain`
+    if mhartid::read() < fdt::machine().ncpus {
+        clint::timer_init();
+    }
     // switch to supervisor mode
     asm!("mret");
 }
@@ -45,10 +61,21 @@ extern "C" fn kmain() -> ! {
         unsafe { uart::init(); }
         info!("booting core-os on hart {}...", hart_id());
         info!("  UART... \x1b[0;32minitialized\x1b[0m");
+        if fdt::machine().from_fdt {
+            info!("  device tree... \x1b[0;32mparsed\x1b[0m");
+        } else if let Some(err) = &fdt::machine().fdt_error {
+            warn!("  device tree... rejected ({:?}), assuming QEMU virt", err);
+        } else {
+            info!("  device tree... \x1b[0;33mnot found, assuming QEMU virt\x1b[0m");
+        }
         unsafe { mem::init(); }
         info!("  kernel page table... \x1b[0;32minitialized\x1b[0m");
         unsafe { virtio::init(); }
         info!("  virt-io... \x1b[0;32minitialized\x1b[0m");
+        if let Err(err) = unsafe { fs::recover(fs::ROOTDEV) } {
+            panic!("cannot recover root file system: {:?}", err);
+        }
+        info!("  file system log... \x1b[0;32mrecovered\x1b[0m");
         unsafe { plic::init(); }
         info!("  PLIC... \x1b[0;32minitialized\x1b[0m");
         mem::hartinit();
@@ -71,6 +98,10 @@ extern "C" fn kmain() -> ! {
                 break;
             }
         }
+        if hart_id() >= fdt::machine().ncpus {
+            warn!("hart {} is not in use, parking", hart_id());
+            wait_forever();
+        }
         info!("hart {} booting", hart_id());
         mem::hartinit();
         unsafe { trap::hartinit(); }
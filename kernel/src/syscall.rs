@@ -10,6 +10,9 @@
 //! Rust primitives and call corresponding functions
 //! with these parameters in kernel code.
 //!
+//! Syscall handlers return `Result`. On error, `-errno` is
+//! returned to user space, see `error` module.
+//!
 //! For specifications and how to do syscalls, refer to
 //! [syscall module in user crate](../../user/syscall/index.html).
 
@@ -17,13 +20,18 @@ mod gen;
 mod file;
 
 pub use gen::*;
-use crate::process::{TrapFrame, Register, my_proc, fork, exec, exit, Process};
+use crate::process::{TrapFrame, Register, my_proc, fork, exec, exit, wait, kill, sbrk, proc_info, Process, ProcInfo, MAXARG, MAXARGLEN};
 use crate::{info, panic, print, println};
 use crate::page;
-use crate::mem::{align_val, page_down};
-use crate::symbols::{PAGE_ORDER, PAGE_SIZE};
+use crate::error::Errno;
+use crate::fs::{as_bytes, as_bytes_mut};
+use crate::signal::{self, Signal, SigAction};
+use core::mem::size_of;
 use file::*;
 use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
 use crate::file::File;
 use alloc::boxed::Box;
 use crate::spinlock::Mutex;
@@ -46,70 +54,79 @@ pub fn arg_int(tf: &TrapFrame, pos: usize) -> i32 {
     argraw(tf, pos) as i32
 }
 
-/// Get the `pos`th argument as usize from syscall
-pub fn arg_uint(tf: &TrapFrame, pos: usize) -> usize {
+/// Get the `pos`th argument as usize from syscall, returns `EINVAL` if it is negative
+pub fn arg_uint(tf: &TrapFrame, pos: usize) -> Result<usize, Errno> {
     let sz = argraw(tf, pos) as i32;
     if sz < 0 {
-        panic!("invalid unsigned int");
+        return Err(Errno::EINVAL);
     }
-    sz as usize
+    Ok(sz as usize)
 }
 
-/// Get the `pos`th argument as a pointer from syscall, return kernel-space pointer (involve security issues!)
-pub fn arg_ptr(pgtable: &page::Table, tf: &TrapFrame, pos: usize, sz: usize) -> *const u8 {
+/// Maximum length of a path passed to syscall
+pub const MAXPATH: usize = 128;
+
+/// Get the `pos`th argument as a pointer to string and the `pos + 1`th argument as its length
+/// from syscall, and copy the string from user space
+pub fn arg_str(pgtable: &mut page::Table, tf: &TrapFrame, pos: usize) -> Result<String, Errno> {
     let ptr = argraw(tf, pos);
-    let pg_begin = page_down(ptr);
-    if ptr + sz >= pg_begin + PAGE_SIZE {
-        panic!("out of bound!");
+    let sz = arg_uint(tf, pos + 1)?;
+    if sz > MAXPATH {
+        return Err(Errno::ENAMETOOLONG);
     }
-    let paddr = pgtable.paddr_of(pg_begin).unwrap();
-    unsafe { (paddr as *const u8).add(ptr - pg_begin) }
-}
-
-/// Get the `pos`th argument as a pointer from syscall, return kernel-space mutable pointer (involve security issues!)
-pub fn arg_ptr_mut(pgtable: &page::Table, tf: &TrapFrame, pos: usize, sz: usize) -> *mut u8 {
-    arg_ptr(pgtable, tf, pos, sz) as *mut u8
+    Ok(pgtable.copy_str_from_user(ptr, sz, MAXPATH)?)
 }
 
-
-/// Get file corresponding to a file descriptor
-pub fn arg_fd(p: &Process, pos: usize) -> &Arc<File> {
+/// Get file corresponding to a file descriptor, returns `EBADF` if it is not opened
+pub fn arg_fd(p: &Process, pos: usize) -> Result<&Arc<File>, Errno> {
     let fd = argraw(&p.trapframe, pos);
-    match &p.files[fd] {
-        Some(x) => return x,
-        None => panic!("invalid file handler {}", fd)
+    match p.files.get(fd) {
+        Some(Some(x)) => Ok(x),
+        _ => Err(Errno::EBADF)
     }
 }
 
 /// fork syscall entry
-fn sys_fork() -> i32 {
-    fork()
+fn sys_fork() -> Result<usize, Errno> {
+    fork().map(|pid| pid as usize)
 }
 
 /// exec syscall entry
-fn sys_exec() -> i32 {
-    let path;
-    {
-        let p = my_proc();
-        let sz = arg_uint(&p.trapframe, 1);
-        let ptr = arg_ptr(&p.pgtable, &p.trapframe, 0, sz);
-        path = unsafe {
-            // First, we build a &[u8]...
-            let slice = core::slice::from_raw_parts(ptr, sz);
-            // ... and then convert that slice into a string slice
-            core::str::from_utf8(slice).unwrap()
-        };
+///
+/// Arguments are path, path length, `argc`, address of an array of `argc`
+/// string pointers, and address of an array of `argc` string lengths (`i32`).
+fn sys_exec() -> Result<usize, Errno> {
+    let p = my_proc();
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    let argc = arg_uint(&p.trapframe, 2)?;
+    if argc > MAXARG {
+        return Err(Errno::E2BIG);
+    }
+    let mut ptrs = [0; MAXARG * 8];
+    let mut lens = [0; MAXARG * 4];
+    p.pgtable.copy_from_user(&mut ptrs[..argc * 8], argraw(&p.trapframe, 3))?;
+    p.pgtable.copy_from_user(&mut lens[..argc * 4], argraw(&p.trapframe, 4))?;
+    let mut args = Vec::new();
+    for i in 0..argc {
+        let ptr = usize::from_ne_bytes(ptrs[i * 8..i * 8 + 8].try_into().unwrap());
+        let len = i32::from_ne_bytes(lens[i * 4..i * 4 + 4].try_into().unwrap());
+        if len < 0 {
+            return Err(Errno::EINVAL);
+        }
+        if len as usize > MAXARGLEN {
+            return Err(Errno::E2BIG);
+        }
+        args.push(p.pgtable.copy_str_from_user(ptr, len as usize, MAXARGLEN)?);
     }
     if path == "/init" {
         info!("running tests before init...");
         crate::test::run_tests();
     }
-    exec(path);
-    0
+    exec(&path, &args)
 }
 
 /// exit syscall entry
-fn sys_exit() -> i32 {
+fn sys_exit() -> ! {
     let code;
     {
         let p = my_proc();
@@ -118,23 +135,125 @@ fn sys_exit() -> i32 {
     exit(code);
 }
 
-/// Process all syscall
-pub fn syscall() -> i32 {
+/// wait syscall entry
+fn sys_wait() -> Result<usize, Errno> {
+    let p = my_proc();
+    let pid = arg_int(&p.trapframe, 0);
+    let status_addr = argraw(&p.trapframe, 1);
+    let (pid, status) = wait(pid)?;
+    if status_addr != 0 {
+        p.pgtable.copy_to_user(status_addr, &status.to_ne_bytes())?;
+    }
+    Ok(pid as usize)
+}
+
+/// kill syscall entry
+///
+/// Arguments are pid and signal number.
+fn sys_kill() -> Result<usize, Errno> {
+    let p = my_proc();
+    let pid = arg_int(&p.trapframe, 0);
+    let sig = Signal::from_signum(arg_int(&p.trapframe, 1)).ok_or(Errno::EINVAL)?;
+    kill(pid, sig).map(|_| 0)
+}
+
+/// sigaction syscall entry
+///
+/// Arguments are signal number, address of new `SigAction` and address to store
+/// the old one. Either address may be 0.
+fn sys_sigaction() -> Result<usize, Errno> {
+    let p = my_proc();
+    let signum = arg_int(&p.trapframe, 0);
+    let (act_addr, old_addr) = (argraw(&p.trapframe, 1), argraw(&p.trapframe, 2));
+    let act = if act_addr != 0 {
+        let mut act = SigAction::DEFAULT;
+        p.pgtable.copy_from_user(as_bytes_mut(&mut act), act_addr)?;
+        Some(act)
+    } else {
+        None
+    };
+    let old = signal::sigaction(signum, act)?;
+    if old_addr != 0 {
+        p.pgtable.copy_to_user(old_addr, as_bytes(&old))?;
+    }
+    Ok(0)
+}
+
+/// sigprocmask syscall entry
+///
+/// Arguments are how to change the mask and a signal set. Returns the old mask.
+fn sys_sigprocmask() -> Result<usize, Errno> {
+    let p = my_proc();
+    let how = arg_int(&p.trapframe, 0);
+    let set = argraw(&p.trapframe, 1) as u32;
+    signal::sigprocmask(how, set).map(|old| old as usize)
+}
+
+/// sigreturn syscall entry
+fn sys_sigreturn() -> Result<usize, Errno> {
+    signal::sigreturn()
+}
+
+/// sbrk syscall entry
+fn sys_sbrk() -> Result<usize, Errno> {
+    let p = my_proc();
+    let increment = argraw(&p.trapframe, 0) as isize;
+    sbrk(increment)
+}
+
+/// procinfo syscall
+///
+/// Arguments are address of an array of `ProcInfo` in user space and its length.
+/// Returns number of processes copied.
+fn sys_procinfo() -> Result<usize, Errno> {
+    let p = my_proc();
+    let addr = argraw(&p.trapframe, 0);
+    let n = arg_uint(&p.trapframe, 1)?;
+    let infos = proc_info();
+    let n = n.min(infos.len());
+    for (i, info) in infos[..n].iter().enumerate() {
+        p.pgtable.copy_to_user(addr + i * size_of::<ProcInfo>(), as_bytes(info))?;
+    }
+    Ok(n)
+}
+
+/// Process all syscall, and returns result to be passed to user space
+pub fn syscall() -> usize {
     let syscall_id;
     {
         let p = my_proc();
         let tf = &p.trapframe;
         syscall_id = tf.regs[Register::a7 as usize] as i64;
     }
-    match syscall_id {
+    let result = match syscall_id {
         SYS_WRITE => sys_write(),
         SYS_READ => sys_read(),
         SYS_FORK => sys_fork(),
         SYS_EXEC => sys_exec(),
         SYS_EXIT => sys_exit(),
+        SYS_WAIT => sys_wait(),
+        SYS_KILL => sys_kill(),
+        SYS_SIGACTION => sys_sigaction(),
+        SYS_SIGPROCMASK => sys_sigprocmask(),
+        SYS_SIGRETURN => sys_sigreturn(),
         SYS_DUP => sys_dup(),
         SYS_OPEN => sys_open(),
         SYS_CLOSE => sys_close(),
-        _ => unreachable!()
+        SYS_PIPE => sys_pipe(),
+        SYS_MKNOD => sys_mknod(),
+        SYS_UNLINK => sys_unlink(),
+        SYS_LINK => sys_link(),
+        SYS_MKDIR => sys_mkdir(),
+        SYS_CHDIR => sys_chdir(),
+        SYS_SBRK => sys_sbrk(),
+        SYS_FSTAT => sys_fstat(),
+        SYS_PROCINFO => sys_procinfo(),
+        SYS_MOUNT => sys_mount(),
+        SYS_UMOUNT => sys_umount(),
+        _ => Err(Errno::ENOSYS)
+    };
+    match result {
+        Ok(ret) => ret,
+        Err(err) => err.as_ret() as usize
     }
 }
@@ -6,94 +6,232 @@
 //! File-related syscalls
 
 use crate::process::my_proc;
-use crate::syscall::{arg_int, arg_uint, arg_ptr, arg_fd, arg_ptr_mut};
-use crate::file::{File, Console, FsFile};
+use crate::syscall::{argraw, arg_int, arg_uint, arg_fd, arg_str};
+use crate::file::{File, Console, FsFile, Pipe};
 use alloc::sync::Arc;
 use crate::spinlock::Mutex;
-use crate::symbols::PAGE_SIZE;
 use crate::virtio::BSIZE;
+use crate::fs::{self, T_DIR, T_DEVICE, as_bytes};
+use crate::error::Errno;
 
 /// write syscall
-pub fn sys_write() -> i32 {
+///
+/// Content is copied from user space through a kernel buffer, `BSIZE` bytes at a time.
+/// If an error occurs after some content is written, returns number of characters written.
+pub fn sys_write() -> Result<usize, Errno> {
     let p = my_proc();
-    let sz = arg_uint(&p.trapframe, 2);
-    if sz > BSIZE {
-        panic!("size > BSIZE not supported");
-    }
-    let content = arg_ptr(&p.pgtable, &p.trapframe, 1, sz);
-    let u8_slice = unsafe { core::slice::from_raw_parts(content, sz) };
-    let file = arg_fd(&p, 0);
-    match (*file).as_ref() {
-        File::Device(dev) => dev.write(u8_slice),
-        File::FsFile(file) => file.write(u8_slice),
-        _ => { unimplemented!(); }
+    let addr = argraw(&p.trapframe, 1);
+    let sz = arg_uint(&p.trapframe, 2)?;
+    let file = arg_fd(&p, 0)?.clone();
+    let mut buf = [0; BSIZE];
+    let mut tot = 0;
+    while tot < sz {
+        let n = (sz - tot).min(BSIZE);
+        let r = p.pgtable.copy_from_user(&mut buf[..n], addr + tot)
+            .map_err(Errno::from)
+            .and_then(|_| match &*file {
+                File::Device(dev) => dev.write(&buf[..n]),
+                File::FsFile(file) => file.write(&buf[..n]),
+                File::Pipe(pipe) => pipe.write(&buf[..n]),
+            });
+        match r {
+            Ok(r) => {
+                tot += r;
+                if r != n {
+                    break;
+                }
+            }
+            Err(err) if tot == 0 => { return Err(err); }
+            Err(_) => break
+        }
     }
+    Ok(tot)
 }
 
 /// read syscall
-pub fn sys_read() -> i32 {
+///
+/// Content is copied to user space through a kernel buffer, `BSIZE` bytes at a time.
+/// Only regular files are read more than once, as reading devices and pipes may block.
+/// Each part of user buffer is made writable before reading, so that no content is
+/// consumed from file if it can't be copied to user space.
+/// If an error occurs after some content is read, returns number of characters read.
+pub fn sys_read() -> Result<usize, Errno> {
     let p = my_proc();
-    let sz = arg_uint(&p.trapframe, 2);
-    if sz > BSIZE {
-        panic!("size > BSIZE not supported");
-    }
-    let content = arg_ptr_mut(&p.pgtable, &p.trapframe, 1, sz);
-    let u8_slice = unsafe { core::slice::from_raw_parts_mut(content, sz) };
-    let file = arg_fd(&p, 0);
-    match (*file).as_ref() {
-        File::Device(dev) => dev.read(u8_slice),
-        File::FsFile(file) => file.read(u8_slice),
-        _ => { unimplemented!(); }
+    let addr = argraw(&p.trapframe, 1);
+    let sz = arg_uint(&p.trapframe, 2)?;
+    let file = arg_fd(&p, 0)?.clone();
+    let again = match &*file {
+        File::FsFile(_) => true,
+        _ => false
+    };
+    let mut buf = [0; BSIZE];
+    let mut tot = 0;
+    while tot < sz {
+        let n = (sz - tot).min(BSIZE);
+        let r = p.pgtable.fault_in_range(addr + tot, n, true)
+            .map_err(Errno::from)
+            .and_then(|_| match &*file {
+                File::Device(dev) => dev.read(&mut buf[..n]),
+                File::FsFile(file) => file.read(&mut buf[..n]),
+                File::Pipe(pipe) => pipe.read(&mut buf[..n]),
+            })
+            .and_then(|r| {
+                p.pgtable.copy_to_user(addr + tot, &buf[..r])?;
+                Ok(r)
+            });
+        match r {
+            Ok(r) => {
+                tot += r;
+                if !again || r != n {
+                    break;
+                }
+            }
+            Err(err) if tot == 0 => { return Err(err); }
+            Err(_) => break
+        }
     }
+    Ok(tot)
 }
 
-/// find a available file descriptor from files array in process
-fn next_available_fd<T>(files: &[Option<T>]) -> Option<usize> {
+/// find a available file descriptor from files array in process,
+/// returns `EMFILE` if all are in use
+fn next_available_fd<T>(files: &[Option<T>]) -> Result<usize, Errno> {
     for i in 0..files.len() {
         match files[i] {
-            None => { return Some(i); }
+            None => { return Ok(i); }
             _ => { continue; }
         }
     }
-    return None;
+    Err(Errno::EMFILE)
 }
 
-/// open syscall, currently only support `/console` file.
-pub fn sys_open() -> i32 {
+/// open syscall
+pub fn sys_open() -> Result<usize, Errno> {
     let p = my_proc();
-    let sz = arg_uint(&p.trapframe, 1);
-    let mode = arg_uint(&p.trapframe, 2);
-    let content = arg_ptr(&p.pgtable, &p.trapframe, 0, sz);
-    let path = core::str::from_utf8(unsafe { core::slice::from_raw_parts(content, sz) }).unwrap();
-    let fd = match next_available_fd(&p.files) {
-        Some(fd) => fd,
-        None => { return -1; }
-    };
-    if path == "/console" {
-        p.files[fd] = Some(Arc::new(File::Device(box Console {})));
-    } else {
-        p.files[fd] = Some(Arc::new(File::FsFile(FsFile::open(path, mode))));
-    }
-    return fd as i32;
+    let mode = arg_uint(&p.trapframe, 2)?;
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    let fd = next_available_fd(&p.files)?;
+    p.files[fd] = Some(Arc::new(File::open(&path, mode)?));
+    Ok(fd)
 }
 
 /// close syscall
-pub fn sys_close() -> i32 {
+pub fn sys_close() -> Result<usize, Errno> {
     let p = my_proc();
-    let fd = arg_int(&p.trapframe, 0) as usize;
+    let fd = argraw(&p.trapframe, 0);
+    arg_fd(&p, 0)?;
     p.files[fd] = None;
-    0
+    Ok(0)
 }
 
 /// dup syscall
-pub fn sys_dup() -> i32 {
+pub fn sys_dup() -> Result<usize, Errno> {
+    let p = my_proc();
+    let file = arg_fd(&p, 0)?.clone();
+    let fd = next_available_fd(&p.files)?;
+    p.files[fd] = Some(file);
+    Ok(fd)
+}
+
+/// pipe syscall
+pub fn sys_pipe() -> Result<usize, Errno> {
     let p = my_proc();
-    let old_fd = arg_uint(&p.trapframe, 0);
-    let fd = match next_available_fd(&p.files) {
-        Some(fd) => fd,
-        None => { return -1; }
+    let fds_addr = argraw(&p.trapframe, 0);
+    let rfd = next_available_fd(&p.files)?;
+    let (rx, tx) = Pipe::new();
+    p.files[rfd] = Some(Arc::new(File::Pipe(rx)));
+    let wfd = match next_available_fd(&p.files) {
+        Ok(fd) => fd,
+        Err(err) => {
+            p.files[rfd] = None;
+            return Err(err);
+        }
     };
-    p.files[fd] = Some(p.files[old_fd].as_ref().unwrap().clone());
-    use crate::info;
-    fd as i32
+    p.files[wfd] = Some(Arc::new(File::Pipe(tx)));
+    let mut fds = [0; 8];
+    fds[..4].copy_from_slice(&(rfd as i32).to_ne_bytes());
+    fds[4..].copy_from_slice(&(wfd as i32).to_ne_bytes());
+    if let Err(err) = p.pgtable.copy_to_user(fds_addr, &fds) {
+        p.files[rfd] = None;
+        p.files[wfd] = None;
+        return Err(err.into());
+    }
+    Ok(0)
+}
+
+/// fstat syscall
+///
+/// Arguments are file descriptor and address of a `Stat` in user space.
+pub fn sys_fstat() -> Result<usize, Errno> {
+    let p = my_proc();
+    let addr = argraw(&p.trapframe, 1);
+    let st = arg_fd(&p, 0)?.stat();
+    p.pgtable.copy_to_user(addr, as_bytes(&st))?;
+    Ok(0)
+}
+
+/// mknod syscall
+pub fn sys_mknod() -> Result<usize, Errno> {
+    let p = my_proc();
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    let major = arg_uint(&p.trapframe, 2)? as u16;
+    let minor = arg_uint(&p.trapframe, 3)? as u16;
+    fs::create(&path, T_DEVICE, major, minor)?;
+    Ok(0)
+}
+
+/// mkdir syscall
+pub fn sys_mkdir() -> Result<usize, Errno> {
+    let p = my_proc();
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    fs::create(&path, T_DIR, 0, 0)?;
+    Ok(0)
+}
+
+/// link syscall
+pub fn sys_link() -> Result<usize, Errno> {
+    let p = my_proc();
+    let old = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    let new = arg_str(&mut p.pgtable, &p.trapframe, 2)?;
+    fs::link(&old, &new)?;
+    Ok(0)
+}
+
+/// unlink syscall
+pub fn sys_unlink() -> Result<usize, Errno> {
+    let p = my_proc();
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    fs::unlink(&path)?;
+    Ok(0)
+}
+
+/// chdir syscall
+pub fn sys_chdir() -> Result<usize, Errno> {
+    let p = my_proc();
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    let ip = fs::namei(&path)?;
+    if ip.lock().typ != T_DIR {
+        return Err(Errno::ENOTDIR);
+    }
+    p.cwd = Some(ip);
+    Ok(0)
+}
+
+/// mount syscall
+///
+/// Arguments are device number of disk and path of mount point.
+pub fn sys_mount() -> Result<usize, Errno> {
+    let p = my_proc();
+    let dev = arg_uint(&p.trapframe, 0)? as u32;
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 1)?;
+    fs::mount(dev, &path)?;
+    Ok(0)
+}
+
+/// umount syscall
+pub fn sys_umount() -> Result<usize, Errno> {
+    let p = my_proc();
+    let path = arg_str(&mut p.pgtable, &p.trapframe, 0)?;
+    fs::umount(&path)?;
+    Ok(0)
 }
@@ -43,3 +43,15 @@ pub const SYS_SBRK : i64 = 18;
 pub const SYS_SLEEP : i64 = 19;
 /// `20`: uptime
 pub const SYS_UPTIME : i64 = 20;
+/// `21`: procinfo
+pub const SYS_PROCINFO : i64 = 21;
+/// `22`: sigaction
+pub const SYS_SIGACTION : i64 = 22;
+/// `23`: sigprocmask
+pub const SYS_SIGPROCMASK : i64 = 23;
+/// `24`: sigreturn
+pub const SYS_SIGRETURN : i64 = 24;
+/// `25`: mount
+pub const SYS_MOUNT : i64 = 25;
+/// `26`: umount
+pub const SYS_UMOUNT : i64 = 26;
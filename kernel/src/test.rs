@@ -13,8 +13,20 @@ type TestSuite = fn() -> &'static [(&'static str, fn())];
 /// Run all tests in core os
 pub fn run_tests() {
     let suites = [
+        ("mem", crate::mem::tests::tests as TestSuite),
+        ("slab", crate::slab::tests::tests as TestSuite),
+        ("fdt", crate::fdt::tests::tests as TestSuite),
+        ("page", crate::page::tests::tests as TestSuite),
         ("virtio", crate::virtio::tests::tests as TestSuite),
-        ("fsfile", crate::file::fsfile::tests::tests as TestSuite)];
+        ("virtio-blk", crate::virtio::blk::tests::tests as TestSuite),
+        ("bio", crate::bio::tests::tests as TestSuite),
+        ("inode", crate::fs::inode::tests::tests as TestSuite),
+        ("fs", crate::fs::dir::tests::tests as TestSuite),
+        ("log", crate::fs::log::tests::tests as TestSuite),
+        ("mount", crate::fs::mount::tests::tests as TestSuite),
+        ("fsfile", crate::file::fsfile::tests::tests as TestSuite),
+        ("elf", crate::elf::tests::tests as TestSuite),
+        ("pipe", crate::file::pipe::tests::tests as TestSuite)];
     for (name, suite) in &suites {
         let tests = suite();
         info!("  {}", name);
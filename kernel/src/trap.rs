@@ -17,6 +17,12 @@ use crate::arch::{hart_id, sp};
 use crate::jump::*;
 use crate::intr::devintr;
 use crate::intr::Intr::Timer;
+use crate::signal::{self, Signal, SIGNAL_LOCK};
+
+/// `scause` of load page fault
+const LOAD_PAGE_FAULT: usize = 13;
+/// `scause` of store/AMO page fault
+const STORE_PAGE_FAULT: usize = 15;
 
 /// Process interrupt from supervisor mode
 #[no_mangle]
@@ -132,14 +138,17 @@ pub extern "C" fn usertrap() -> ! {
 
     let mut intr = None;
     if scause == 8 {
+        process::exit_if_killed();
         p.trapframe.epc += 4;
         arch::intr_on();
-        p.trapframe.regs[a0 as usize] = syscall::syscall() as usize;
+        p.trapframe.regs[a0 as usize] = syscall::syscall();
+    } else if (scause == LOAD_PAGE_FAULT || scause == STORE_PAGE_FAULT)
+        && p.pgtable.handle_page_fault(stval::read(), scause == STORE_PAGE_FAULT).is_ok() {
+        // page is allocated or copied, and the faulting instruction will be executed again
     } else {
         intr = devintr();
-        match intr {
-            None => panic!("unexpected scause {:x}", scause),
-            _ => ()
+        if intr.is_none() {
+            handle_exception(p, scause);
         }
     }
 
@@ -150,6 +159,46 @@ pub extern "C" fn usertrap() -> ! {
     usertrapret();
 }
 
+/// Name of exception `scause` raised in user space, and the signal sent to
+/// the process. Returns `None` if `scause` is not such an exception.
+fn user_exception(scause: usize) -> Option<(&'static str, Signal)> {
+    match scause {
+        0 => Some(("instruction address misaligned", Signal::SIGBUS)),
+        1 => Some(("instruction access fault", Signal::SIGSEGV)),
+        2 => Some(("illegal instruction", Signal::SIGILL)),
+        3 => Some(("breakpoint", Signal::SIGTRAP)),
+        4 => Some(("load address misaligned", Signal::SIGBUS)),
+        5 => Some(("load access fault", Signal::SIGSEGV)),
+        6 => Some(("store/AMO address misaligned", Signal::SIGBUS)),
+        7 => Some(("store/AMO access fault", Signal::SIGSEGV)),
+        12 => Some(("instruction page fault", Signal::SIGSEGV)),
+        LOAD_PAGE_FAULT => Some(("load page fault", Signal::SIGSEGV)),
+        STORE_PAGE_FAULT => Some(("store/AMO page fault", Signal::SIGSEGV)),
+        _ => None
+    }
+}
+
+/// Handle exception `scause` raised by process `p` in user space. If `p` catches the
+/// corresponding signal, the signal is sent so that its handler runs. Otherwise `p`
+/// is terminated, and its parent gets exit status of the signal.
+fn handle_exception(p: &mut Process, scause: usize) {
+    use riscv::register::stval;
+    let (name, sig) = match user_exception(scause) {
+        Some(exception) => exception,
+        None => panic!("unexpected scause {:x}", scause)
+    };
+    if p.catches(sig) {
+        let _sig_lock = SIGNAL_LOCK.lock();
+        p.send_signal(sig);
+        return;
+    }
+    println!(
+        "pid {}: {}, sepc {:x}, stval {:x}, killed by {:?}",
+        p.pid, name, p.trapframe.epc, stval::read(), sig
+    );
+    process::exit(sig.exit_status());
+}
+
 /// Jump to user space through trampoline after trapframe is properly set. Calls `userret` in `trampoline.S`.
 #[inline]
 fn trampoline_userret(tf: usize, satp_val: usize) -> ! {
@@ -166,6 +215,8 @@ fn trampoline_userret(tf: usize, satp_val: usize) -> ! {
 /// should be wrapped in brackets so that all objects are
 /// dropped before jumping to trampoline.
 pub fn usertrapret() -> ! {
+    signal::deliver();
+    process::exit_if_killed();
     let satp_val: usize;
     {
         use riscv::register::*;
@@ -9,9 +9,12 @@ use core::convert::TryInto;
 use core::fmt::Write;
 use core::fmt::Error;
 use crate::spinlock::Mutex;
-use crate::{println, print};
+use crate::file::consoleintr;
 
-/// UART base address on QEMU RISC-V
+/// `Ctrl-P`, which prints process list for debugging
+const CTRL_P: u8 = 0x10;
+
+/// UART base address on QEMU RISC-V, used if there's no device tree
 pub const UART_BASE_ADDR: usize = 0x1000_0000;
 
 /// UART driver
@@ -132,27 +135,19 @@ impl Uart {
 }
 
 /// Process UART interrupt. Should only be called when interrupt.
+///
+/// Received characters are put into console input without echo, as
+/// line editing is done by user programs. `Ctrl-P` prints process list.
 pub fn uartintr() {
-    let mut uart = UART().lock();
-    if let Some(c) = uart.get() {
-        drop(uart);
-        match c {
-            8 => {
-                // This is a backspace, so we
-                // essentially have to write a space and
-                // backup again:
-                print!("{} {}", 8 as char, 8 as char);
-            }
-            10 | 13 => {
-                // Newline or carriage-return
-                println!();
-            }
-            _ => {
-                print!("{}", c as char);
-                if c == 97 {
-                    crate::process::debug();
-                }
-            }
+    loop {
+        let c = match UART().lock().get() {
+            Some(c) => c,
+            None => break
+        };
+        if c == CTRL_P {
+            crate::process::debug();
+        } else {
+            consoleintr(c);
         }
     }
 }
@@ -164,6 +159,9 @@ static __UART: Mutex<Uart> = Mutex::new(Uart::new(UART_BASE_ADDR), "uart driver"
 #[allow(non_snake_case)]
 pub fn UART() -> &'static Mutex<Uart> { &__UART }
 
+/// Initialize UART found in device tree
 pub unsafe fn init() {
-    UART().get().init();
+    let uart = UART().get();
+    uart.base_address = crate::fdt::machine().uart.base;
+    uart.init();
 }
\ No newline at end of file
@@ -3,18 +3,21 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-//! virt-io driver
+//! virt-io bus
+//!
+//! Every `virtio,mmio` slot in device tree is probed at boot. Drivers are
+//! registered for devices with known type, and interrupts of a slot are
+//! routed to its driver.
+
+pub mod blk;
 
-use crate::spinlock::{Mutex, MutexGuard};
 use crate::{panic, info};
 use crate::symbols::{PAGE_SIZE, PAGE_ORDER};
-use crate::process::{wakeup, sleep};
-use alloc::boxed::Box;
-use crate::arch::__sync_synchronize;
-use crate::uart::UART;
-use core::sync::atomic::Ordering;
+use crate::fdt::{machine, Device};
+use alloc::vec::Vec;
+pub use blk::{disk, BSIZE};
 
-/// VIRTIO base address on QEMU RISC-V
+/// VIRTIO base address on QEMU RISC-V, used if there's no device tree
 pub const VIRTIO_MMIO_BASE: usize = 0x10001000;
 
 /// VIRTIO MMIO address offset
@@ -40,13 +43,13 @@ pub enum VIRTIO_MMIO {
 }
 
 impl VIRTIO_MMIO {
-    /// Get address of MMIO from enum
-    pub const fn val(self) -> usize {
-        self as usize + VIRTIO_MMIO_BASE
+    /// Get address of MMIO register of device at `base` from enum
+    pub const fn val(self, base: usize) -> usize {
+        self as usize + base
     }
-    /// Get pointer to MMIO register from enum
-    pub const fn ptr(self) -> *mut u32 {
-        self.val() as _
+    /// Get pointer to MMIO register of device at `base` from enum
+    pub const fn ptr(self, base: usize) -> *mut u32 {
+        self.val(base) as _
     }
 }
 
@@ -118,9 +121,6 @@ impl VRingUsedElem {
     }
 }
 
-pub const VIRTIO_BLK_T_IN: u32 = 0;
-pub const VIRTIO_BLK_T_OUT: u32 = 1;
-
 #[repr(C)]
 pub struct UsedArea {
     pub flags: u16,
@@ -138,339 +138,184 @@ impl UsedArea {
     }
 }
 
-pub struct InflightOp {
-    pub buf: Box<Buf>,
-    pub status: u8,
-}
-
-/// Size of avail array
-const AVAIL_SZ: usize = (PAGE_SIZE - DESC_NUM * core::mem::size_of::<VRingDesc>()) / core::mem::size_of::<u16>();
-
-#[repr(C)]
-#[repr(align(4096))]
-pub struct VirtIOData {
-    /// VIRTIO MMIO descriptor register
-    pub desc: [VRingDesc; DESC_NUM],
-    /// VIRTIO MMIO descriptor avail register (padding to page size)
-    pub avail: [u16; AVAIL_SZ],
-    /// VIRTIO MMIO descriptor used register
-    pub used: [UsedArea; DESC_NUM],
-
-    /// is descriptor free
-    pub free: [bool; DESC_NUM],
-    /// used index of used array
-    pub used_idx: u16,
-    /// in-flight operations
-    pub info: [Option<InflightOp>; DESC_NUM],
-}
-
-pub struct VirtIO(Mutex<VirtIOData>);
-
-/// VIRTIO buffer size
-pub const BSIZE: usize = 1024;
-
-/// VIRTIO Buffer
-#[repr(C)]
-pub struct Buf {
-    pub valid: bool,
-    /// TODO: this can be removed
-    pub disk: i32,
-    /// device ID
-    pub dev: u32,
-    /// block number
-    pub blockno: u32,
-    /// buffer data
-    pub data: [u8; BSIZE],
+/// Device types in virtio specification
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    Entropy,
+    Balloon,
+    Scsi,
+    Gpu,
+    Input,
+    Unknown(u32),
 }
 
-impl Buf {
-    pub const fn new() -> Self {
-        Self {
-            valid: false,
-            disk: 0,
-            dev: 0,
-            blockno: 0,
-            data: [0; BSIZE],
+impl DeviceType {
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => DeviceType::Network,
+            2 => DeviceType::Block,
+            3 => DeviceType::Console,
+            4 => DeviceType::Entropy,
+            5 => DeviceType::Balloon,
+            8 => DeviceType::Scsi,
+            16 => DeviceType::Gpu,
+            18 => DeviceType::Input,
+            _ => DeviceType::Unknown(id)
         }
     }
 }
 
-#[repr(C)]
-pub struct BlkOutHdr {
-    pub blk_type: u32,
-    reserved: u32,
-    sector: usize,
+/// Legacy MMIO transport of a virtio device, which is provided by QEMU
+#[derive(Debug, Clone, Copy)]
+pub struct Transport {
+    /// MMIO base address
+    pub base: usize,
+    /// Interrupt number on PLIC
+    pub irq: u32,
 }
 
-impl VirtIOData {
-    /// Free one descriptor
-    fn free_desc(&mut self, i: usize) {
-        if i >= DESC_NUM {
-            panic!("invalid desc");
-        }
-        if self.free[i] {
-            panic!("already free");
-        }
-        self.desc[i].addr = 0;
-        self.free[i] = true;
-        wakeup(&self.free[0]);
-    }
-
-    /// Allocate one descriptor
-    fn alloc_desc(&mut self) -> Option<usize> {
-        for i in 0..DESC_NUM {
-            if self.free[i] {
-                self.free[i] = false;
-                return Some(i);
-            }
+impl Transport {
+    /// Probe slot `dev`. Returns `None` if it is not a virtio transport,
+    /// or there's no device attached to it.
+    pub unsafe fn probe(dev: &Device) -> Option<Self> {
+        use VIRTIO_MMIO::*;
+        let transport = Transport { base: dev.base, irq: dev.irq };
+        if transport.read(MAGIC_VALUE) != 0x74726976
+            || transport.read(VERSION) != 1
+            || transport.read(VENDOR_ID) != 0x554d4551
+            || transport.read(DEVICE_ID) == 0 {
+            return None;
         }
-        None
+        Some(transport)
     }
 
-    /// Allocate three descriptors, return array of indices
-    fn alloc3_desc(&mut self) -> Option<[usize; 3]> {
-        let mut idx = [0; 3];
-        for i in 0..3 {
-            match self.alloc_desc() {
-                Some(x) => idx[i] = x,
-                None => {
-                    for j in 0..i {
-                        self.free_desc(idx[j]);
-                    }
-                    return None;
-                }
-            }
-        }
-        Some(idx)
+    /// Read register `reg`
+    pub fn read(&self, reg: VIRTIO_MMIO) -> u32 {
+        unsafe { reg.ptr(self.base).read_volatile() }
     }
 
-    /// Free descriptor chain
-    fn free_chain(&mut self, mut i: usize) {
-        loop {
-            self.free_desc(i);
-            if self.desc[i].flags & VRING_DESC_F_NEXT != 0 {
-                i = self.desc[i].next as usize;
-            } else {
-                break;
-            }
-        }
+    /// Write `val` to register `reg`
+    pub fn write(&self, reg: VIRTIO_MMIO, val: u32) {
+        unsafe { reg.ptr(self.base).write_volatile(val) }
     }
-}
 
-impl VirtIO {
-    pub const fn new() -> Self {
-        Self(
-            Mutex::new(VirtIOData {
-                desc: [VRingDesc::new(); DESC_NUM],
-                avail: [0; AVAIL_SZ],
-                used: [UsedArea::new(); DESC_NUM],
-                free: [false; DESC_NUM],
-                used_idx: 0,
-                info: [None; DESC_NUM],
-            }, "vdisk"))
+    pub fn device_type(&self) -> DeviceType {
+        DeviceType::from_id(self.read(VIRTIO_MMIO::DEVICE_ID))
     }
 
-    /// Initialize VIRTIO driver.
-    ///
-    /// Should be called in booting hart.
-    pub unsafe fn init(&mut self) {
+    /// Initialize device, and accept features it offers except `unsupported` ones.
+    pub fn init(&self, unsupported: u32) {
         use VIRTIO_MMIO::*;
         use VIRTIO_CONFIG_S::*;
-        use VIRTIO_FEATURE::*;
-
-        let mut vio = self.0.get();
-
-        if MAGIC_VALUE.ptr().read_volatile() != 0x74726976 {
-            panic!("cannot find virtio disk: magic value");
-        }
-        if VERSION.ptr().read_volatile() != 1 {
-            panic!("cannot find virtio disk: version");
-        }
-        if DEVICE_ID.ptr().read_volatile() != 2 {
-            panic!("cannot find virtio disk: device id");
-        }
-        if VENDOR_ID.ptr().read_volatile() != 0x554d4551 {
-            panic!("cannot find virtio disk: vendor id");
-        }
 
         let mut status: u32 = 0;
         status |= ACKNOWLDGE.val();
-        STATUS.ptr().write_volatile(status);
+        self.write(STATUS, status);
 
         status |= DRIVER.val();
-        STATUS.ptr().write_volatile(status);
-
-        let mut features: u32 = DEVICE_FEATURES.ptr().read_volatile();
-        features &= !BLK_F_RO.bit();
-        features &= !BLK_F_SCSI.bit();
-        features &= !BLK_F_CONFIG_WCE.bit();
-        features &= !BLK_F_MQ.bit();
-        features &= !F_ANY_LAYOUT.bit();
-        features &= !RING_F_EVENT_IDX.bit();
-        features &= !RING_F_INDIRECT_DESC.bit();
-        DEVICE_FEATURES.ptr().write_volatile(features);
+        self.write(STATUS, status);
+
+        let features = self.read(DEVICE_FEATURES) & !unsupported;
+        self.write(DRIVER_FEATURES, features);
 
         status |= FEATURES_OK.val();
-        STATUS.ptr().write_volatile(status);
+        self.write(STATUS, status);
 
         status |= DRIVER_OK.val();
-        STATUS.ptr().write_volatile(status);
-
-        GUEST_PAGE_SIZE.ptr().write_volatile(PAGE_SIZE as u32);
-
-        QUEUE_SEL.ptr().write_volatile(0);
-        let max = QUEUE_NUM_MAX.ptr().read_volatile();
-        if max == 0 {
-            panic!("virtio disk has no queue");
-        }
-        if max < DESC_NUM as u32 {
-            panic!("virtio disk max queue too short {} < {}", max, DESC_NUM);
-        }
-        QUEUE_NUM.ptr().write_volatile(DESC_NUM as u32);
+        self.write(STATUS, status);
 
-        QUEUE_PFN.ptr().write_volatile(((vio as *mut _ as usize) >> PAGE_ORDER) as u32);
-
-        for i in 0..DESC_NUM {
-            vio.free[i] = true;
-        }
+        self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
     }
 
-    /// Read-write operation
-    fn rw(&mut self, mut b: Box<Buf>, write: bool) -> Box<Buf> {
+    /// Set up virtqueue `queue` of `num` descriptors at page-aligned `addr`
+    pub fn init_queue(&self, queue: u32, num: u32, addr: usize) {
         use VIRTIO_MMIO::*;
 
-        let sector = b.blockno as usize * (BSIZE / 512);
-
-        let mut vio = self.0.lock();
-
-        let idx: [usize; 3] = loop {
-            if let Some(idx) = vio.alloc3_desc() {
-                break idx;
-            }
-            vio = sleep(&vio.free[0] as *const _, vio);
-        };
-
-        // info!("3 desc: {:?}", idx);
-
-        let buf0 = BlkOutHdr {
-            reserved: 0,
-            sector,
-            blk_type: if write { VIRTIO_BLK_T_OUT } else { VIRTIO_BLK_T_IN },
-        };
-
-
-        // VIRTIO 5.2.6.4
-        // MUST use a single 8-byte descriptor containing type, reserved and sector,
-        // followed by descriptors for data, then finally a separate 1-byte descriptor for status.
-
-        {
-            let desc0 = &mut vio.desc[idx[0]];
-            desc0.addr = &buf0 as *const _ as usize;
-            desc0.len = core::mem::size_of::<BlkOutHdr>() as u32;
-            desc0.flags = VRING_DESC_F_NEXT;
-            desc0.next = idx[1] as u16;
-        }
-
-        {
-            let desc1 = &mut vio.desc[idx[1]];
-            desc1.addr = b.data.as_mut_ptr() as usize;
-            desc1.len = BSIZE as u32;
-            desc1.flags = if write { 0 } else { VRING_DESC_F_WRITE };
-            desc1.flags |= VRING_DESC_F_NEXT;
-            desc1.next = idx[2] as u16;
+        self.write(QUEUE_SEL, queue);
+        let max = self.read(QUEUE_NUM_MAX);
+        if max == 0 {
+            panic!("virtio {:x}: no queue {}", self.base, queue);
         }
-
-        b.disk = 1;
-        vio.info[idx[0]] = Some(InflightOp {
-            buf: b,
-            status: 0,
-        });
-
-        {
-            let addr = &vio.info[idx[0]].as_ref().unwrap().status as *const _ as usize;
-            {
-                let desc2 = &mut vio.desc[idx[2]];
-
-                desc2.addr = addr;
-                desc2.len = 1;
-                desc2.flags = VRING_DESC_F_WRITE;
-                desc2.next = 0;
-            }
-
-            let idx_id = 2 + vio.avail[1] as usize % DESC_NUM;
-            vio.avail[idx_id] = idx[0] as u16;
-
-            __sync_synchronize();
-
-            vio.avail[1] = vio.avail[1] + 1;
-
-            unsafe { QUEUE_NOTIFY.ptr().write_volatile(0); }
-            // info!("{:x}", &vio.info[idx[0]].as_ref().unwrap().status as *const _ as usize);
-            let buf_addr = &*vio.info[idx[0]].as_ref().unwrap().buf as *const _;
-            // info!("sleep {:x} id={}", buf_addr as usize, vio.info[idx[0]].as_ref().unwrap().status);
-            while vio.info[idx[0]].as_ref().unwrap().buf.disk == 1 {
-                vio = sleep(buf_addr, vio);
-            }
+        if max < num {
+            panic!("virtio {:x}: max queue too short {} < {}", self.base, max, num);
         }
-        let result = core::mem::replace(&mut vio.info[idx[0]], None);
-        unsafe { vio.info[idx[0]] = core::mem::zeroed(); }
-        vio.info[idx[0]] = None;
-        vio.free_chain(idx[0]);
-        result.unwrap().buf
+        self.write(QUEUE_NUM, num);
+        self.write(QUEUE_PFN, (addr >> PAGE_ORDER) as u32);
     }
 
-    /// Read from device and block number
-    pub fn read(&mut self, dev: u32, blockno: u32) -> Box<Buf> {
-        let mut buf = box Buf::new();
-        buf.dev = dev;
-        buf.blockno = blockno;
-        self.rw(buf, false)
+    /// Notify device of new buffers in `queue`
+    pub fn notify(&self, queue: u32) {
+        self.write(VIRTIO_MMIO::QUEUE_NOTIFY, queue);
     }
 
-    /// Write buffer to disk
-    pub fn write(&mut self, buf: Box<Buf>) {
-        self.rw(buf, true);
+    /// Acknowledge interrupt of device
+    pub fn ack_interrupt(&self) {
+        let status = self.read(VIRTIO_MMIO::INTERRUPT_STATUS);
+        self.write(VIRTIO_MMIO::INTERRUPT_ACK, status & 0x3);
     }
 }
 
-/// VirtIO driver object
-static mut __VIRTIO: VirtIO = VirtIO::new();
-
-/// Global function to get an instance of VirtIO driver
-#[allow(non_snake_case)]
-pub fn VIRTIO() -> &'static mut VirtIO { unsafe { &mut __VIRTIO } }
-
-pub unsafe fn init() {
-    VIRTIO().init();
+/// Driver of a virtio device
+pub trait Driver {
+    /// Handle interrupt of device, which is already acknowledged
+    fn intr(&self);
 }
 
+/// A device on virtio bus
+pub struct Slot {
+    pub transport: Transport,
+    pub device_type: DeviceType,
+    /// Driver of device, or `None` if device type is not supported
+    pub driver: Option<&'static dyn Driver>,
+}
 
-/// VIRTIO interrupt
-pub fn virtiointr() {
-    use crate::info;
-    let mut disk = VIRTIO().0.lock();
-    while disk.used_idx as usize % DESC_NUM != disk.used[0].id as usize % DESC_NUM {
-
-        let id = disk.used[0].elems[disk.used_idx as usize].id as usize;
+/// Devices found on virtio bus
+static mut BUS: Vec<Slot> = Vec::new();
 
-        if disk.info[id].is_none() {
-            panic!("invalid id");
-        }
+/// Devices found on virtio bus
+pub fn bus() -> &'static [Slot] {
+    unsafe { &BUS }
+}
 
-        let info = disk.info[id].as_mut().unwrap();
+/// Probe all virtio slots and register drivers.
+///
+/// Should be called in booting hart.
+pub unsafe fn init() {
+    for dev in machine().virtio() {
+        let transport = match Transport::probe(dev) {
+            Some(transport) => transport,
+            None => continue
+        };
+        let device_type = transport.device_type();
+        let driver = match device_type {
+            DeviceType::Block => Some(blk::probe(transport) as &'static dyn Driver),
+            _ => None
+        };
+        info!("    {:?} at 0x{:x}, irq {}{}", device_type, transport.base, transport.irq,
+              if driver.is_some() { "" } else { ", no driver" });
+        BUS.push(Slot { transport, device_type, driver });
+    }
+}
 
-        // info!("processing 0x{:x}", &*info.buf as *const _ as usize);
+/// Interrupts of devices with drivers
+pub fn irqs() -> Vec<u32> {
+    bus().iter().filter(|slot| slot.driver.is_some()).map(|slot| slot.transport.irq).collect()
+}
 
-        if info.status != 0 {
-            panic!("virtio_disk_intr status status={} id={}", info.status, id);
+/// Handle interrupt `irq` if it comes from a virtio device with driver.
+/// Returns whether it is handled.
+pub fn intr(irq: u32) -> bool {
+    for slot in bus() {
+        if let (Some(driver), true) = (slot.driver, slot.transport.irq == irq) {
+            slot.transport.ack_interrupt();
+            driver.intr();
+            return true;
         }
-
-        info.buf.disk = 0;
-
-        wakeup(&*info.buf);
-
-        disk.used_idx = ((disk.used_idx + 1) as usize % DESC_NUM) as u16;
     }
+    false
 }
 
 pub mod tests {
@@ -478,34 +323,17 @@ pub mod tests {
 
     pub fn tests() -> &'static [(&'static str, fn())] {
         &[
-            ("memory layout", test_memory_layout),
-            ("read and write", test_rw)
+            ("probe", test_probe),
         ]
     }
 
-    /// Test virtio memory layout
-    pub fn test_memory_layout() {
-        let virtio = VIRTIO().0.lock();
-        assert_eq!(&virtio.desc as *const _ as usize % PAGE_SIZE, 0);
-        assert_eq!(&virtio.used as *const _ as usize % PAGE_SIZE, 0);
-        assert_eq!(&virtio.used as *const _ as usize - &virtio.desc as *const _ as usize, PAGE_SIZE);
-    }
-
-    use crate::{print, println};
-
-    /// Test read and write
-    pub fn test_rw() {
-        let virtio = VIRTIO();
-        let b = virtio.read(1, 0);
-        unsafe { println!("size: {}", core::ptr::read(b.data.as_ptr() as *const usize)); }
-        unsafe { println!("offset: {}", core::ptr::read(b.data.as_ptr().add(8) as *const usize)); }
-        for i in 16..b.data.len() {
-            let d = b.data[i];
-            if d == 0 {
-                break;
-            }
-            print!("{}", b.data[i] as char);
-        }
-        println!();
+    /// Test that disk is found, and every device has its own interrupt
+    pub fn test_probe() {
+        assert!(bus().iter().any(|slot| slot.device_type == DeviceType::Block && slot.driver.is_some()));
+        let mut irqs: Vec<u32> = bus().iter().map(|slot| slot.transport.irq).collect();
+        let n = irqs.len();
+        irqs.sort();
+        irqs.dedup();
+        assert_eq!(irqs.len(), n);
     }
 }
@@ -0,0 +1,345 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! virtio block device driver
+
+use super::*;
+use crate::spinlock::Mutex;
+use crate::panic;
+use crate::symbols::PAGE_SIZE;
+use crate::process::{wakeup, sleep};
+use crate::arch::__sync_synchronize;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub const VIRTIO_BLK_T_IN: u32 = 0;
+pub const VIRTIO_BLK_T_OUT: u32 = 1;
+
+/// In-flight disk operation
+pub struct InflightOp {
+    /// status written by device
+    pub status: u8,
+    /// set by interrupt handler when device finishes the operation
+    pub done: bool,
+}
+
+/// Size of avail array
+const AVAIL_SZ: usize = (PAGE_SIZE - DESC_NUM * core::mem::size_of::<VRingDesc>()) / core::mem::size_of::<u16>();
+
+#[repr(C)]
+#[repr(align(4096))]
+pub struct VirtIOData {
+    /// VIRTIO MMIO descriptor register
+    pub desc: [VRingDesc; DESC_NUM],
+    /// VIRTIO MMIO descriptor avail register (padding to page size)
+    pub avail: [u16; AVAIL_SZ],
+    /// VIRTIO MMIO descriptor used register
+    pub used: [UsedArea; DESC_NUM],
+
+    /// is descriptor free
+    pub free: [bool; DESC_NUM],
+    /// used index of used array
+    pub used_idx: u16,
+    /// in-flight operations
+    pub info: [Option<InflightOp>; DESC_NUM],
+}
+
+/// Driver of a virtio disk
+pub struct Blk {
+    data: Mutex<VirtIOData>,
+    transport: Transport,
+}
+
+/// VIRTIO buffer size
+pub const BSIZE: usize = 1024;
+
+#[repr(C)]
+pub struct BlkOutHdr {
+    pub blk_type: u32,
+    reserved: u32,
+    sector: usize,
+}
+
+impl VirtIOData {
+    /// Free one descriptor
+    fn free_desc(&mut self, i: usize) {
+        if i >= DESC_NUM {
+            panic!("invalid desc");
+        }
+        if self.free[i] {
+            panic!("already free");
+        }
+        self.desc[i].addr = 0;
+        self.free[i] = true;
+        wakeup(&self.free[0]);
+    }
+
+    /// Allocate one descriptor
+    fn alloc_desc(&mut self) -> Option<usize> {
+        for i in 0..DESC_NUM {
+            if self.free[i] {
+                self.free[i] = false;
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Allocate three descriptors, return array of indices
+    fn alloc3_desc(&mut self) -> Option<[usize; 3]> {
+        let mut idx = [0; 3];
+        for i in 0..3 {
+            match self.alloc_desc() {
+                Some(x) => idx[i] = x,
+                None => {
+                    for j in 0..i {
+                        self.free_desc(idx[j]);
+                    }
+                    return None;
+                }
+            }
+        }
+        Some(idx)
+    }
+
+    /// Mark operations in used ring as done, and wake up processes waiting for them
+    fn handle_used(&mut self) {
+        while self.used_idx as usize % DESC_NUM != self.used[0].id as usize % DESC_NUM {
+
+            let id = self.used[0].elems[self.used_idx as usize].id as usize;
+
+            if self.info[id].is_none() {
+                panic!("invalid id");
+            }
+
+            let info = self.info[id].as_mut().unwrap();
+
+            if info.status != 0 {
+                panic!("virtio_disk_intr status status={} id={}", info.status, id);
+            }
+
+            info.done = true;
+
+            wakeup(info as *const InflightOp);
+
+            self.used_idx = ((self.used_idx + 1) as usize % DESC_NUM) as u16;
+        }
+    }
+
+    /// Free descriptor chain
+    fn free_chain(&mut self, mut i: usize) {
+        loop {
+            self.free_desc(i);
+            if self.desc[i].flags & VRING_DESC_F_NEXT != 0 {
+                i = self.desc[i].next as usize;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+impl Blk {
+    /// Driver of disk on `transport`, which should be initialized by `init`
+    fn new(transport: Transport) -> Self {
+        Self {
+            data: Mutex::new(VirtIOData {
+                desc: [VRingDesc::new(); DESC_NUM],
+                avail: [0; AVAIL_SZ],
+                used: [UsedArea::new(); DESC_NUM],
+                free: [false; DESC_NUM],
+                used_idx: 0,
+                info: [None; DESC_NUM],
+            }, "vdisk"),
+            transport,
+        }
+    }
+
+    /// Initialize device and its only virtqueue
+    unsafe fn init(&mut self) {
+        use VIRTIO_FEATURE::*;
+
+        self.transport.init(BLK_F_RO.bit() | BLK_F_SCSI.bit() | BLK_F_CONFIG_WCE.bit()
+            | BLK_F_MQ.bit() | F_ANY_LAYOUT.bit() | RING_F_EVENT_IDX.bit()
+            | RING_F_INDIRECT_DESC.bit());
+
+        let vio = self.data.get();
+        self.transport.init_queue(0, DESC_NUM as u32, vio as *mut _ as usize);
+
+        for i in 0..DESC_NUM {
+            vio.free[i] = true;
+        }
+    }
+
+    /// Read-write operation. Device reads from or writes to `data` directly.
+    ///
+    /// If `poll` is set, used ring is polled until the operation is done.
+    /// Otherwise, current process sleeps until the interrupt handler wakes it up.
+    fn rw(&self, blockno: u32, data: *mut u8, write: bool, poll: bool) {
+        let sector = blockno as usize * (BSIZE / 512);
+
+        let mut vio = self.data.lock();
+
+        let idx: [usize; 3] = loop {
+            if let Some(idx) = vio.alloc3_desc() {
+                break idx;
+            }
+            if poll {
+                __sync_synchronize();
+                vio.handle_used();
+            } else {
+                vio = sleep(&vio.free[0] as *const _, vio);
+            }
+        };
+
+        // info!("3 desc: {:?}", idx);
+
+        let buf0 = BlkOutHdr {
+            reserved: 0,
+            sector,
+            blk_type: if write { VIRTIO_BLK_T_OUT } else { VIRTIO_BLK_T_IN },
+        };
+
+
+        // VIRTIO 5.2.6.4
+        // MUST use a single 8-byte descriptor containing type, reserved and sector,
+        // followed by descriptors for data, then finally a separate 1-byte descriptor for status.
+
+        {
+            let desc0 = &mut vio.desc[idx[0]];
+            desc0.addr = &buf0 as *const _ as usize;
+            desc0.len = core::mem::size_of::<BlkOutHdr>() as u32;
+            desc0.flags = VRING_DESC_F_NEXT;
+            desc0.next = idx[1] as u16;
+        }
+
+        {
+            let desc1 = &mut vio.desc[idx[1]];
+            desc1.addr = data as usize;
+            desc1.len = BSIZE as u32;
+            desc1.flags = if write { 0 } else { VRING_DESC_F_WRITE };
+            desc1.flags |= VRING_DESC_F_NEXT;
+            desc1.next = idx[2] as u16;
+        }
+
+        vio.info[idx[0]] = Some(InflightOp {
+            status: 0,
+            done: false,
+        });
+
+        {
+            let addr = &vio.info[idx[0]].as_ref().unwrap().status as *const _ as usize;
+            {
+                let desc2 = &mut vio.desc[idx[2]];
+
+                desc2.addr = addr;
+                desc2.len = 1;
+                desc2.flags = VRING_DESC_F_WRITE;
+                desc2.next = 0;
+            }
+
+            let idx_id = 2 + vio.avail[1] as usize % DESC_NUM;
+            vio.avail[idx_id] = idx[0] as u16;
+
+            __sync_synchronize();
+
+            vio.avail[1] = vio.avail[1] + 1;
+
+            self.transport.notify(0);
+            let op_addr = vio.info[idx[0]].as_ref().unwrap() as *const InflightOp;
+            while !vio.info[idx[0]].as_ref().unwrap().done {
+                if poll {
+                    __sync_synchronize();
+                    vio.handle_used();
+                } else {
+                    vio = sleep(op_addr, vio);
+                }
+            }
+        }
+        vio.info[idx[0]] = None;
+        vio.free_chain(idx[0]);
+    }
+
+    /// Read block `blockno` into `data`
+    pub fn read(&self, blockno: u32, data: &mut [u8; BSIZE]) {
+        self.rw(blockno, data.as_mut_ptr(), false, false);
+    }
+
+    /// Write `data` to block `blockno`
+    pub fn write(&self, blockno: u32, data: &[u8; BSIZE]) {
+        self.rw(blockno, data.as_ptr() as *mut u8, true, false);
+    }
+
+    /// Read block `blockno` into `data` by polling.
+    /// Can be used before there is any process, e.g. at boot time.
+    pub fn read_poll(&self, blockno: u32, data: &mut [u8; BSIZE]) {
+        self.rw(blockno, data.as_mut_ptr(), false, true);
+    }
+
+    /// Write `data` to block `blockno` by polling.
+    /// Can be used before there is any process, e.g. at boot time.
+    pub fn write_poll(&self, blockno: u32, data: &[u8; BSIZE]) {
+        self.rw(blockno, data.as_ptr() as *mut u8, true, true);
+    }
+}
+
+impl Driver for Blk {
+    fn intr(&self) {
+        self.data.lock().handle_used();
+    }
+}
+
+/// Disks found on virtio bus, in the order of their slots
+static mut DISKS: Vec<&'static Blk> = Vec::new();
+
+/// Register driver of disk on `transport`
+pub unsafe fn probe(transport: Transport) -> &'static Blk {
+    let blk = Box::leak(box Blk::new(transport));
+    blk.init();
+    let blk: &'static Blk = blk;
+    DISKS.push(blk);
+    blk
+}
+
+/// Driver of disk with device number `dev`, or `None` if there's no such disk.
+///
+/// Disks are numbered from 1 in the order of their slots, so the disk
+/// attached to `virtio-mmio-bus.0` is device 1, which holds root file system.
+pub fn disk(dev: u32) -> Option<&'static Blk> {
+    unsafe { DISKS.get((dev as usize).wrapping_sub(1)).copied() }
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn tests() -> &'static [(&'static str, fn())] {
+        &[
+            ("memory layout", test_memory_layout),
+            ("read and write", test_rw)
+        ]
+    }
+
+    /// Test virtio memory layout
+    pub fn test_memory_layout() {
+        let virtio = disk(1).unwrap().data.lock();
+        assert_eq!(&virtio.desc as *const _ as usize % PAGE_SIZE, 0);
+        assert_eq!(&virtio.used as *const _ as usize % PAGE_SIZE, 0);
+        assert_eq!(&virtio.used as *const _ as usize - &virtio.desc as *const _ as usize, PAGE_SIZE);
+    }
+
+    use crate::{print, println};
+
+    /// Test read and write
+    pub fn test_rw() {
+        let virtio = disk(1).unwrap();
+        let mut data = box [0; BSIZE];
+        virtio.read(1, &mut data);
+        unsafe { println!("magic: {:x}", core::ptr::read(data.as_ptr() as *const u32)); }
+        virtio.write(1, &data);
+        let mut data2 = box [0; BSIZE];
+        virtio.read(1, &mut data2);
+        assert_eq!(&data[..], &data2[..]);
+    }
+}
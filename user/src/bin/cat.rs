@@ -0,0 +1,57 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Concatenate files to stdout. Reads stdin if no file is given.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::syscall::{open, close, read, write, exit};
+use user::util::{Args, report};
+use user::constant::{STDIN, STDOUT, O_RDONLY};
+use user::error::Result;
+
+/// Copy everything from `fd` to stdout
+fn cat(fd: i32) -> Result<()> {
+    let mut buf = [0; 512];
+    loop {
+        let n = read(fd, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        write(STDOUT, &buf[..n])?;
+    }
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "cat", "", "[file...]");
+    if args.operands.is_empty() {
+        if let Err(err) = cat(STDIN) {
+            report(args.prog, "stdin", err);
+            exit(1);
+        }
+        return;
+    }
+    let mut failed = false;
+    for path in &args.operands {
+        let result = open(path, O_RDONLY).and_then(|fd| {
+            let result = cat(fd);
+            let _ = close(fd);
+            result
+        });
+        if let Err(err) = result {
+            report(args.prog, path, err);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
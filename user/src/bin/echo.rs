@@ -0,0 +1,36 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Print arguments separated by spaces. `-n` omits the trailing newline.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+extern crate alloc;
+
+use user::syscall::write;
+use user::util::Args;
+use user::constant::STDOUT;
+use alloc::vec::Vec;
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "echo", "n", "[-n] [string...]");
+    let mut out = Vec::new();
+    for (i, arg) in args.operands.iter().enumerate() {
+        if i != 0 {
+            out.push(b' ');
+        }
+        out.extend_from_slice(arg.as_bytes());
+    }
+    if !args.flag('n') {
+        out.push(b'\n');
+    }
+    let _ = write(STDOUT, &out);
+}
@@ -0,0 +1,103 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Print lines containing `pattern`. Reads stdin if no file is given.
+//!
+//! `-v` prints lines not containing `pattern`, and `-c` prints number of
+//! matching lines instead. Exits with 1 if nothing matches.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::{print, println};
+use user::syscall::{open, close, exit, write};
+use user::util::{Args, report, read_to_end, die_usage};
+use user::constant::{STDIN, STDOUT, O_RDONLY};
+use user::error::Result;
+
+const USAGE: &str = "[-vc] pattern [file...]";
+
+/// Whether `pattern` appears in `line`
+fn contains(line: &[u8], pattern: &[u8]) -> bool {
+    pattern.is_empty() || line.windows(pattern.len()).any(|w| w == pattern)
+}
+
+/// Print matching lines of `fd`, prefixed by `name` if it is set.
+/// Returns number of matching lines.
+fn grep(args: &Args, pattern: &[u8], fd: i32, name: Option<&str>) -> Result<usize> {
+    let content = read_to_end(fd)?;
+    if content.is_empty() {
+        return Ok(0);
+    }
+    // don't treat trailing newline as the start of an empty line
+    let end = if content.last() == Some(&b'\n') { content.len() - 1 } else { content.len() };
+    let mut matched = 0;
+    for line in content[..end].split(|c| *c == b'\n') {
+        if contains(line, pattern) == args.flag('v') {
+            continue;
+        }
+        matched += 1;
+        if args.flag('c') {
+            continue;
+        }
+        if let Some(name) = name {
+            print!("{}:", name);
+        }
+        write(STDOUT, line)?;
+        write(STDOUT, b"\n")?;
+    }
+    if args.flag('c') {
+        match name {
+            Some(name) => println!("{}:{}", name, matched),
+            None => println!("{}", matched)
+        }
+    }
+    Ok(matched)
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "grep", "vc", USAGE);
+    let (pattern, files) = match args.operands.split_first() {
+        Some((pattern, files)) => (pattern.as_bytes(), files),
+        None => die_usage(args.prog, USAGE)
+    };
+    let mut matched = 0;
+    let mut failed = false;
+    if files.is_empty() {
+        match grep(&args, pattern, STDIN, None) {
+            Ok(n) => matched += n,
+            Err(err) => {
+                report(args.prog, "stdin", err);
+                failed = true;
+            }
+        }
+    }
+    for path in files {
+        let name = if files.len() > 1 { Some(*path) } else { None };
+        let result = open(path, O_RDONLY).and_then(|fd| {
+            let result = grep(&args, pattern, fd, name);
+            let _ = close(fd);
+            result
+        });
+        match result {
+            Ok(n) => matched += n,
+            Err(err) => {
+                report(args.prog, path, err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        exit(2);
+    }
+    if matched == 0 {
+        exit(1);
+    }
+}
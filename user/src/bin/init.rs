@@ -11,19 +11,42 @@
 #![feature(const_generics)]
 
 use user::println;
-use user::syscall::{fork, exec, open, dup};
+use user::syscall::{exit, fork, exec, open, dup, wait, mknod};
+use user::constant::{CONSOLE, O_RDWR};
 
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
-    open("/console", 0);
-    dup(0);
-    dup(0);
-    println!("ready to fork!");
-    let p = fork();
-    if p == 0 {
-        println!("calling test1...");
-        exec("/test1", &["test1", "test2"]);
-    } else {
-        loop {}
+pub fn main(_args: &[&str]) {
+    if open("/console", O_RDWR).is_err() {
+        mknod("/console", CONSOLE, 0).unwrap();
+        open("/console", O_RDWR).unwrap();
+    }
+    dup(0).unwrap();
+    dup(0).unwrap();
+    loop {
+        println!("init: starting sh");
+        let pid = match fork() {
+            Ok(pid) => pid,
+            Err(err) => {
+                println!("init: fork failed: {}", err);
+                exit(1);
+            }
+        };
+        if pid == 0 {
+            let err = exec("/sh", &["sh"]);
+            println!("init: exec /sh failed: {}", err);
+            exit(1);
+        }
+        // reap orphaned processes until shell exits, and then restart it
+        let mut status = 0;
+        loop {
+            match wait(-1, &mut status) {
+                Ok(wpid) if wpid == pid => break,
+                Ok(_) => {}
+                Err(err) => {
+                    println!("init: wait failed: {}", err);
+                    exit(1);
+                }
+            }
+        }
     }
 }
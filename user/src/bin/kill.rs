@@ -0,0 +1,78 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Send a signal to processes, which is `SIGTERM` by default.
+//!
+//! The signal may be given by number or name, e.g. `kill -9 3` or
+//! `kill -KILL 3`. `kill -l` lists all signals.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::{println, eprintln};
+use user::syscall::{kill, exit};
+use user::signal::{Signal, SIGNALS};
+use user::util::{report, die_usage};
+
+const USAGE: &str = "[-signal] pid... | -l";
+
+/// Parse signal given by number or name
+fn parse_signal(spec: &str) -> Option<Signal> {
+    match spec.parse() {
+        Ok(signum) => Signal::from_signum(signum),
+        Err(_) => Signal::from_name(spec)
+    }
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let prog = args.first().copied().unwrap_or("kill");
+    let mut pids = args.get(1..).unwrap_or(&[]);
+    let mut sig = Signal::SIGTERM;
+    match pids.first() {
+        Some(&"-l") => {
+            for sig in SIGNALS.iter() {
+                println!("{:2} {}", *sig as i32, sig.name());
+            }
+            return;
+        }
+        Some(arg) if arg.starts_with('-') => {
+            sig = match parse_signal(&arg[1..]) {
+                Some(sig) => sig,
+                None => {
+                    eprintln!("{}: unknown signal {}", prog, &arg[1..]);
+                    die_usage(prog, USAGE);
+                }
+            };
+            pids = &pids[1..];
+        }
+        _ => {}
+    }
+    if pids.is_empty() {
+        die_usage(prog, USAGE);
+    }
+    let mut failed = false;
+    for arg in pids {
+        let result = match arg.parse() {
+            Ok(pid) => kill(pid, sig),
+            Err(_) => {
+                eprintln!("{}: invalid pid {}", prog, arg);
+                failed = true;
+                continue;
+            }
+        };
+        if let Err(err) = result {
+            report(prog, arg, err);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Create a hard link `new` to file `old`
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::syscall::{link, exit};
+use user::util::{Args, report, die_usage};
+
+const USAGE: &str = "old new";
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "ln", "", USAGE);
+    if args.operands.len() != 2 {
+        die_usage(args.prog, USAGE);
+    }
+    let (old, new) = (args.operands[0], args.operands[1]);
+    if let Err(err) = link(old, new) {
+        report(args.prog, new, err);
+        exit(1);
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! List directories. Prints type, inode number and size of every entry.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+extern crate alloc;
+
+use user::println;
+use user::syscall::{open, close, fstat, exit, Stat};
+use user::util::{Args, report, read_dirent};
+use user::constant::{O_RDONLY, T_DIR, T_FILE, T_DEVICE};
+use user::error::Result;
+use alloc::string::String;
+
+fn type_name(st: &Stat) -> &'static str {
+    match st.typ {
+        T_DIR => "dir",
+        T_FILE => "file",
+        T_DEVICE => "dev",
+        _ => "?"
+    }
+}
+
+fn print_entry(name: &str, st: &Stat) {
+    println!("{:14} {:4} {:4} {}", name, type_name(st), st.ino, st.size);
+}
+
+/// Get status of file at `path`
+fn stat(path: &str) -> Result<Stat> {
+    let fd = open(path, O_RDONLY)?;
+    let st = fstat(fd);
+    let _ = close(fd);
+    st
+}
+
+/// List directory or file at `path`
+fn ls(prog: &str, path: &str) -> Result<()> {
+    let fd = open(path, O_RDONLY)?;
+    let st = match fstat(fd) {
+        Ok(st) => st,
+        Err(err) => {
+            let _ = close(fd);
+            return Err(err);
+        }
+    };
+    if st.typ != T_DIR {
+        print_entry(path, &st);
+        let _ = close(fd);
+        return Ok(());
+    }
+    loop {
+        let de = match read_dirent(fd) {
+            Ok(Some(de)) => de,
+            Ok(None) => break,
+            Err(err) => {
+                let _ = close(fd);
+                return Err(err);
+            }
+        };
+        let name = core::str::from_utf8(de.name()).unwrap_or("?");
+        let mut entry = String::from(path);
+        entry.push('/');
+        entry.push_str(name);
+        match stat(&entry) {
+            Ok(st) => print_entry(name, &st),
+            Err(err) => report(prog, &entry, err)
+        }
+    }
+    let _ = close(fd);
+    Ok(())
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "ls", "", "[path...]");
+    let paths = if args.operands.is_empty() { &["."][..] } else { &args.operands[..] };
+    let mut failed = false;
+    for path in paths {
+        if paths.len() > 1 {
+            println!("{}:", path);
+        }
+        if let Err(err) = ls(args.prog, path) {
+            report(args.prog, path, err);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
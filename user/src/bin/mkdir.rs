@@ -0,0 +1,36 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Create directories
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::syscall::{mkdir, exit};
+use user::util::{Args, report, die_usage};
+
+const USAGE: &str = "directory...";
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "mkdir", "", USAGE);
+    if args.operands.is_empty() {
+        die_usage(args.prog, USAGE);
+    }
+    let mut failed = false;
+    for path in &args.operands {
+        if let Err(err) = mkdir(path) {
+            report(args.prog, path, err);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Mount file system on a disk at a directory, e.g. `mount 2 /mnt`.
+//!
+//! Disks are numbered from 1 in the order they are attached to virtio bus.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::eprintln;
+use user::syscall::{mount, exit};
+use user::util::{Args, report, die_usage};
+
+const USAGE: &str = "disk directory";
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "mount", "", USAGE);
+    if args.operands.len() != 2 {
+        die_usage(args.prog, USAGE);
+    }
+    let (disk, dir) = (args.operands[0], args.operands[1]);
+    let dev = match disk.parse() {
+        Ok(dev) => dev,
+        Err(_) => {
+            eprintln!("{}: invalid disk {}", args.prog, disk);
+            die_usage(args.prog, USAGE);
+        }
+    };
+    if let Err(err) = mount(dev, dir) {
+        report(args.prog, dir, err);
+        exit(1);
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! List processes
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+extern crate alloc;
+
+use user::println;
+use user::syscall::{procinfo, exit, ProcInfo};
+use user::util::{Args, report};
+use alloc::vec;
+
+/// Maximum number of processes, which is the same as `NMAXPROCS` in kernel
+const MAX_PROCS: usize = 256;
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "ps", "", "");
+    let mut infos = vec![ProcInfo::default(); MAX_PROCS];
+    let n = match procinfo(&mut infos) {
+        Ok(n) => n,
+        Err(err) => {
+            report(args.prog, "procinfo", err);
+            exit(1);
+        }
+    };
+    println!("{:>4} {:>4} {:9} {}", "PID", "PPID", "STATE", "NAME");
+    for info in &infos[..n] {
+        println!("{:>4} {:>4} {:9} {}", info.pid, info.ppid, info.state_name(), info.name());
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Remove files or empty directories. `-f` ignores errors.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::syscall::{unlink, exit};
+use user::util::{Args, report, die_usage};
+
+const USAGE: &str = "[-f] file...";
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "rm", "f", USAGE);
+    if args.operands.is_empty() {
+        die_usage(args.prog, USAGE);
+    }
+    let mut failed = false;
+    for path in &args.operands {
+        if let Err(err) = unlink(path) {
+            if !args.flag('f') {
+                report(args.prog, path, err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
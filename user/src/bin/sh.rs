@@ -0,0 +1,327 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Shell
+//!
+//! Supports `<` and `>` redirection, `|` pipelines, background jobs with
+//! trailing `&`, and built-in commands `cd` and `exit`. Programs without
+//! `/` in their names are looked up in root directory.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+extern crate alloc;
+
+use user::{print, println};
+use user::syscall::{exit, fork, exec, open, close, dup, read, write, wait, pipe, chdir};
+use user::constant::{STDIN, STDOUT, O_RDONLY, O_WRONLY, O_CREATE, O_TRUNC};
+use user::error::Error;
+use user::signal::Signal;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+/// Maximum length of a command line
+const MAX_LINE: usize = 128;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+/// `Ctrl-D`, end of input on an empty line
+const CTRL_D: u8 = 0x04;
+/// `Ctrl-U`, erase the whole line
+const CTRL_U: u8 = 0x15;
+
+/// Erase one character on terminal
+fn erase() {
+    let _ = write(STDOUT, &[BACKSPACE, b' ', BACKSPACE]);
+}
+
+/// Read a line from stdin with simple line editing. Characters are echoed,
+/// as console doesn't echo input.
+///
+/// Returns `None` on end of input.
+fn read_line() -> Option<String> {
+    let mut line = String::new();
+    let mut c = [0; 1];
+    loop {
+        match read(STDIN, &mut c) {
+            Ok(1) => {}
+            _ => {
+                return if line.is_empty() { None } else { Some(line) };
+            }
+        }
+        match c[0] {
+            b'\r' | b'\n' => {
+                println!();
+                return Some(line);
+            }
+            BACKSPACE | DELETE => {
+                if line.pop().is_some() {
+                    erase();
+                }
+            }
+            CTRL_U => {
+                while line.pop().is_some() {
+                    erase();
+                }
+            }
+            CTRL_D if line.is_empty() => return None,
+            ch @ 0x20..=0x7e if line.len() < MAX_LINE => {
+                line.push(ch as char);
+                let _ = write(STDOUT, &c);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Token<'a> {
+    Word(&'a str),
+    In,
+    Out,
+    Pipe,
+    Background,
+}
+
+/// Split a command line into tokens. Operators need not be separated by spaces.
+fn tokenize(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = line;
+    loop {
+        rest = rest.trim_start();
+        let token = match rest.chars().next() {
+            None => return tokens,
+            Some('<') => Token::In,
+            Some('>') => Token::Out,
+            Some('|') => Token::Pipe,
+            Some('&') => Token::Background,
+            Some(_) => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || "<>|&".contains(c))
+                    .unwrap_or(rest.len());
+                tokens.push(Token::Word(&rest[..end]));
+                rest = &rest[end..];
+                continue;
+            }
+        };
+        tokens.push(token);
+        rest = &rest[1..];
+    }
+}
+
+/// A command with its arguments and redirections
+#[derive(Default)]
+struct Command<'a> {
+    args: Vec<&'a str>,
+    stdin: Option<&'a str>,
+    stdout: Option<&'a str>,
+}
+
+/// Commands connected with pipes
+struct Pipeline<'a> {
+    commands: Vec<Command<'a>>,
+    background: bool,
+}
+
+/// Parse tokens into a pipeline, or returns a message on syntax error.
+fn parse<'a>(tokens: &[Token<'a>]) -> Result<Pipeline<'a>, &'static str> {
+    let mut pipeline = Pipeline { commands: Vec::new(), background: false };
+    let mut cmd = Command::default();
+    let mut iter = tokens.iter();
+    while let Some(token) = iter.next() {
+        if pipeline.background {
+            return Err("& should be at the end of command");
+        }
+        match token {
+            Token::Word(word) => cmd.args.push(*word),
+            Token::In | Token::Out => {
+                let file = match iter.next() {
+                    Some(Token::Word(file)) => *file,
+                    _ => return Err("missing file name for redirection")
+                };
+                if *token == Token::In {
+                    cmd.stdin = Some(file);
+                } else {
+                    cmd.stdout = Some(file);
+                }
+            }
+            Token::Pipe => {
+                if cmd.args.is_empty() {
+                    return Err("missing command before |");
+                }
+                pipeline.commands.push(core::mem::replace(&mut cmd, Command::default()));
+            }
+            Token::Background => pipeline.background = true,
+        }
+    }
+    if cmd.args.is_empty() {
+        if !pipeline.commands.is_empty() {
+            return Err("missing command after |");
+        }
+        if pipeline.background || cmd.stdin.is_some() || cmd.stdout.is_some() {
+            return Err("missing command");
+        }
+    } else {
+        pipeline.commands.push(cmd);
+    }
+    Ok(pipeline)
+}
+
+/// Replace file descriptor `fd` with `new_fd`, and close `new_fd`.
+fn replace_fd(fd: i32, new_fd: i32) {
+    close(fd).unwrap();
+    dup(new_fd).unwrap();
+    close(new_fd).unwrap();
+}
+
+/// Open `path` in place of file descriptor `fd`
+fn redirect(fd: i32, path: &str, mode: i32) -> Result<(), Error> {
+    let new_fd = open(path, mode)?;
+    replace_fd(fd, new_fd);
+    Ok(())
+}
+
+/// Set up redirections in child process and exec `cmd`. Never returns.
+fn run_command(cmd: &Command) -> ! {
+    if let Some(path) = cmd.stdin {
+        if let Err(err) = redirect(STDIN, path, O_RDONLY) {
+            println!("sh: {}: {}", path, err);
+            exit(1);
+        }
+    }
+    if let Some(path) = cmd.stdout {
+        if let Err(err) = redirect(STDOUT, path, O_WRONLY | O_CREATE | O_TRUNC) {
+            println!("sh: {}: {}", path, err);
+            exit(1);
+        }
+    }
+    let name = cmd.args[0];
+    let err = if name.contains('/') {
+        exec(name, &cmd.args)
+    } else {
+        let mut path = String::from("/");
+        path.push_str(name);
+        exec(&path, &cmd.args)
+    };
+    println!("sh: {}: {}", name, err);
+    exit(1);
+}
+
+/// Run built-in command in shell process. Returns false if `cmd` is not a built-in.
+fn run_builtin(cmd: &Command) -> bool {
+    match cmd.args[0] {
+        "cd" => {
+            let path = cmd.args.get(1).copied().unwrap_or("/");
+            if let Err(err) = chdir(path) {
+                println!("sh: cd: {}: {}", path, err);
+            }
+            true
+        }
+        "exit" => {
+            let code = cmd.args.get(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+            exit(code);
+        }
+        _ => false
+    }
+}
+
+/// Fork a process for every command in `pipeline`, connect them with pipes,
+/// and wait for them unless it runs in background.
+fn run_pipeline(pipeline: &Pipeline) {
+    let mut pids = Vec::new();
+    // read end of pipe from previous command
+    let mut prev_read = None;
+    for (i, cmd) in pipeline.commands.iter().enumerate() {
+        let mut fds = [0; 2];
+        let is_last = i + 1 == pipeline.commands.len();
+        if !is_last {
+            if let Err(err) = pipe(&mut fds) {
+                println!("sh: pipe: {}", err);
+                break;
+            }
+        }
+        match fork() {
+            Ok(0) => {
+                if let Some(fd) = prev_read {
+                    replace_fd(STDIN, fd);
+                }
+                if !is_last {
+                    close(fds[0]).unwrap();
+                    replace_fd(STDOUT, fds[1]);
+                }
+                run_command(cmd);
+            }
+            Ok(pid) => pids.push(pid),
+            Err(err) => println!("sh: fork: {}", err)
+        }
+        if let Some(fd) = prev_read.take() {
+            let _ = close(fd);
+        }
+        if !is_last {
+            let _ = close(fds[1]);
+            prev_read = Some(fds[0]);
+        }
+    }
+    if let Some(fd) = prev_read {
+        let _ = close(fd);
+    }
+    if pipeline.background {
+        for pid in pids {
+            println!("[{}]", pid);
+        }
+        return;
+    }
+    // also reap background jobs which have exited
+    let mut status = 0;
+    while !pids.is_empty() {
+        match wait(-1, &mut status) {
+            Ok(pid) => {
+                let background = match pids.iter().position(|p| *p == pid) {
+                    Some(pos) => {
+                        pids.remove(pos);
+                        false
+                    }
+                    None => true
+                };
+                match Signal::from_status(status) {
+                    Some(sig) => println!("[{}] {}", pid, sig),
+                    None if background => println!("[{}] done {}", pid, status),
+                    None => {}
+                }
+            }
+            Err(_) => break
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) {
+    loop {
+        print!("$ ");
+        let line = match read_line() {
+            Some(line) => line,
+            None => break
+        };
+        let tokens = tokenize(&line);
+        let pipeline = match parse(&tokens) {
+            Ok(pipeline) => pipeline,
+            Err(msg) => {
+                println!("sh: {}", msg);
+                continue;
+            }
+        };
+        if pipeline.commands.is_empty() {
+            continue;
+        }
+        if pipeline.commands.len() == 1 && !pipeline.background && run_builtin(&pipeline.commands[0]) {
+            continue;
+        }
+        run_pipeline(&pipeline);
+    }
+}
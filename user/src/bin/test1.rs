@@ -15,16 +15,23 @@ use user::syscall::{exit, fork, exec, open, read, write};
 use user::constant::STDOUT;
 
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
-    let p = fork();
-    if p == 0 {
+pub fn main(_args: &[&str]) {
+    if fork() == Ok(0) {
         println!("forking test2...");
-        exec("/test2", &["test1", "test2"]);
+        let err = exec("/test2", &["test2"]);
+        println!("test1: exec /test2 failed: {}", err);
+        exit(1);
     }
     println!("test1 running...");
-    let fd = open("/test.txt", 0);
+    let fd = match open("/test.txt", 0) {
+        Ok(fd) => fd,
+        Err(err) => {
+            println!("test1: open /test.txt failed: {}", err);
+            exit(1);
+        }
+    };
     let mut data = [0; 32];
-    read(fd, &mut data);
-    write(STDOUT, &data);
-    exit(0);
+    if let Ok(sz) = read(fd, &mut data) {
+        let _ = write(STDOUT, &data[..sz]);
+    }
 }
@@ -14,12 +14,12 @@ use user::println;
 use user::syscall::{exit, fork, exec};
 
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
-    let p = fork();
-    if p == 0 {
+pub fn main(_args: &[&str]) {
+    if fork() == Ok(0) {
         println!("forking test3...");
-        exec("/test3", &["test1", "test2"]);
+        let err = exec("/test3", &["test3", "hello", "world"]);
+        println!("test2: exec /test3 failed: {}", err);
+        exit(1);
     }
     println!("test2 running...");
-    exit(0);
 }
@@ -10,11 +10,32 @@
 #![feature(format_args_nl)]
 #![feature(const_generics)]
 
-use user::println;
-use user::syscall::exit;
+extern crate alloc;
+
+use user::{println, format};
+use alloc::vec::Vec;
+use alloc::string::String;
+use alloc::boxed::Box;
+
+/// A small object aligned to more than a page
+#[repr(align(16384))]
+struct Aligned(u8);
 
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub fn main(args: &[&str]) {
     println!("test3!");
-    exit(0);
+    for (i, arg) in args.iter().enumerate() {
+        println!("argv[{}] = {}", i, arg);
+    }
+    // exercise allocator
+    let mut words: Vec<String> = args.iter().map(|arg| format!("<{}>", arg)).collect();
+    words.reverse();
+    println!("reversed: {}", words.join(" "));
+    let big: Vec<u64> = (0..2048).collect();
+    println!("sum: {}", big.iter().sum::<u64>());
+    let aligned: Vec<Box<Aligned>> = (0..3).map(|i| Box::new(Aligned(i))).collect();
+    for x in &aligned {
+        assert_eq!(&**x as *const Aligned as usize % 16384, 0);
+    }
+    println!("aligned: {}", aligned.iter().map(|x| x.0 as usize).sum::<usize>());
 }
@@ -0,0 +1,97 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Count lines, words and bytes of files. Reads stdin if no file is given.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(format_args_nl)]
+#![feature(const_generics)]
+
+use user::println;
+use user::syscall::{open, close, read, exit};
+use user::util::{Args, report};
+use user::constant::{STDIN, O_RDONLY};
+use user::error::Result;
+
+#[derive(Default)]
+struct Count {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+}
+
+/// Count everything read from `fd`
+fn wc(fd: i32) -> Result<Count> {
+    let mut count = Count::default();
+    let mut in_word = false;
+    let mut buf = [0; 512];
+    loop {
+        let n = read(fd, &mut buf)?;
+        if n == 0 {
+            return Ok(count);
+        }
+        count.bytes += n;
+        for c in &buf[..n] {
+            if *c == b'\n' {
+                count.lines += 1;
+            }
+            if c.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                count.words += 1;
+            }
+        }
+    }
+}
+
+fn print_count(count: &Count, name: &str) {
+    println!("{} {} {} {}", count.lines, count.words, count.bytes, name);
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) {
+    let args = Args::parse(args, "wc", "", "[file...]");
+    if args.operands.is_empty() {
+        match wc(STDIN) {
+            Ok(count) => print_count(&count, ""),
+            Err(err) => {
+                report(args.prog, "stdin", err);
+                exit(1);
+            }
+        }
+        return;
+    }
+    let mut total = Count::default();
+    let mut failed = false;
+    for path in &args.operands {
+        let result = open(path, O_RDONLY).and_then(|fd| {
+            let result = wc(fd);
+            let _ = close(fd);
+            result
+        });
+        match result {
+            Ok(count) => {
+                print_count(&count, path);
+                total.lines += count.lines;
+                total.words += count.words;
+                total.bytes += count.bytes;
+            }
+            Err(err) => {
+                report(args.prog, path, err);
+                failed = true;
+            }
+        }
+    }
+    if args.operands.len() > 1 {
+        print_count(&total, "total");
+    }
+    if failed {
+        exit(1);
+    }
+}
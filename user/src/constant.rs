@@ -8,3 +8,21 @@
 pub const STDIN: i32 = 0;
 pub const STDOUT: i32 = 1;
 pub const STDERR: i32 = 2;
+
+pub use fs_defs::{T_DIR, T_FILE, T_DEVICE, DIRSIZ, DIRENT_SIZE, Dirent};
+
+/// Major device number of console
+pub const CONSOLE: i32 = 1;
+
+/// Open for reading only
+pub const O_RDONLY: i32 = 0x000;
+/// Open for writing only
+pub const O_WRONLY: i32 = 0x001;
+/// Open for reading and writing
+pub const O_RDWR: i32 = 0x002;
+/// Create file if it doesn't exist
+pub const O_CREATE: i32 = 0x200;
+/// Truncate file to zero length
+pub const O_TRUNC: i32 = 0x400;
+/// Write to the end of file
+pub const O_APPEND: i32 = 0x800;
@@ -0,0 +1,144 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Errors returned by syscalls
+//!
+//! A syscall returns `-errno` on failure. Error codes are the same as
+//! `Errno` in kernel, which follow Linux.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Error {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// No such device or address
+    ENXIO = 6,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// Cross-device link
+    EXDEV = 18,
+    /// No such device
+    ENODEV = 19,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// File table overflow
+    ENFILE = 23,
+    /// Too many open files
+    EMFILE = 24,
+    /// File too large
+    EFBIG = 27,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Broken pipe
+    EPIPE = 32,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+}
+
+/// Result of syscalls
+pub type Result<T> = core::result::Result<T, Error>;
+
+const ERRORS: [Error; 28] = [
+    Error::EPERM, Error::ENOENT, Error::ESRCH, Error::EINTR, Error::EIO, Error::ENXIO,
+    Error::E2BIG, Error::ENOEXEC, Error::EBADF, Error::ECHILD, Error::EAGAIN,
+    Error::ENOMEM, Error::EFAULT, Error::EBUSY, Error::EEXIST, Error::EXDEV, Error::ENODEV,
+    Error::ENOTDIR, Error::EISDIR, Error::EINVAL, Error::ENFILE, Error::EMFILE, Error::EFBIG,
+    Error::ENOSPC, Error::EPIPE, Error::ENAMETOOLONG, Error::ENOSYS, Error::ENOTEMPTY,
+];
+
+impl Error {
+    /// Get error of `errno`. Unknown error codes are reported as `EINVAL`.
+    pub fn from_errno(errno: i32) -> Self {
+        for err in ERRORS.iter() {
+            if *err as i32 == errno {
+                return *err;
+            }
+        }
+        Error::EINVAL
+    }
+
+    /// Turn return value of a syscall into `Result`
+    pub fn check(ret: i32) -> Result<i32> {
+        if ret < 0 {
+            Err(Error::from_errno(-ret))
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Human-readable description of error
+    pub fn description(&self) -> &'static str {
+        match self {
+            Error::EPERM => "operation not permitted",
+            Error::ENOENT => "no such file or directory",
+            Error::ESRCH => "no such process",
+            Error::EINTR => "interrupted system call",
+            Error::EIO => "I/O error",
+            Error::ENXIO => "no such device or address",
+            Error::E2BIG => "argument list too long",
+            Error::ENOEXEC => "exec format error",
+            Error::EBADF => "bad file descriptor",
+            Error::ECHILD => "no child processes",
+            Error::EAGAIN => "try again",
+            Error::ENOMEM => "out of memory",
+            Error::EFAULT => "bad address",
+            Error::EBUSY => "device or resource busy",
+            Error::EEXIST => "file exists",
+            Error::EXDEV => "cross-device link",
+            Error::ENODEV => "no such device",
+            Error::ENOTDIR => "not a directory",
+            Error::EISDIR => "is a directory",
+            Error::EINVAL => "invalid argument",
+            Error::ENFILE => "file table overflow",
+            Error::EMFILE => "too many open files",
+            Error::EFBIG => "file too large",
+            Error::ENOSPC => "no space left on device",
+            Error::EPIPE => "broken pipe",
+            Error::ENAMETOOLONG => "file name too long",
+            Error::ENOSYS => "function not implemented",
+            Error::ENOTEMPTY => "directory not empty",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
@@ -4,16 +4,57 @@
 // https://opensource.org/licenses/MIT
 
 //! User-space library
+//!
+//! User programs may use `alloc` crate (e.g. `Vec`, `String`, `Box`),
+//! which is backed by the allocator in `mem` module.
 
 #![no_std]
 #![feature(global_asm)]
+#![feature(alloc_error_handler)]
+#![feature(format_args_nl)]
+
+extern crate alloc;
 
 pub mod print;
 pub mod syscall;
 pub mod constant;
+pub mod error;
+pub mod signal;
+pub mod util;
 mod syscall_internal;
+mod mem;
 
 use core::panic::PanicInfo;
+use crate::syscall::{exit, EXEC_MAX_ARGS};
+
+/// An argument laid out on user stack by kernel in `exec`
+#[repr(C)]
+struct Arg {
+    ptr: *const u8,
+    len: usize,
+}
+
+extern "Rust" {
+    /// Entry of user program, which should be defined with `#[no_mangle]`
+    /// in every binary.
+    fn main(args: &[&str]);
+}
+
+/// Entry of all user programs. Kernel passes number of arguments in `argc`,
+/// and an array of arguments in `argv`. This function forwards them to `main`,
+/// and exits with 0 after `main` returns.
+#[no_mangle]
+unsafe extern "C" fn _start(argc: usize, argv: *const Arg) -> ! {
+    let mut args = [""; EXEC_MAX_ARGS];
+    let argc = argc.min(EXEC_MAX_ARGS);
+    for i in 0..argc {
+        let arg = &*argv.add(i);
+        let bytes = core::slice::from_raw_parts(arg.ptr, arg.len);
+        args[i] = core::str::from_utf8_unchecked(bytes);
+    }
+    main(&args[..argc]);
+    exit(0);
+}
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
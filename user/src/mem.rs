@@ -0,0 +1,252 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! User-space allocator
+//!
+//! Memory is taken from kernel page by page with `sbrk`. Small objects
+//! (up to `MAX_SMALL` bytes) are served from size classes of power-of-two
+//! sizes. Each class keeps a free list of blocks, and is refilled by
+//! splitting a whole page. Larger objects are made of contiguous pages,
+//! which are taken from a free list of page runs, sorted by address and
+//! merged on free.
+//!
+//! Alignment counts as size when choosing a size class, so an object aligned
+//! to more than `MAX_SMALL` bytes is made of pages even if it is small. Pages
+//! aligned to more than a page are cut from a longer run.
+//!
+//! User processes are single-threaded, so there's no lock around the heap.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr::{self, null_mut};
+use crate::syscall::{sbrk, exit};
+
+const PAGE_ORDER: usize = 12;
+const PAGE_SIZE: usize = 1 << PAGE_ORDER;
+
+/// Smallest block, which should hold a `FreeBlock`
+const MIN_SMALL_ORDER: usize = 4;
+/// Largest block served by size classes
+const MAX_SMALL_ORDER: usize = 11;
+const MAX_SMALL: usize = 1 << MAX_SMALL_ORDER;
+const SIZE_CLASS_CNT: usize = MAX_SMALL_ORDER - MIN_SMALL_ORDER + 1;
+
+/// Align `val` to upper bound of a page
+const fn page_up(val: usize) -> usize {
+    (val + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// A free block in size classes, stored in the block itself
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+/// A run of free pages, stored in its first page
+struct FreeRun {
+    pages: usize,
+    next: *mut FreeRun,
+}
+
+struct Heap {
+    /// Free lists of size classes, from `1 << MIN_SMALL_ORDER` bytes
+    /// to `1 << MAX_SMALL_ORDER` bytes
+    classes: [*mut FreeBlock; SIZE_CLASS_CNT],
+    /// Free page runs, sorted by address
+    runs: *mut FreeRun,
+}
+
+/// Size class of an allocation, or `None` if it should be made of pages.
+/// As blocks are split from pages, a block of size `1 << order` is
+/// aligned to `1 << order` bytes.
+fn size_class(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(1 << MIN_SMALL_ORDER);
+    if size > MAX_SMALL {
+        return None;
+    }
+    let order = size.next_power_of_two().trailing_zeros() as usize;
+    Some(order - MIN_SMALL_ORDER)
+}
+
+/// Number of pages of a large allocation
+fn page_count(layout: &Layout) -> usize {
+    page_up(layout.size()) >> PAGE_ORDER
+}
+
+impl Heap {
+    const fn new() -> Self {
+        Heap {
+            classes: [null_mut(); SIZE_CLASS_CNT],
+            runs: null_mut(),
+        }
+    }
+
+    /// Get `pages` pages from kernel. Program break is not page-aligned
+    /// after `exec`, so the first call pads it to the next page.
+    unsafe fn morecore(&mut self, pages: usize) -> *mut u8 {
+        let brk = match sbrk(0) {
+            Ok(brk) => brk,
+            Err(_) => return null_mut()
+        };
+        let pad = page_up(brk) - brk;
+        match sbrk((pad + pages * PAGE_SIZE) as isize) {
+            Ok(old) => page_up(old) as *mut u8,
+            Err(_) => null_mut()
+        }
+    }
+
+    /// Allocate `pages` contiguous pages with first-fit
+    unsafe fn alloc_pages(&mut self, pages: usize) -> *mut u8 {
+        let mut prev: *mut *mut FreeRun = &mut self.runs;
+        while !(*prev).is_null() {
+            let run = *prev;
+            if (*run).pages == pages {
+                *prev = (*run).next;
+                return run as *mut u8;
+            }
+            if (*run).pages > pages {
+                // take pages from the end of run, so that it stays in place
+                (*run).pages -= pages;
+                return (run as *mut u8).add((*run).pages * PAGE_SIZE);
+            }
+            prev = &mut (*run).next;
+        }
+        self.morecore(pages)
+    }
+
+    /// Allocate `pages` contiguous pages aligned to `align`, which is a multiple
+    /// of page size, and give back the unaligned pages around them
+    unsafe fn alloc_aligned_pages(&mut self, pages: usize, align: usize) -> *mut u8 {
+        let extra = align / PAGE_SIZE - 1;
+        let run = self.alloc_pages(pages + extra);
+        if run.is_null() {
+            return null_mut();
+        }
+        let head = (align - run as usize % align) % align / PAGE_SIZE;
+        let tail = extra - head;
+        if head != 0 {
+            self.free_pages(run, head);
+        }
+        if tail != 0 {
+            self.free_pages(run.add((head + pages) * PAGE_SIZE), tail);
+        }
+        run.add(head * PAGE_SIZE)
+    }
+
+    /// Free `pages` pages at `ptr`, and merge them with adjacent runs
+    unsafe fn free_pages(&mut self, ptr: *mut u8, pages: usize) {
+        let new = ptr as *mut FreeRun;
+        let mut prev: *mut FreeRun = null_mut();
+        let mut next = self.runs;
+        while !next.is_null() && next < new {
+            prev = next;
+            next = (*next).next;
+        }
+        (*new).pages = pages;
+        (*new).next = next;
+        if !next.is_null() && ptr.add(pages * PAGE_SIZE) == next as *mut u8 {
+            (*new).pages += (*next).pages;
+            (*new).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.runs = new;
+        } else if (prev as *mut u8).add((*prev).pages * PAGE_SIZE) == ptr {
+            (*prev).pages += (*new).pages;
+            (*prev).next = (*new).next;
+        } else {
+            (*prev).next = new;
+        }
+    }
+
+    /// Allocate a block of size class `class`, and refill the class
+    /// with a new page if it is empty.
+    unsafe fn alloc_small(&mut self, class: usize) -> *mut u8 {
+        if self.classes[class].is_null() {
+            let page = self.alloc_pages(1);
+            if page.is_null() {
+                return null_mut();
+            }
+            let size = 1 << (class + MIN_SMALL_ORDER);
+            for off in (0..PAGE_SIZE).step_by(size).rev() {
+                self.free_small(page.add(off), class);
+            }
+        }
+        let block = self.classes[class];
+        self.classes[class] = (*block).next;
+        block as *mut u8
+    }
+
+    unsafe fn free_small(&mut self, ptr: *mut u8, class: usize) {
+        let block = ptr as *mut FreeBlock;
+        (*block).next = self.classes[class];
+        self.classes[class] = block;
+    }
+
+    /// Allocate a block for `layout`. A small object aligned to more than
+    /// `MAX_SMALL` bytes takes whole pages, and is freed as pages by `dealloc`.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match size_class(&layout) {
+            Some(class) => self.alloc_small(class),
+            None if layout.align() <= PAGE_SIZE => self.alloc_pages(page_count(&layout)),
+            None => self.alloc_aligned_pages(page_count(&layout), layout.align())
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match size_class(&layout) {
+            Some(class) => self.free_small(ptr, class),
+            None => self.free_pages(ptr, page_count(&layout))
+        }
+    }
+}
+
+/// Global allocator of user programs
+struct UserAllocator {
+    heap: UnsafeCell<Heap>,
+}
+
+// user processes are single-threaded
+unsafe impl Sync for UserAllocator {}
+
+unsafe impl GlobalAlloc for UserAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        (*self.heap.get()).alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        (*self.heap.get()).dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // reuse the block if it is still in the same size class or of the same pages
+        let fits = match (size_class(&layout), size_class(&new_layout)) {
+            (Some(old), Some(new)) => old == new,
+            (None, None) => page_count(&layout) == page_count(&new_layout),
+            _ => false
+        };
+        if fits {
+            return ptr;
+        }
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GA: UserAllocator = UserAllocator { heap: UnsafeCell::new(Heap::new()) };
+
+#[alloc_error_handler]
+fn alloc_error(l: Layout) -> ! {
+    crate::println!(
+        "Allocator failed to allocate {} bytes with {}-byte alignment.",
+        l.size(),
+        l.align()
+    );
+    exit(-1);
+}
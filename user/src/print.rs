@@ -5,25 +5,39 @@
 
 use core::fmt::{Write, Error, self};
 use crate::syscall;
+use crate::constant::{STDOUT, STDERR};
+use alloc::string::String;
 
-struct StdIO {}
+struct StdIO {
+    fd: i32,
+}
 
 impl StdIO {
-    pub fn new() -> Self {
-        StdIO {}
+    pub fn new(fd: i32) -> Self {
+        StdIO { fd }
     }
 }
 
 impl Write for StdIO {
     fn write_str(&mut self, out: &str) -> Result<(), Error> {
-        syscall::write(1, out.as_bytes());
+        let _ = syscall::write(self.fd, out.as_bytes());
         Ok(())
     }
 }
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    StdIO::new().write_fmt(args).unwrap();
+    StdIO::new(STDOUT).write_fmt(args).unwrap();
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    StdIO::new(STDERR).write_fmt(args).unwrap();
+}
+
+#[doc(hidden)]
+pub fn _format(args: fmt::Arguments) -> String {
+    alloc::fmt::format(args)
 }
 
 #[macro_export]
@@ -39,7 +53,20 @@ macro_rules! println {
     })
 }
 
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::print::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ({
+        $crate::print::_eprint(format_args_nl!($($arg)*));
+    })
+}
+
 #[macro_export]
 macro_rules! format {
-    ($($arg:tt)*) => (core::fmt::format(format_args_nl!($($arg)*)))
+    ($($arg:tt)*) => ($crate::print::_format(format_args!($($arg)*)))
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Signals
+//!
+//! A signal sent by `kill` is delivered when the receiving process next
+//! returns from kernel. Unless its action is changed by `sigaction`, a
+//! signal terminates the process, stops it, or is ignored. Signals may be
+//! blocked by `sigprocmask`, which delays their delivery.
+//!
+//! A process terminated by signal exits with `128 + signal`. Signal numbers
+//! are the same as `Signal` in kernel, which follow Linux.
+//!
+//! # Examples
+//!
+//! ```
+//! use user::println;
+//! use user::signal::{signal, Signal, Action};
+//! use user::syscall::{kill, wait};
+//!
+//! fn on_interrupt(sig: Signal) {
+//!     println!("got {}", sig.name());
+//! }
+//!
+//! signal(Signal::SIGINT, Action::Handle(on_interrupt)).unwrap();
+//!
+//! let mut status = 0;
+//! let pid = wait(-1, &mut status).unwrap();
+//! if let Some(sig) = Signal::from_status(status) {
+//!     println!("{}: {}", pid, sig);
+//! }
+//! ```
+
+use core::fmt;
+use crate::error::{Error, Result};
+use crate::syscall_internal::{__sigaction, __sigprocmask, __sigreturn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Signal {
+    /// Hangup
+    SIGHUP = 1,
+    /// Interrupt
+    SIGINT = 2,
+    /// Quit
+    SIGQUIT = 3,
+    /// Illegal instruction
+    SIGILL = 4,
+    /// Breakpoint
+    SIGTRAP = 5,
+    /// Abort
+    SIGABRT = 6,
+    /// Misaligned memory access
+    SIGBUS = 7,
+    /// Arithmetic error
+    SIGFPE = 8,
+    /// Kill, which can't be caught or blocked
+    SIGKILL = 9,
+    /// User-defined signal 1
+    SIGUSR1 = 10,
+    /// Invalid memory access
+    SIGSEGV = 11,
+    /// User-defined signal 2
+    SIGUSR2 = 12,
+    /// Write to pipe with no readers
+    SIGPIPE = 13,
+    /// Alarm clock
+    SIGALRM = 14,
+    /// Termination
+    SIGTERM = 15,
+    /// Child stopped or exited
+    SIGCHLD = 17,
+    /// Continue if stopped
+    SIGCONT = 18,
+    /// Stop, which can't be caught or blocked
+    SIGSTOP = 19,
+    /// Stop from terminal
+    SIGTSTP = 20,
+}
+
+/// Number of signal numbers, including the unused 0
+const NSIG: usize = 32;
+
+/// All signals
+pub const SIGNALS: [Signal; 19] = [
+    Signal::SIGHUP, Signal::SIGINT, Signal::SIGQUIT, Signal::SIGILL, Signal::SIGTRAP,
+    Signal::SIGABRT, Signal::SIGBUS, Signal::SIGFPE, Signal::SIGKILL, Signal::SIGUSR1,
+    Signal::SIGSEGV, Signal::SIGUSR2, Signal::SIGPIPE, Signal::SIGALRM, Signal::SIGTERM,
+    Signal::SIGCHLD, Signal::SIGCONT, Signal::SIGSTOP, Signal::SIGTSTP,
+];
+
+impl Signal {
+    /// Get signal numbered `signum`
+    pub fn from_signum(signum: i32) -> Option<Self> {
+        SIGNALS.iter().copied().find(|sig| *sig as i32 == signum)
+    }
+
+    /// Get signal by name, with or without `SIG` prefix, e.g. `SIGINT` or `INT`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = if name.starts_with("SIG") { &name[3..] } else { name };
+        SIGNALS.iter().copied().find(|sig| &sig.name()[3..] == name)
+    }
+
+    /// Get signal which terminated a process from its exit status,
+    /// or `None` if the process exited normally.
+    pub fn from_status(status: i32) -> Option<Self> {
+        if status > 128 {
+            Signal::from_signum(status - 128)
+        } else {
+            None
+        }
+    }
+
+    /// Exit status of a process terminated by this signal
+    pub fn exit_status(self) -> i32 {
+        128 + self as i32
+    }
+
+    /// Name of signal, e.g. `SIGINT`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signal::SIGHUP => "SIGHUP",
+            Signal::SIGINT => "SIGINT",
+            Signal::SIGQUIT => "SIGQUIT",
+            Signal::SIGILL => "SIGILL",
+            Signal::SIGTRAP => "SIGTRAP",
+            Signal::SIGABRT => "SIGABRT",
+            Signal::SIGBUS => "SIGBUS",
+            Signal::SIGFPE => "SIGFPE",
+            Signal::SIGKILL => "SIGKILL",
+            Signal::SIGUSR1 => "SIGUSR1",
+            Signal::SIGSEGV => "SIGSEGV",
+            Signal::SIGUSR2 => "SIGUSR2",
+            Signal::SIGPIPE => "SIGPIPE",
+            Signal::SIGALRM => "SIGALRM",
+            Signal::SIGTERM => "SIGTERM",
+            Signal::SIGCHLD => "SIGCHLD",
+            Signal::SIGCONT => "SIGCONT",
+            Signal::SIGSTOP => "SIGSTOP",
+            Signal::SIGTSTP => "SIGTSTP",
+        }
+    }
+
+    /// Human-readable description of signal
+    pub fn description(&self) -> &'static str {
+        match self {
+            Signal::SIGHUP => "hangup",
+            Signal::SIGINT => "interrupt",
+            Signal::SIGQUIT => "quit",
+            Signal::SIGILL => "illegal instruction",
+            Signal::SIGTRAP => "trace/breakpoint trap",
+            Signal::SIGABRT => "aborted",
+            Signal::SIGBUS => "bus error",
+            Signal::SIGFPE => "floating point exception",
+            Signal::SIGKILL => "killed",
+            Signal::SIGUSR1 => "user defined signal 1",
+            Signal::SIGSEGV => "segmentation fault",
+            Signal::SIGUSR2 => "user defined signal 2",
+            Signal::SIGPIPE => "broken pipe",
+            Signal::SIGALRM => "alarm clock",
+            Signal::SIGTERM => "terminated",
+            Signal::SIGCHLD => "child exited",
+            Signal::SIGCONT => "continued",
+            Signal::SIGSTOP => "stopped (signal)",
+            Signal::SIGTSTP => "stopped",
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// A set of signals
+///
+/// # Examples
+///
+/// ```
+/// use user::signal::{SigSet, Signal};
+/// let set = SigSet::empty().with(Signal::SIGINT).with(Signal::SIGTERM);
+/// assert!(set.contains(Signal::SIGINT));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SigSet(u32);
+
+impl SigSet {
+    pub const fn empty() -> Self {
+        SigSet(0)
+    }
+
+    /// Set with `sig` added
+    pub fn with(self, sig: Signal) -> Self {
+        SigSet(self.0 | 1 << sig as u32)
+    }
+
+    pub fn insert(&mut self, sig: Signal) {
+        self.0 |= 1 << sig as u32;
+    }
+
+    pub fn remove(&mut self, sig: Signal) {
+        self.0 &= !(1 << sig as u32);
+    }
+
+    pub fn contains(&self, sig: Signal) -> bool {
+        self.0 & 1 << sig as u32 != 0
+    }
+}
+
+/// Action taken when a signal is delivered
+#[derive(Clone, Copy)]
+pub enum Action {
+    /// Default action of signal, which terminates or stops the process,
+    /// or ignores the signal
+    Default,
+    /// Ignore signal
+    Ignore,
+    /// Run handler. The signal is blocked while handler is running.
+    Handle(fn(Signal)),
+}
+
+/// Action of a signal, the same as `SigAction` in kernel
+#[repr(C)]
+pub(crate) struct SigAction {
+    handler: usize,
+    restorer: usize,
+    mask: u32,
+}
+
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+
+/// Handlers registered by `sigaction`, indexed by signal number
+static mut HANDLERS: [Option<fn(Signal)>; NSIG] = [None; NSIG];
+
+/// Entry of all handlers. Kernel runs it with signal number when a signal is
+/// delivered, and it returns to `__sigreturn`.
+extern "C" fn handler_entry(signum: i32) {
+    let sig = Signal::from_signum(signum);
+    let handler = unsafe { HANDLERS.get(signum as usize).copied().flatten() };
+    if let (Some(sig), Some(handler)) = (sig, handler) {
+        handler(sig);
+    }
+}
+
+/// Set action of `sig`. Signals in `mask` are blocked in addition to `sig`
+/// while its handler is running. Returns the previous action.
+///
+/// Returns `EINVAL` if `sig` is `SIGKILL` or `SIGSTOP`, whose action can't be changed.
+pub fn sigaction(sig: Signal, action: Action, mask: SigSet) -> Result<Action> {
+    let prev_handler = unsafe { HANDLERS[sig as usize] };
+    let handler = match action {
+        Action::Default => SIG_DFL,
+        Action::Ignore => SIG_IGN,
+        Action::Handle(f) => {
+            unsafe { HANDLERS[sig as usize] = Some(f); }
+            handler_entry as usize
+        }
+    };
+    let act = SigAction { handler, restorer: __sigreturn as usize, mask: mask.0 };
+    let mut old = SigAction { handler: SIG_DFL, restorer: 0, mask: 0 };
+    if let Err(err) = Error::check(unsafe { __sigaction(sig as i32, &act, &mut old) }) {
+        unsafe { HANDLERS[sig as usize] = prev_handler; }
+        return Err(err);
+    }
+    Ok(match (old.handler, prev_handler) {
+        (SIG_DFL, _) => Action::Default,
+        (SIG_IGN, _) => Action::Ignore,
+        (_, Some(f)) => Action::Handle(f),
+        (_, None) => Action::Default
+    })
+}
+
+/// Set action of `sig` without blocking other signals in handler. Returns the previous action.
+pub fn signal(sig: Signal, action: Action) -> Result<Action> {
+    sigaction(sig, action, SigSet::empty())
+}
+
+/// How `sigprocmask` changes blocked signals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum How {
+    /// Block signals in set
+    Block = 0,
+    /// Unblock signals in set
+    Unblock = 1,
+    /// Block exactly signals in set
+    SetMask = 2,
+}
+
+/// Change blocked signals of current process, and returns the previously blocked ones.
+/// `SIGKILL` and `SIGSTOP` are never blocked.
+///
+/// # Examples
+///
+/// ```
+/// use user::signal::{sigprocmask, How, SigSet, Signal};
+/// let old = sigprocmask(How::Block, SigSet::empty().with(Signal::SIGINT)).unwrap();
+/// // SIGINT is delivered after it is unblocked
+/// sigprocmask(How::SetMask, old).unwrap();
+/// ```
+pub fn sigprocmask(how: How, set: SigSet) -> Result<SigSet> {
+    Error::check(unsafe { __sigprocmask(how as i32, set.0) }).map(|old| SigSet(old as u32))
+}
@@ -22,3 +22,9 @@
 #define SYS_sbrk 18
 #define SYS_sleep 19
 #define SYS_uptime 20
+#define SYS_procinfo 21
+#define SYS_sigaction 22
+#define SYS_sigprocmask 23
+#define SYS_sigreturn 24
+#define SYS_mount 25
+#define SYS_umount 26
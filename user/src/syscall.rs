@@ -11,9 +11,14 @@
 //! representations before calling functions in `syscall_internal` and
 //! trapping into kernel.
 //!
+//! Syscalls return `Result`, whose error is the error code returned
+//! by kernel. See `error` module.
+//!
 //! Usage of syscalls is listed in their corresponding sub-page.
 
 use crate::syscall_internal::*;
+use crate::error::{Error, Result};
+use crate::signal::Signal;
 use core::ptr::null;
 
 /// Exit current process with exit code `code`.
@@ -32,35 +37,44 @@ pub fn exit(code: i32) -> ! {
 /// 
 /// Child process will get return value of 0.
 /// Parent process (the one calling `fork`) will
-/// get pid of child process.
+/// get pid of child process, or `EAGAIN` if there
+/// are too many processes.
 /// 
 /// # Examples
 ///
 /// ```
 /// use user::syscall::fork;
-/// if fork() == 0 {
+/// if fork() == Ok(0) {
 ///     println!("subprocess!");
 /// } else {
 ///     println!("parent process");
 /// }
 /// ```
-pub fn fork() -> i32 {
-    unsafe { __fork() }
+pub fn fork() -> Result<i32> {
+    Error::check(unsafe { __fork() })
 }
 
+/// Maximum number of arguments passed to `exec`
 pub const EXEC_MAX_ARGS: usize = 10;
 
 /// Replace current process image with the new one
-/// in the filesystem.
+/// in the filesystem, and pass `args` to its `main`.
+/// By convention, the first argument is name of the program.
 ///
-/// This function will not return.
+/// This function returns only if it fails, and current
+/// process image is kept. Returns `E2BIG` if there are more than
+/// `EXEC_MAX_ARGS` arguments.
 ///
 /// # Examples
 /// ```
 /// use user::syscall::exec;
-/// exec("/init", &[]);
+/// let err = exec("/test3", &["test3", "hello"]);
+/// println!("exec failed: {}", err);
 /// ```
-pub fn exec(path: &str, args: &[&str]) -> ! {
+pub fn exec(path: &str, args: &[&str]) -> Error {
+    if args.len() > EXEC_MAX_ARGS {
+        return Error::E2BIG;
+    }
     let arg_cnt = args.len();
     let mut args_sz = [0; EXEC_MAX_ARGS];
     let mut args_ptr = [null(); EXEC_MAX_ARGS];
@@ -68,7 +82,7 @@ pub fn exec(path: &str, args: &[&str]) -> ! {
         args_sz[i] = args[i].len() as i32;
         args_ptr[i] = args[i].as_bytes().as_ptr() as *const u8;
     }
-    unsafe {
+    let ret = unsafe {
         __exec(
             path.as_bytes().as_ptr() as *const u8,
             path.len() as i32,
@@ -76,52 +90,57 @@ pub fn exec(path: &str, args: &[&str]) -> ! {
             args_ptr.as_ptr(),
             args_sz.as_ptr()
         )
-    }
+    };
+    Error::from_errno(-ret)
 }
 
 /// Write `content` to file descriptor `fd`.
 ///
-/// Returns number of characters written. A negative return value means error while writing.
+/// Returns number of characters written, which may be less than `content.len()`
+/// if disk becomes full. Returns `ENOSPC` if nothing can be written as disk is full.
 ///
 /// # Examples
 /// ```
 /// use user::syscall::write;
 /// use user::constant::STDOUT;
-/// write(STDOUT, "Hello, World!");
+/// write(STDOUT, b"Hello, World!").unwrap();
 /// ```
-pub fn write(fd: i32, content: &[u8]) -> i32 {
-    unsafe {
+pub fn write(fd: i32, content: &[u8]) -> Result<usize> {
+    Error::check(unsafe {
         __write(fd,
                 content.as_ptr(),
                 content.len() as i32)
-    }
+    }).map(|sz| sz as usize)
 }
 
 /// Read `content` from file descriptor `fd`.
 ///
 /// You may read a maximum of `content.len()` characters from `fd`.
 /// Returns number of characters read.
-pub fn read(fd: i32, content: &mut [u8]) -> i32 {
-    unsafe {
+pub fn read(fd: i32, content: &mut [u8]) -> Result<usize> {
+    Error::check(unsafe {
         __read(fd,
                 content.as_mut_ptr(),
                 content.len() as i32)
-    }
+    }).map(|sz| sz as usize)
 }
 
-/// Open file of `path` with `mode`.
+/// Open file of `path` with `mode`, which is a combination of `O_*` flags
+/// in `user::constant`.
 ///
-/// This function returns file descriptor. Negative value means error.
+/// This function returns file descriptor. When creating a file, returns `ENOSPC`
+/// if there's no space on disk, or `ENFILE` if too many files are in use.
 ///
 /// # Examples
 /// ```
 /// use user::syscall::open;
-/// let fd = open("/console", 0);
+/// use user::constant::{O_CREATE, O_WRONLY};
+/// let fd = open("/log.txt", O_CREATE | O_WRONLY).unwrap();
 /// ```
-pub fn open(path: &str, mode: i32) -> i32 {
-    unsafe {
+pub fn open(path: &str, mode: i32) -> Result<i32> {
+    Error::check(unsafe {
         __open(path.as_ptr(), path.len() as i32, mode)
-    }
+    })
 }
 
 /// Close a file with file descriptor `fd`.
@@ -129,10 +148,10 @@ pub fn open(path: &str, mode: i32) -> i32 {
 /// # Examples
 /// ```
 /// use user::syscall::close;
-/// close(0);
+/// close(0).unwrap();
 /// ```
-pub fn close(fd: i32) -> i32 {
-    unsafe { __close(fd) }
+pub fn close(fd: i32) -> Result<()> {
+    Error::check(unsafe { __close(fd) }).map(|_| ())
 }
 
 /// Duplicate file descriptor `fd`.
@@ -143,12 +162,271 @@ pub fn close(fd: i32) -> i32 {
 /// ```
 /// use user::syscall::dup;
 /// use user::constant::STDIN;
-/// let fd = dup(STDIN);
+/// let fd = dup(STDIN).unwrap();
+/// ```
+pub fn dup(fd: i32) -> Result<i32> {
+    Error::check(unsafe { __dup(fd) })
+}
+
+/// Wait for child process `pid` to exit. If `pid` is -1, wait for any child.
+///
+/// Exit code of the child is stored in `status`. If the child is terminated by
+/// a signal, it is `128 + signal`, see [`Signal::from_status`](../signal/enum.Signal.html#method.from_status).
+/// Returns pid of the child, or `ECHILD` if there is no such child.
+///
+/// # Examples
+/// ```
+/// use user::syscall::{fork, exit, wait};
+/// let pid = fork().unwrap();
+/// if pid == 0 {
+///     exit(42);
+/// }
+/// let mut status = 0;
+/// assert_eq!(wait(pid, &mut status), Ok(pid));
+/// assert_eq!(status, 42);
+/// ```
+pub fn wait(pid: i32, status: &mut i32) -> Result<i32> {
+    Error::check(unsafe { __wait(pid, status as *mut i32) })
+}
+
+/// Create a pipe, and store file descriptors of its read end and
+/// write end in `fds[0]` and `fds[1]`.
+///
+/// Reading from a pipe whose write ends are all closed returns 0.
+/// Writing to a pipe whose read ends are all closed returns `EPIPE`.
+///
+/// Returns `EMFILE` if there are no free file descriptors.
+///
+/// # Examples
+/// ```
+/// use user::syscall::{pipe, read, write};
+/// let mut fds = [0; 2];
+/// pipe(&mut fds).unwrap();
+/// write(fds[1], b"hello").unwrap();
+/// let mut buf = [0; 5];
+/// read(fds[0], &mut buf).unwrap();
+/// ```
+pub fn pipe(fds: &mut [i32; 2]) -> Result<()> {
+    Error::check(unsafe { __pipe(fds.as_mut_ptr()) }).map(|_| ())
+}
+
+/// Create a device file of `major` and `minor` device number at `path`.
+///
+/// Returns `EEXIST` if `path` already exists.
+///
+/// # Examples
+/// ```
+/// use user::syscall::mknod;
+/// use user::constant::CONSOLE;
+/// mknod("/console", CONSOLE, 0).unwrap();
+/// ```
+pub fn mknod(path: &str, major: i32, minor: i32) -> Result<()> {
+    Error::check(unsafe { __mknod(path.as_ptr(), path.len() as i32, major, minor) }).map(|_| ())
+}
+
+/// Remove directory entry `path`. The file is deleted after all
+/// links to it are removed and all file descriptors referring to it are closed.
+///
+/// Returns `ENOENT` if `path` doesn't exist, or `ENOTEMPTY` if it is a non-empty directory.
+///
+/// # Examples
+/// ```
+/// use user::syscall::unlink;
+/// unlink("/test.txt").unwrap();
+/// ```
+pub fn unlink(path: &str) -> Result<()> {
+    Error::check(unsafe { __unlink(path.as_ptr(), path.len() as i32) }).map(|_| ())
+}
+
+/// Create a new directory entry `new` for the file at `old`.
+///
+/// Returns `ENOENT` if `old` doesn't exist, `EPERM` if it is a directory,
+/// `EEXIST` if `new` already exists, or `ENOSPC` if there's no space on disk.
+///
+/// # Examples
+/// ```
+/// use user::syscall::link;
+/// link("/test.txt", "/test2.txt").unwrap();
+/// ```
+pub fn link(old: &str, new: &str) -> Result<()> {
+    Error::check(unsafe { __link(old.as_ptr(), old.len() as i32, new.as_ptr(), new.len() as i32) }).map(|_| ())
+}
+
+/// Create a directory at `path`.
+///
+/// Returns `EEXIST` if `path` already exists, or `ENOSPC` if there's no space on disk.
+///
+/// # Examples
+/// ```
+/// use user::syscall::mkdir;
+/// mkdir("/usr").unwrap();
+/// ```
+pub fn mkdir(path: &str) -> Result<()> {
+    Error::check(unsafe { __mkdir(path.as_ptr(), path.len() as i32) }).map(|_| ())
+}
+
+/// Change current working directory to `path`.
+///
+/// Returns `ENOENT` if `path` doesn't exist, or `ENOTDIR` if it is not a directory.
+///
+/// # Examples
+/// ```
+/// use user::syscall::chdir;
+/// chdir("/usr").unwrap();
+/// ```
+pub fn chdir(path: &str) -> Result<()> {
+    Error::check(unsafe { __chdir(path.as_ptr(), path.len() as i32) }).map(|_| ())
+}
+
+/// Mount file system on disk `dev` at directory `path`. Disks are numbered from 1
+/// in the order they are attached to virtio bus, and disk 1 holds root file system.
+///
+/// Returns `ENXIO` if there's no disk `dev`, `ENOTDIR` if `path` is not a directory,
+/// `EBUSY` if the disk is already mounted or `path` is a mount point, or `EINVAL`
+/// if there's no file system on the disk.
+///
+/// # Examples
+/// ```
+/// use user::syscall::{mkdir, mount};
+/// mkdir("/mnt").unwrap();
+/// mount(2, "/mnt").unwrap();
+/// ```
+pub fn mount(dev: u32, path: &str) -> Result<()> {
+    Error::check(unsafe { __mount(dev as i32, path.as_ptr(), path.len() as i32) }).map(|_| ())
+}
+
+/// Unmount the file system mounted at `path`.
+///
+/// Returns `EINVAL` if `path` is not a mount point, or `EBUSY` if files on the
+/// file system are still open or used as working directory.
+///
+/// # Examples
+/// ```
+/// use user::syscall::umount;
+/// umount("/mnt").unwrap();
+/// ```
+pub fn umount(path: &str) -> Result<()> {
+    Error::check(unsafe { __umount(path.as_ptr(), path.len() as i32) }).map(|_| ())
+}
+
+/// Grow heap of current process by `increment` bytes, or shrink it
+/// if `increment` is negative. Returns the old program break, which
+/// is the start of newly allocated memory.
+///
+/// Returns `ENOMEM` if heap would run into user stack.
+///
+/// # Examples
+/// ```
+/// use user::syscall::sbrk;
+/// let mem = sbrk(4096).unwrap() as *mut u8;
+/// unsafe { *mem = 1; }
+/// sbrk(-4096).unwrap();
+/// ```
+pub fn sbrk(increment: isize) -> Result<usize> {
+    let ret = unsafe { __sbrk(increment) };
+    if ret < 0 {
+        Err(Error::from_errno(-ret as i32))
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// File status returned by `fstat`
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stat {
+    /// Device number of file system
+    pub dev: u32,
+    /// Inode number
+    pub ino: u32,
+    /// File type, which is one of `T_DIR`, `T_FILE` and `T_DEVICE`
+    /// in `user::constant`, or 0 for pipes
+    pub typ: u16,
+    /// Number of links to file
+    pub nlink: u16,
+    /// Size of file (bytes)
+    pub size: u32,
+}
+
+/// Get status of file descriptor `fd`.
+///
+/// # Examples
+/// ```
+/// use user::syscall::{open, fstat};
+/// use user::constant::{O_RDONLY, T_DIR};
+/// let fd = open("/", O_RDONLY).unwrap();
+/// assert_eq!(fstat(fd).unwrap().typ, T_DIR);
+/// ```
+pub fn fstat(fd: i32) -> Result<Stat> {
+    let mut st = Stat::default();
+    Error::check(unsafe { __fstat(fd, &mut st) }).map(|_| st)
+}
+
+/// Send signal `sig` to process `pid`, see `signal` module. Blocking `read`, `write`
+/// and `wait` of the process are interrupted with `EINTR` if the signal is not ignored.
+///
+/// Returns `EPERM` if `pid` is init (pid 0), or `ESRCH` if there's no such process.
+///
+/// # Examples
+/// ```
+/// use user::syscall::kill;
+/// use user::signal::Signal;
+/// kill(3, Signal::SIGTERM).unwrap();
 /// ```
-pub fn dup(fd: i32) -> i32 {
-    unsafe { __dup(fd) }
+pub fn kill(pid: i32, sig: Signal) -> Result<()> {
+    Error::check(unsafe { __kill(pid, sig as i32) }).map(|_| ())
 }
 
-pub fn wait(pid: i32) -> i32 {
-    unsafe { __wait(pid) }
+/// Maximum length of process name
+pub const PROC_NAME_LEN: usize = 16;
+
+/// Information of a process returned by `procinfo`
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ProcInfo {
+    pub pid: i32,
+    /// pid of parent, or -1 if there's none
+    pub ppid: i32,
+    /// state of process, see `ProcInfo::state_name`
+    pub state: u32,
+    /// name of program, padded with 0
+    pub name: [u8; PROC_NAME_LEN],
+}
+
+impl ProcInfo {
+    /// Name of program
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|c| *c == 0).unwrap_or(PROC_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// Name of process state, in the same order as `ProcessState` in kernel
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            0 => "unused",
+            1 => "sleeping",
+            2 => "runnable",
+            3 => "running",
+            4 => "zombie",
+            _ => "unknown"
+        }
+    }
+}
+
+/// Get information of all processes into `infos`.
+///
+/// Returns number of processes, which is at most `infos.len()`.
+///
+/// # Examples
+/// ```
+/// use user::syscall::{procinfo, ProcInfo};
+/// let mut infos = [ProcInfo::default(); 64];
+/// for info in &infos[..procinfo(&mut infos).unwrap()] {
+///     println!("{} {}", info.pid, info.name());
+/// }
+/// ```
+pub fn procinfo(infos: &mut [ProcInfo]) -> Result<usize> {
+    Error::check(unsafe {
+        __procinfo(infos.as_mut_ptr(), infos.len() as i32)
+    }).map(|n| n as usize)
 }
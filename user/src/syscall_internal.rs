@@ -10,6 +10,9 @@
 //! transmuted into pointers in `syscall` module, and then
 //! this module will finally trap into kernel.
 
+use crate::syscall::{Stat, ProcInfo};
+use crate::signal::SigAction;
+
 global_asm!(include_str!("usys.S"));
 
 extern "C" {
@@ -17,9 +20,24 @@ extern "C" {
     pub fn __read(fd: i32, content: *mut u8, sz: i32) -> i32;
     pub fn __exit(code: i32) -> !;
     pub fn __fork() -> i32;
-    pub fn __exec(path: *const u8, path_sz: i32, arg_cnt: i32, args: *const *const u8, args_sz: *const i32) -> !;
+    pub fn __exec(path: *const u8, path_sz: i32, arg_cnt: i32, args: *const *const u8, args_sz: *const i32) -> i32;
     pub fn __open(path: *const u8, sz: i32, mode: i32) -> i32;
     pub fn __close(fd: i32) -> i32;
     pub fn __dup(fd: i32) -> i32;
-    pub fn __wait(pid: i32) -> i32;
+    pub fn __wait(pid: i32, status: *mut i32) -> i32;
+    pub fn __pipe(fds: *mut i32) -> i32;
+    pub fn __mknod(path: *const u8, sz: i32, major: i32, minor: i32) -> i32;
+    pub fn __unlink(path: *const u8, sz: i32) -> i32;
+    pub fn __link(old: *const u8, old_sz: i32, new: *const u8, new_sz: i32) -> i32;
+    pub fn __mkdir(path: *const u8, sz: i32) -> i32;
+    pub fn __chdir(path: *const u8, sz: i32) -> i32;
+    pub fn __sbrk(increment: isize) -> isize;
+    pub fn __fstat(fd: i32, st: *mut Stat) -> i32;
+    pub fn __kill(pid: i32, sig: i32) -> i32;
+    pub fn __procinfo(infos: *mut ProcInfo, n: i32) -> i32;
+    pub fn __sigaction(sig: i32, act: *const SigAction, old: *mut SigAction) -> i32;
+    pub fn __sigprocmask(how: i32, set: u32) -> i32;
+    pub fn __sigreturn() -> !;
+    pub fn __mount(dev: i32, path: *const u8, sz: i32) -> i32;
+    pub fn __umount(path: *const u8, sz: i32) -> i32;
 }
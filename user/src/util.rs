@@ -0,0 +1,116 @@
+// Copyright (c) 2020 Alex Chi
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Helpers shared by user programs
+//!
+//! # Examples
+//!
+//! ```
+//! use user::util::{Args, report};
+//! use user::syscall::unlink;
+//! let args = Args::parse(args, "rm", "f", "[-f] file...");
+//! for path in &args.operands {
+//!     if let Err(err) = unlink(path) {
+//!         if !args.flag('f') {
+//!             report(args.prog, path, err);
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::syscall::{exit, read};
+use crate::error::{Error, Result};
+use crate::constant::{Dirent, DIRENT_SIZE};
+use crate::eprintln;
+use alloc::vec::Vec;
+
+/// Command-line arguments split into flags and operands
+pub struct Args<'a> {
+    /// Name of program
+    pub prog: &'a str,
+    /// Flags, e.g. `-l -a` or `-la` are both `['l', 'a']`
+    pub flags: Vec<char>,
+    /// Arguments other than program name and flags
+    pub operands: Vec<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    /// Parse `args` passed to `main`. `prog` is used as program name if `args` is empty.
+    ///
+    /// Arguments starting with `-` before the first operand are flags, and each of their
+    /// characters should be in `allowed`. `--` ends flags, and a single `-` is an operand.
+    /// Prints `usage` and exits if there's an unknown flag.
+    pub fn parse(args: &[&'a str], prog: &'a str, allowed: &str, usage: &str) -> Self {
+        let mut parsed = Args {
+            prog: args.first().copied().unwrap_or(prog),
+            flags: Vec::new(),
+            operands: Vec::new(),
+        };
+        let mut rest = args.iter().skip(1);
+        while let Some(arg) = rest.next() {
+            if *arg == "--" {
+                break;
+            }
+            if !arg.starts_with('-') || *arg == "-" {
+                parsed.operands.push(*arg);
+                break;
+            }
+            for c in arg[1..].chars() {
+                if !allowed.contains(c) {
+                    eprintln!("{}: unknown option -{}", parsed.prog, c);
+                    die_usage(parsed.prog, usage);
+                }
+                parsed.flags.push(c);
+            }
+        }
+        parsed.operands.extend(rest);
+        parsed
+    }
+
+    /// Whether flag `c` is set
+    pub fn flag(&self, c: char) -> bool {
+        self.flags.contains(&c)
+    }
+}
+
+/// Print `prog: what: err` to stderr
+pub fn report(prog: &str, what: &str, err: Error) {
+    eprintln!("{}: {}: {}", prog, what, err);
+}
+
+/// Print usage of program to stderr and exit with 1
+pub fn die_usage(prog: &str, usage: &str) -> ! {
+    eprintln!("usage: {} {}", prog, usage);
+    exit(1);
+}
+
+/// Read from `fd` until end of file
+pub fn read_to_end(fd: i32) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    let mut buf = [0; 512];
+    loop {
+        let n = read(fd, &mut buf)?;
+        if n == 0 {
+            return Ok(content);
+        }
+        content.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Read next entry from directory `fd`, skipping free entries.
+///
+/// Returns `None` at the end of directory.
+pub fn read_dirent(fd: i32) -> Result<Option<Dirent>> {
+    let mut buf = [0; DIRENT_SIZE];
+    loop {
+        if read(fd, &mut buf)? != DIRENT_SIZE {
+            return Ok(None);
+        }
+        let de: Dirent = unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const Dirent) };
+        if de.inum != 0 {
+            return Ok(Some(de));
+        }
+    }
+}
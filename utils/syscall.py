@@ -19,5 +19,11 @@ syscalls = [
     "getpid",
     "sbrk",
     "sleep",
-    "uptime"
+    "uptime",
+    "procinfo",
+    "sigaction",
+    "sigprocmask",
+    "sigreturn",
+    "mount",
+    "umount"
 ]